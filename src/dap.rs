@@ -0,0 +1,626 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{stdin, BufRead, Write};
+
+use nix::sys::signal::Signal;
+use nix::sys::wait::WaitStatus;
+use nix::unistd::Pid;
+use serde_json::{json, Value};
+
+use crate::debugfile::DEFAULT_DEBUG_FILE_DIRECTORY;
+use crate::disassembler::{Instruction, MAX_INSTRUCTION_LENGTH};
+use crate::gimliwrapper::TypeLookup;
+use crate::target::{Frame, Target, VariableValue};
+use crate::util::{base64_encode, take_stdout};
+
+/// The debuggee is single threaded, so there is only ever one thread to report.
+const THREAD_ID: i64 = 1;
+
+enum Resume {
+    Continue,
+    Next,
+    StepIn,
+    StepOut,
+}
+
+/// Debug Adapter Protocol server speaking over stdin/stdout.
+/// See https://microsoft.github.io/debug-adapter-protocol/specification
+pub struct DapServer {
+    target: Option<Target>,
+    output: File,
+    seq: i64,
+    stop_on_entry: bool,
+    /// Breakpoint addresses per source path, setBreakpoints replaces all of them.
+    source_breakpoints: HashMap<String, Vec<usize>>,
+    /// Show values with the printers for Rust types, like `set pretty-printers` of the
+    /// console. The `prettyPrinters` argument of launch and attach turns them off.
+    pretty_printers: bool,
+}
+
+impl DapServer {
+    pub fn create() -> Result<DapServer, nix::Error> {
+        Ok(DapServer {
+            target: None,
+            output: take_stdout()?,
+            seq: 1,
+            stop_on_entry: false,
+            source_breakpoints: HashMap::new(),
+            pretty_printers: true,
+        })
+    }
+
+    pub fn run(&mut self) -> Result<(), ()> {
+        let stdin = stdin();
+        let mut reader = stdin.lock();
+
+        while let Some(message) = read_message(&mut reader) {
+            if message["type"] == "request" && !self.handle_request(&message) {
+                break;
+            }
+        }
+
+        if let Some(target) = &self.target {
+            target.kill().ok();
+        }
+        Ok(())
+    }
+
+    /// Handle a single request. Returns false once the session is over.
+    fn handle_request(&mut self, request: &Value) -> bool {
+        let args = &request["arguments"];
+        match request["command"].as_str().unwrap_or("") {
+            "initialize" => {
+                self.respond(
+                    request,
+                    json!({
+                        "supportsConfigurationDoneRequest": true,
+                        "supportsEvaluateForHovers": true,
+                        "supportsReadMemoryRequest": true,
+                        "supportsDisassembleRequest": true,
+                    }),
+                );
+            }
+            "launch" => {
+                let program = args["program"].as_str().unwrap_or("a.out");
+                let program_args: Vec<String> = args["args"]
+                    .as_array()
+                    .map(|a| a.iter().filter_map(|v| v.as_str()).map(String::from).collect())
+                    .unwrap_or_default();
+                self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+                self.pretty_printers = args["prettyPrinters"].as_bool().unwrap_or(true);
+                match Target::create(program, &program_args, &debug_file_directories(args)) {
+                    Ok(target) => {
+                        self.target = Some(target);
+                        self.respond(request, json!({}));
+                        self.event("initialized", json!({}));
+                    }
+                    Err(e) => self.respond_error(request, &format!("Could not launch: {}", e)),
+                }
+            }
+            "attach" => {
+                let pid = args["pid"].as_i64().or_else(|| args["processId"].as_i64());
+                self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+                self.pretty_printers = args["prettyPrinters"].as_bool().unwrap_or(true);
+                let directories = debug_file_directories(args);
+                match pid.map(|pid| Target::attach(Pid::from_raw(pid as i32), &directories)) {
+                    Some(Ok(target)) => {
+                        self.target = Some(target);
+                        self.respond(request, json!({}));
+                        self.event("initialized", json!({}));
+                    }
+                    Some(Err(e)) => self.respond_error(request, &format!("Could not attach: {}", e)),
+                    None => self.respond_error(request, "Missing pid."),
+                }
+            }
+            "setBreakpoints" => self.set_breakpoints(request),
+            "configurationDone" => {
+                self.respond(request, json!({}));
+                if self.stop_on_entry {
+                    self.stopped("entry", None, None);
+                } else {
+                    self.resume(Resume::Continue);
+                }
+            }
+            "threads" => {
+                self.respond(
+                    request,
+                    json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] }),
+                );
+            }
+            "continue" => {
+                self.respond(request, json!({ "allThreadsContinued": true }));
+                self.resume(Resume::Continue);
+            }
+            "next" => {
+                self.respond(request, json!({}));
+                self.resume(Resume::Next);
+            }
+            "stepIn" => {
+                self.respond(request, json!({}));
+                self.resume(Resume::StepIn);
+            }
+            "stepOut" => {
+                self.respond(request, json!({}));
+                self.resume(Resume::StepOut);
+            }
+            "stackTrace" => self.stack_trace(request),
+            "scopes" => {
+                let frame_id = args["frameId"].as_i64().unwrap_or(0);
+                self.respond(
+                    request,
+                    json!({ "scopes": [
+                        {
+                            "name": "Arguments",
+                            "presentationHint": "arguments",
+                            "variablesReference": frame_id * 2 + 1,
+                            "expensive": false,
+                        },
+                        {
+                            "name": "Locals",
+                            "presentationHint": "locals",
+                            "variablesReference": frame_id * 2 + 2,
+                            "expensive": false,
+                        },
+                    ]}),
+                );
+            }
+            "variables" => self.variables(request),
+            "evaluate" => self.evaluate(request),
+            "readMemory" => self.read_memory(request),
+            "disassemble" => self.disassemble(request),
+            "disconnect" => {
+                if let Some(mut target) = self.target.take() {
+                    if target.attached {
+                        target.detach().ok();
+                    } else {
+                        target.kill().ok();
+                    }
+                }
+                self.respond(request, json!({}));
+                return false;
+            }
+            command => {
+                self.respond_error(request, &format!("Unsupported request {}.", command));
+            }
+        }
+        true
+    }
+
+    fn set_breakpoints(&mut self, request: &Value) {
+        let args = &request["arguments"];
+        let path = String::from(args["source"]["path"].as_str().unwrap_or(""));
+        let target = match self.target.as_mut() {
+            Some(target) => target,
+            None => return self.respond_error(request, "No program is running."),
+        };
+
+        for addr in self.source_breakpoints.remove(&path).unwrap_or_default() {
+            target.delete_breakpoint(addr).ok();
+        }
+
+        let mut addresses = Vec::new();
+        let mut breakpoints = Vec::new();
+        for bp in args["breakpoints"].as_array().into_iter().flatten() {
+            let line = bp["line"].as_u64().unwrap_or(0);
+            match target.debug_info.dwarf_info.get_address_for_line(&path, line) {
+                Some((line, addr)) => {
+                    let addr = addr + target.base_address;
+                    if !addresses.contains(&addr) && target.set_breakpoint(addr).is_ok() {
+                        addresses.push(addr);
+                    }
+                    let id = target.breakpoints.get(&addr).map(|bp| bp.idx);
+                    breakpoints.push(json!({
+                        "id": id,
+                        "verified": id.is_some(),
+                        "line": line,
+                        "source": { "path": path },
+                    }));
+                }
+                None => breakpoints.push(json!({
+                    "verified": false,
+                    "line": line,
+                    "message": "No code at this line.",
+                })),
+            }
+        }
+        self.source_breakpoints.insert(path, addresses);
+        self.respond(request, json!({ "breakpoints": breakpoints }));
+    }
+
+    fn stack_trace(&mut self, request: &Value) {
+        let frames: Vec<Value> = match &self.target {
+            Some(target) => target
+                .backtrace()
+                .iter()
+                .map(|frame| {
+                    let file_name = frame.location.file.split('/').next_back().unwrap_or("");
+                    json!({
+                        "id": frame.level,
+                        "name": frame.location.function_name,
+                        "source": { "name": file_name, "path": frame.location.file },
+                        "line": frame.location.line,
+                        "column": 1,
+                        "instructionPointerReference": format!("{:#x}", frame.address),
+                    })
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        self.respond(
+            request,
+            json!({ "stackFrames": frames, "totalFrames": frames.len() }),
+        );
+    }
+
+    fn variables(&mut self, request: &Value) {
+        let reference = request["arguments"]["variablesReference"].as_u64().unwrap_or(0);
+        let variables: Vec<Value> = match (&self.target, reference) {
+            (Some(target), 1..) => {
+                let level = ((reference - 1) / 2) as usize;
+                match target.backtrace().into_iter().nth(level) {
+                    Some(frame) if reference % 2 == 1 => target.frame_arguments(&frame),
+                    Some(frame) => target.frame_locals(&frame),
+                    None => Vec::new(),
+                }
+                .iter()
+                .map(|var| variable_json(target, var, self.pretty_printers))
+                .collect()
+            }
+            _ => Vec::new(),
+        };
+        self.respond(request, json!({ "variables": variables }));
+    }
+
+    fn evaluate(&mut self, request: &Value) {
+        let args = &request["arguments"];
+        let expression = args["expression"].as_str().unwrap_or("");
+        let level = args["frameId"].as_u64().unwrap_or(0) as usize;
+
+        let result = match &self.target {
+            Some(target) => selected_frame(target, level).and_then(|frame| {
+                target
+                    .evaluate(expression, &frame)
+                    .map(|var| variable_json(target, &var, self.pretty_printers))
+            }),
+            None => Err(String::from("No program is running.")),
+        };
+        match result {
+            Ok(var) => self.respond(
+                request,
+                json!({
+                    "result": var["value"],
+                    "type": var["type"],
+                    "variablesReference": 0,
+                }),
+            ),
+            Err(message) => self.respond_error(request, &message),
+        }
+    }
+
+    fn read_memory(&mut self, request: &Value) {
+        let args = &request["arguments"];
+        let reference = args["memoryReference"].as_str().unwrap_or("");
+        let address = usize::from_str_radix(reference.trim_start_matches("0x"), 16);
+        let offset = args["offset"].as_i64().unwrap_or(0);
+        let count = args["count"].as_u64().unwrap_or(0) as usize;
+
+        let (target, address) = match (&self.target, address) {
+            (Some(target), Ok(address)) => (target, (address as i64 + offset) as usize),
+            (None, _) => return self.respond_error(request, "No program is running."),
+            (_, Err(_)) => return self.respond_error(request, "Invalid memory reference."),
+        };
+        let body = match target.read_bytes(address, count) {
+            Ok(bytes) => json!({
+                "address": format!("{:#x}", address),
+                "data": base64_encode(&bytes),
+            }),
+            Err(_) => json!({
+                "address": format!("{:#x}", address),
+                "unreadableBytes": count,
+            }),
+        };
+        self.respond(request, body);
+    }
+
+    fn disassemble(&mut self, request: &Value) {
+        let args = &request["arguments"];
+        let reference = args["memoryReference"].as_str().unwrap_or("");
+        let address = usize::from_str_radix(reference.trim_start_matches("0x"), 16);
+        let offset = args["offset"].as_i64().unwrap_or(0);
+        let instruction_offset = args["instructionOffset"].as_i64().unwrap_or(0);
+        let count = args["instructionCount"].as_i64().unwrap_or(0);
+        let resolve_symbols = args["resolveSymbols"].as_bool().unwrap_or(false);
+
+        let (target, address) = match (&self.target, address) {
+            (Some(target), Ok(address)) => (target, (address as i64 + offset) as usize),
+            (None, _) => return self.respond_error(request, "No program is running."),
+            (_, Err(_)) => return self.respond_error(request, "Invalid memory reference."),
+        };
+
+        // x86 can't be decoded backwards, so start at the beginning of the function if needed.
+        let before = (-instruction_offset).max(0) as usize * MAX_INSTRUCTION_LENGTH;
+        let start = match target.function_bounds(address) {
+            Some((low_pc, _)) if address - low_pc <= before => low_pc,
+            _ => address.saturating_sub(before),
+        };
+        let after = (instruction_offset + count).max(0) as usize * MAX_INSTRUCTION_LENGTH;
+        let instructions = target
+            .disassemble(start, address - start + after)
+            .unwrap_or_default();
+
+        let mut last_line = None;
+        let mut disassembled = Vec::new();
+        for slot in instruction_window(&instructions, start, address, instruction_offset, count) {
+            let instruction = match slot {
+                Ok(instruction) => instruction,
+                Err(address) => {
+                    disassembled.push(json!({
+                        "address": format!("{:#x}", address),
+                        "instruction": "??",
+                    }));
+                    continue;
+                }
+            };
+
+            let bytes: Vec<String> = instruction.bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            let mut text = instruction.text.clone();
+            if let Some(symbol) = instruction.branch_target.and_then(|a| target.symbolize(a)) {
+                text = format!("{} <{}>", text, symbol);
+            }
+            let mut entry = json!({
+                "address": format!("{:#x}", instruction.address),
+                "instructionBytes": bytes.join(" "),
+                "instruction": text,
+            });
+            if resolve_symbols {
+                if let Some(symbol) = target.symbolize(instruction.address) {
+                    entry["symbol"] = json!(symbol);
+                }
+            }
+            let location = target.location_at(instruction.address).filter(|l| l.has_source());
+            if let Some(location) = location {
+                let line = Some((location.file.clone(), location.line));
+                if line != last_line {
+                    let file_name = location.file.split('/').next_back().unwrap_or("");
+                    entry["location"] = json!({ "name": file_name, "path": location.file });
+                    entry["line"] = json!(location.line);
+                    last_line = line;
+                }
+            }
+            disassembled.push(entry);
+        }
+        self.respond(request, json!({ "instructions": disassembled }));
+    }
+
+    fn resume(&mut self, how: Resume) {
+        let target = match self.target.as_mut() {
+            Some(target) => target,
+            None => return,
+        };
+        let (result, reason) = match how {
+            Resume::Continue => (target.cont().and_then(|_| target.wait()), "pause"),
+            Resume::Next => (target.next_line(), "step"),
+            Resume::StepIn => (target.step_line(), "step"),
+            Resume::StepOut => (target.step_out(), "step"),
+        };
+        match result {
+            Ok(status) => self.report_stop(status, reason),
+            Err(e) => self.event(
+                "output",
+                json!({ "category": "stderr", "output": format!("{}\n", e) }),
+            ),
+        }
+    }
+
+    /// Translate a wait status into `stopped`, `exited` and `terminated` events.
+    fn report_stop(&mut self, status: WaitStatus, reason: &str) {
+        match status {
+            WaitStatus::Exited(_, exit_code) => {
+                self.target = None;
+                self.event("exited", json!({ "exitCode": exit_code }));
+                self.event("terminated", json!({}));
+            }
+            WaitStatus::Signaled(_, signal, _) => {
+                self.target = None;
+                self.event(
+                    "output",
+                    json!({ "category": "console", "output": format!("Program terminated with {}\n", signal) }),
+                );
+                self.event("terminated", json!({}));
+            }
+            WaitStatus::Stopped(_, Signal::SIGTRAP) => {
+                let hit = self
+                    .target
+                    .as_ref()
+                    .and_then(|t| t.stopped_at_breakpoint.and_then(|addr| t.breakpoints.get(&addr)))
+                    .map(|bp| bp.idx);
+                match hit {
+                    Some(idx) => self.stopped("breakpoint", None, Some(idx)),
+                    None => self.stopped(reason, None, None),
+                }
+            }
+            WaitStatus::Stopped(_, signal) => {
+                self.stopped("exception", Some(signal.as_str()), None);
+            }
+            _ => self.stopped(reason, None, None),
+        }
+    }
+
+    fn stopped(&mut self, reason: &str, description: Option<&str>, breakpoint: Option<u32>) {
+        let mut body = json!({
+            "reason": reason,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        });
+        if let Some(description) = description {
+            body["description"] = json!(description);
+            body["text"] = json!(description);
+        }
+        if let Some(breakpoint) = breakpoint {
+            body["hitBreakpointIds"] = json!([breakpoint]);
+        }
+        self.event("stopped", body);
+    }
+
+    fn respond(&mut self, request: &Value, body: Value) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": true,
+            "body": body,
+        }));
+    }
+
+    fn respond_error(&mut self, request: &Value, message: &str) {
+        self.send(json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": false,
+            "message": message,
+        }));
+    }
+
+    fn event(&mut self, event: &str, body: Value) {
+        self.send(json!({ "type": "event", "event": event, "body": body }));
+    }
+
+    fn send(&mut self, mut message: Value) {
+        message["seq"] = json!(self.seq);
+        self.seq += 1;
+        let content = message.to_string();
+        write!(self.output, "Content-Length: {}\r\n\r\n{}", content.len(), content)
+            .and_then(|_| self.output.flush())
+            .expect("Could not write DAP message.");
+    }
+}
+
+fn selected_frame(target: &Target, level: usize) -> Result<Frame, String> {
+    target
+        .backtrace()
+        .into_iter()
+        .nth(level)
+        .ok_or(String::from("No frame selected."))
+}
+
+/// The colon separated `debugFileDirectory` of a launch or attach request.
+fn debug_file_directories(args: &Value) -> Vec<String> {
+    args["debugFileDirectory"]
+        .as_str()
+        .unwrap_or(DEFAULT_DEBUG_FILE_DIRECTORY)
+        .split(':')
+        .filter(|dir| !dir.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+fn variable_json(target: &Target, var: &VariableValue, pretty: bool) -> Value {
+    let dwarf_info = &target.debug_info.dwarf_info;
+    json!({
+        "name": var.name,
+        "value": target.format_value(var, pretty),
+        "type": dwarf_info.type_name(var.t),
+        "variablesReference": 0,
+    })
+}
+
+/// The `count` instructions starting `instruction_offset` instructions from `address`, out
+/// of `instructions` decoded from `start`. The client expects exactly `count` of them, the
+/// ones outside of `instructions` are the address of an invalid instruction to pad with.
+fn instruction_window(
+    instructions: &[Instruction],
+    start: usize,
+    address: usize,
+    instruction_offset: i64,
+    count: i64,
+) -> Vec<Result<&Instruction, usize>> {
+    let first = instructions
+        .iter()
+        .position(|i| i.address >= address)
+        .unwrap_or(instructions.len()) as i64
+        + instruction_offset;
+    let end = instructions
+        .last()
+        .map_or(address, |i| i.address + i.bytes.len());
+    (first..first + count.max(0))
+        .map(|idx| match usize::try_from(idx).ok().and_then(|i| instructions.get(i)) {
+            Some(instruction) => Ok(instruction),
+            None if idx < 0 => Err(start.saturating_sub(idx.unsigned_abs() as usize)),
+            None => Err(end + idx as usize - instructions.len()),
+        })
+        .collect()
+}
+
+/// Read one `Content-Length` framed message.
+fn read_message(reader: &mut impl BufRead) -> Option<Value> {
+    let mut content_length = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).ok()? == 0 {
+            return None;
+        }
+        let line = line.trim_end();
+        if line.is_empty() && content_length.is_some() {
+            break;
+        }
+        if let Some(length) = line.strip_prefix("Content-Length:") {
+            content_length = length.trim().parse::<usize>().ok();
+        }
+    }
+
+    let mut content = vec![0; content_length?];
+    reader.read_exact(&mut content).ok()?;
+    serde_json::from_slice(&content).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two byte instructions at 0x10, 0x12 and 0x14.
+    fn instructions() -> Vec<Instruction> {
+        (0..3)
+            .map(|i| Instruction {
+                address: 0x10 + 2 * i,
+                bytes: vec![0x90, 0x90],
+                text: String::from("nop"),
+                branch_target: None,
+            })
+            .collect()
+    }
+
+    fn addresses(window: Vec<Result<&Instruction, usize>>) -> Vec<Result<usize, usize>> {
+        window.into_iter().map(|slot| slot.map(|i| i.address)).collect()
+    }
+
+    #[test]
+    fn window_at_address() {
+        let instructions = instructions();
+        let window = instruction_window(&instructions, 0x10, 0x12, 0, 2);
+        assert_eq!(addresses(window), vec![Ok(0x12), Ok(0x14)]);
+    }
+
+    #[test]
+    fn window_before_address() {
+        let instructions = instructions();
+        let window = instruction_window(&instructions, 0x10, 0x14, -2, 3);
+        assert_eq!(addresses(window), vec![Ok(0x10), Ok(0x12), Ok(0x14)]);
+    }
+
+    #[test]
+    fn window_padded_on_both_sides() {
+        let instructions = instructions();
+        let window = instruction_window(&instructions, 0x10, 0x10, -2, 6);
+        assert_eq!(
+            addresses(window),
+            vec![Err(0xe), Err(0xf), Ok(0x10), Ok(0x12), Ok(0x14), Err(0x16)]
+        );
+    }
+
+    #[test]
+    fn window_without_instructions() {
+        let window = instruction_window(&[], 0x20, 0x20, 0, 2);
+        assert_eq!(addresses(window), vec![Err(0x20), Err(0x21)]);
+    }
+}
@@ -0,0 +1,58 @@
+use std::fs;
+use std::path::Path;
+
+use object::Object;
+
+/// Where distributions install the debug info of their packages.
+pub const DEFAULT_DEBUG_FILE_DIRECTORY: &str = "/usr/lib/debug";
+
+/// Find the separate debug info of the executable or library at `path`, like gdb does:
+/// first `DIR/.build-id/xx/yyyy.debug` by its build-id, then the file named by
+/// `.gnu_debuglink` next to it, in its `.debug` directory or below `DIR`.
+pub fn find_debug_file(path: &str, object: &object::File, directories: &[String]) -> Option<String> {
+    if let Ok(Some(build_id)) = object.build_id() {
+        let hex: String = build_id.iter().map(|b| format!("{:02x}", b)).collect();
+        if hex.len() > 2 {
+            let found = directories
+                .iter()
+                .map(|dir| format!("{}/.build-id/{}/{}.debug", dir, &hex[..2], &hex[2..]))
+                .find(|candidate| has_build_id(candidate, build_id));
+            if found.is_some() {
+                return found;
+            }
+        }
+    }
+
+    let (name, crc) = object.gnu_debuglink().ok()??;
+    let name = String::from_utf8_lossy(name);
+    let path = fs::canonicalize(path).ok()?;
+    let dir = path.parent()?.to_string_lossy();
+    let mut candidates = vec![format!("{}/{}", dir, name), format!("{}/.debug/{}", dir, name)];
+    candidates.extend(directories.iter().map(|debug_dir| format!("{}{}/{}", debug_dir, dir, name)));
+    candidates
+        .into_iter()
+        // The link may name the file itself if it was not stripped.
+        .filter(|candidate| Path::new(candidate) != path)
+        .find(|candidate| has_crc(candidate, crc))
+}
+
+fn has_build_id(path: &str, build_id: &[u8]) -> bool {
+    let map = match map_file(path) {
+        Some(map) => map,
+        None => return false,
+    };
+    object::File::parse(&*map)
+        .ok()
+        .and_then(|object| object.build_id().ok().flatten().map(|id| id == build_id))
+        .unwrap_or(false)
+}
+
+/// True if the file exists and its CRC32 is the one `.gnu_debuglink` recorded.
+fn has_crc(path: &str, crc: u32) -> bool {
+    map_file(path).is_some_and(|map| crc32fast::hash(&map) == crc)
+}
+
+pub fn map_file(path: &str) -> Option<memmap2::Mmap> {
+    let file = fs::File::open(path).ok()?;
+    unsafe { memmap2::Mmap::map(&file).ok() }
+}
@@ -1,12 +1,76 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{stdin, stdout, Write};
+use std::path::PathBuf;
+
 use nix::sys::signal::Signal;
 use nix::sys::wait::WaitStatus;
+use regex::Regex;
+use serde_json::json;
+
+use crate::debugfile::DEFAULT_DEBUG_FILE_DIRECTORY;
+use crate::debuginfo;
+use crate::disassembler::Instruction;
+use crate::dprintf;
+use crate::gimliwrapper::TypeLookup;
+use crate::lineeditor::{Completions, LineEditor};
+use crate::output::{
+    BreakpointInfo, FrameDetails, FrameInfo, FunctionInfo, InstructionInfo, Output, RegisterInfo,
+    SharedLibraryInfo, StopEvent, StopReason, SymbolInfo, VariableInfo,
+};
+use crate::replcommand::{parse_number, BreakpointRef, DisassembleRange, LocationSpec, ReplCommand};
+use crate::symbolcache;
+use crate::target::{Breakpoint, Dprintf, Frame, Target, VariableValue, Watchpoint};
+use crate::util::take_stdout;
+
+/// Bytes disassembled if an address does not belong to a known function.
+const DEFAULT_DISASSEMBLY_LENGTH: usize = 32;
+
+/// Name of the init file read from the home and the current directory.
+const INIT_FILE: &str = ".minidbgrc";
 
-use crate::replcommand::ReplCommand;
-use crate::target::Target;
+/// Commands given on the command line, run in order before the prompt.
+pub enum Script {
+    /// `-x FILE`
+    File(String),
+    /// `-ex COMMAND`
+    Command(String),
+}
+
+/// How commands are read and how their results are presented.
+pub enum Interpreter {
+    /// Human readable output and a prompt.
+    Console,
+    /// One JSON object per line on the contained stdout, see `--interpreter=json`.
+    Json(File),
+}
 
 pub struct Debugger {
     target_process: Option<Target>,
     target_path: String,
+    interpreter: Interpreter,
+    /// Line editor of the console, `None` if it could not be set up.
+    editor: Option<LineEditor>,
+    /// The last step or continue command, repeated by an empty line.
+    last_resume: Option<String>,
+    scripts: Vec<Script>,
+    read_init_files: bool,
+    /// Exit after the scripts instead of showing a prompt.
+    batch: bool,
+    /// Exit code of the debugger, the status of the program once it exited.
+    exit_code: i32,
+    /// Breakpoint number and commands recorded so far while reading a `commands` list.
+    recording: Option<(u32, Vec<String>)>,
+    /// File dprintf output is appended to, see `set dprintf-log`.
+    dprintf_log: Option<File>,
+    /// Level of the frame `print` and `info locals` look at, reset whenever the program stops.
+    selected_frame: usize,
+    /// Where separate debug files are searched for, see `set debug-file-directory`.
+    debug_file_directories: Vec<String>,
+    /// Show Rust enums and standard library types like Rust does, see `set pretty-printers`.
+    pretty_printers: bool,
+    /// Files being executed by `source`, innermost last, to reject a script sourcing itself.
+    sourcing: Vec<PathBuf>,
 }
 
 impl Debugger {
@@ -14,143 +78,1080 @@ impl Debugger {
         Debugger {
             target_process: None,
             target_path,
+            interpreter: Interpreter::Console,
+            editor: None,
+            last_resume: None,
+            scripts: Vec::new(),
+            read_init_files: true,
+            batch: false,
+            exit_code: 0,
+            recording: None,
+            dprintf_log: None,
+            selected_frame: 0,
+            debug_file_directories: vec![String::from(DEFAULT_DEBUG_FILE_DIRECTORY)],
+            pretty_printers: true,
+            sourcing: Vec::new(),
         }
     }
 
-    pub fn run(&mut self) -> Result<(), ()> {
+    pub fn add_script(&mut self, script: Script) {
+        self.scripts.push(script);
+    }
 
-        let target_process = Target::create(&self.target_path)
-            .expect("Could not instantiate target process.");
-        self.target_process = Some(target_process);
+    /// Don't read `~/.minidbgrc` and `./.minidbgrc`.
+    pub fn skip_init_files(&mut self) {
+        self.read_init_files = false;
+    }
 
-        self.run_repl();
+    /// Run the scripts and exit. The first failing command aborts with exit code 1.
+    pub fn use_batch_mode(&mut self) {
+        self.batch = true;
+    }
 
+    /// Switch to machine readable JSON output. Everything else printed goes to stderr.
+    pub fn use_json_interpreter(&mut self) -> Result<(), nix::Error> {
+        self.interpreter = Interpreter::Json(take_stdout()?);
         Ok(())
     }
 
-    fn run_repl(&mut self) {
-        loop {
-            let cmd = crate::replcommand::get_command();
+    /// Run the debugger and return the exit code of the process.
+    pub fn run(&mut self) -> i32 {
+
+        self.start();
 
-            if let ReplCommand::Exit = cmd {
-                if let Some(t) = &mut self.target_process {
-                    println!("Killing child.");
-                    t.kill().expect("Could not kill child.");
+        if self.run_startup_scripts() && !self.batch {
+            if let Interpreter::Console = self.interpreter {
+                match LineEditor::create() {
+                    Ok(editor) => self.editor = Some(editor),
+                    Err(e) => println!("Could not set up line editing: {}", e),
                 }
-                break;
-            } else {
-                self.handle_command(&cmd);
+            }
+            self.run_repl();
+        }
+        self.kill_target();
+        if let Some(editor) = &mut self.editor {
+            editor.save_history();
+        }
+
+        self.exit_code
+    }
+
+    /// Run the full screen terminal UI instead of the line based REPL.
+    pub fn run_tui(&mut self) -> Result<(), ()> {
+        let result = crate::tui::run(self);
+        self.kill_target();
+
+        result.map_err(|e| println!("TUI error: {}", e))
+    }
 
-                let should_wait = match cmd {
-                    ReplCommand::Continue => true,
-                    ReplCommand::SingleStep => true,
-                    _ => false,
+    /// Launch the target process.
+    pub fn start(&mut self) {
+        match Target::create(&self.target_path, &[], &self.debug_file_directories) {
+            Ok(target_process) => self.target_process = Some(target_process),
+            Err(e) => println!("Could not instantiate target process: {}", e),
+        }
+    }
+
+    pub fn target_process(&self) -> Option<&Target> {
+        self.target_process.as_ref()
+    }
+
+    pub fn selected_frame(&self) -> usize {
+        self.selected_frame
+    }
+
+    pub fn pretty_printers(&self) -> bool {
+        self.pretty_printers
+    }
+
+    /// Add a line to the command list being recorded, `end` finishes it.
+    fn record(&mut self, line: &str) -> Result<Output, String> {
+        if line != "end" {
+            if let Some((_, commands)) = &mut self.recording {
+                if !line.is_empty() {
+                    commands.push(String::from(line));
+                }
+            }
+            return Ok(Output::Nothing);
+        }
+
+        let (idx, commands) = self.recording.take().unwrap();
+        let mut found = false;
+        // All locations of a breakpoint share its commands.
+        for bp in self.target()?.breakpoints.values_mut().filter(|bp| bp.idx == idx) {
+            bp.commands = commands.clone();
+            found = true;
+        }
+        if !found {
+            return Err(format!("No breakpoint number {}.", idx));
+        }
+        Ok(Output::Nothing)
+    }
+
+    /// Parse and execute a single command line. Returns `None` if the user wants to exit.
+    /// An empty line repeats the last step or continue command.
+    fn execute(&mut self, input: &str) -> Option<Result<Output, String>> {
+        if self.recording.is_some() {
+            return Some(self.record(input.trim()));
+        }
+        let input = match (input.trim().is_empty(), &self.last_resume) {
+            (true, Some(last_resume)) => last_resume.clone(),
+            _ => String::from(input),
+        };
+        let cmd = match crate::replcommand::parse_command(&input) {
+            Ok(Some(cmd)) => cmd,
+            Ok(None) => return Some(Ok(Output::Nothing)),
+            Err(message) => return Some(Err(message)),
+        };
+        match cmd {
+            ReplCommand::Exit => None,
+            ReplCommand::Source(path) => match self.source(&path) {
+                Ok(true) => Some(Ok(Output::Nothing)),
+                Ok(false) => None,
+                Err(message) => Some(Err(message)),
+            },
+            cmd => {
+                if let ReplCommand::Continue
+                | ReplCommand::SingleStep(_)
+                | ReplCommand::Step(_)
+                | ReplCommand::Next(_)
+                | ReplCommand::Finish = cmd
+                {
+                    self.last_resume = Some(input);
+                }
+                Some(self.handle_command(&cmd))
+            }
+        }
+    }
+
+    /// Run the init files and the scripts from the command line.
+    /// Returns false if the session is over, because of `exit` or an error in batch mode.
+    pub fn run_startup_scripts(&mut self) -> bool {
+        let mut scripts = Vec::new();
+        if self.read_init_files {
+            let mut init_files: Vec<PathBuf> = Vec::new();
+            if let Some(home) = env::var_os("HOME") {
+                init_files.push(PathBuf::from(home).join(INIT_FILE));
+            }
+            init_files.push(PathBuf::from(INIT_FILE));
+            // Don't read the same file twice when started from the home directory.
+            let mut seen = Vec::new();
+            for file in init_files {
+                if let Ok(path) = fs::canonicalize(&file) {
+                    if !seen.contains(&path) {
+                        scripts.push(Script::File(path.to_string_lossy().to_string()));
+                        seen.push(path);
+                    }
+                }
+            }
+        }
+        scripts.append(&mut self.scripts);
+
+        for script in scripts {
+            let (command, result) = match script {
+                Script::File(path) => (format!("source {}", path), self.source(&path)),
+                Script::Command(command) => {
+                    let result = self.run_command(&command);
+                    (command, result)
+                }
+            };
+            match result {
+                Ok(true) => {}
+                Ok(false) => return false,
+                Err(message) => {
+                    self.emit(&command, Err(message));
+                    if self.batch {
+                        self.exit_code = 1;
+                        return false;
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    /// Execute the commands in the file at `path`, stopping at the first error.
+    /// Returns false if one of them was `exit`.
+    fn source(&mut self, path: &str) -> Result<bool, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let file = fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+        if self.sourcing.contains(&file) {
+            return Err(format!("{}: already being sourced.", path));
+        }
+        self.sourcing.push(file);
+        let result = self.source_lines(path, &text);
+        self.sourcing.pop();
+        result
+    }
+
+    fn source_lines(&mut self, path: &str, text: &str) -> Result<bool, String> {
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match self.run_command(line) {
+                Ok(true) => {}
+                Ok(false) => return Ok(false),
+                Err(message) => return Err(format!("{}:{}: {}", path, idx + 1, message)),
+            }
+        }
+        Ok(true)
+    }
+
+    fn run_repl(&mut self) {
+        while let Some(input) = self.read_line() {
+            match self.run_command(&input) {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(message) => self.emit(input.trim(), Err(message)),
+            }
+        }
+    }
+
+    /// Execute a command line and present its result, then run the commands of the
+    /// breakpoint it stopped at. Errors are returned instead of presented.
+    /// Returns false if the user wants to exit.
+    pub fn run_command(&mut self, input: &str) -> Result<bool, String> {
+        let output = match self.execute(input) {
+            Some(result) => result?,
+            None => return Ok(false),
+        };
+        let mut hit = breakpoint_hit(&output);
+        self.emit(input.trim(), Ok(output));
+
+        // A command that resumes the program ends the list, the next stop may start another one.
+        while let Some(idx) = hit.take() {
+            let commands = match &self.target_process {
+                Some(target) => target
+                    .breakpoints
+                    .values()
+                    .find(|bp| bp.idx == idx)
+                    .map(|bp| bp.commands.clone())
+                    .unwrap_or_default(),
+                None => Vec::new(),
+            };
+            for command in commands {
+                let output = match self.execute(&command) {
+                    Some(result) => result?,
+                    None => return Ok(false),
                 };
+                let resumed = matches!(output, Output::Stopped(_));
+                hit = breakpoint_hit(&output);
+                self.emit(&command, Ok(output));
+                if resumed {
+                    break;
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    fn kill_target(&mut self) {
+        if let Some(t) = &mut self.target_process {
+            if let Interpreter::Console = self.interpreter {
+                println!("Killing child.");
+            }
+            t.kill().expect("Could not kill child.");
+        }
+    }
+
+    /// Read the next command, `None` at the end of input.
+    fn read_line(&mut self) -> Option<String> {
+        let completions = self.completions();
+        if let Some(editor) = &mut self.editor {
+            editor.set_completions(completions);
+            let prompt = if self.recording.is_some() { ">" } else { "> " };
+            return editor.read_line(prompt);
+        }
 
-                if should_wait {
-                    if let Some(target) = &mut self.target_process {
-                        let wait_status = target.wait().expect("Error during wait.");
-                        match wait_status {
-                            WaitStatus::Exited(_, exit_code) => {
-                                println!("Program exited with code {}", exit_code);
-                                self.target_process = None;
-                            }
-                            WaitStatus::Stopped(_, Signal::SIGSEGV) => {
-                                let location = target.get_current_location().unwrap();
-                                print!("{}", location);
-                                println!(" 🔥 Segmentation Fault 🔥:");
-                                target.print_current_source_line(1);
-                            }
-                            _ => {
-                                let location = target.get_current_location().unwrap();
-                                println!("{}", location);
-                                target.print_current_source_line(1);
-                            }
+        if let Interpreter::Console = self.interpreter {
+            print!("> ");
+            stdout().flush().unwrap();
+        }
+
+        let mut input = String::new();
+        match stdin().read_line(&mut input) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(input),
+        }
+    }
+
+    /// What tab completion offers in the current state.
+    fn completions(&self) -> Completions {
+        let target = match &self.target_process {
+            Some(target) => target,
+            None => return Completions::default(),
+        };
+        let dwarf_info = &target.debug_info.dwarf_info;
+        let mut variables: Vec<String> = match target.backtrace().get(self.selected_frame) {
+            Some(frame) => target
+                .frame_arguments(frame)
+                .into_iter()
+                .chain(target.frame_locals(frame))
+                .map(|v| v.name)
+                .collect(),
+            None => Vec::new(),
+        };
+        variables.extend(dwarf_info.global_names());
+        Completions {
+            functions: dwarf_info.function_names(),
+            source_files: dwarf_info.source_files(),
+            variables,
+            breakpoints: target
+                .breakpoints
+                .values()
+                .map(|bp| (bp.address, bp.idx))
+                .collect(),
+        }
+    }
+
+    /// Present the result of `command` using the active interpreter.
+    fn emit(&mut self, command: &str, result: Result<Output, String>) {
+        if let Ok(Output::Nothing) = result {
+            return;
+        }
+        match &mut self.interpreter {
+            Interpreter::Console => match result {
+                Ok(output) => print!("{}", output),
+                Err(message) => println!("{}", message),
+            },
+            Interpreter::Json(out) => {
+                let mut lines = Vec::new();
+                match &result {
+                    Ok(output) => {
+                        lines.push(json!({
+                            "type": "result",
+                            "command": command,
+                            "success": true,
+                            "result": output.to_json(),
+                        }));
+                        if let Output::Stopped(event) = output {
+                            lines.push(event.to_json());
                         }
                     }
+                    Err(message) => lines.push(json!({
+                        "type": "result",
+                        "command": command,
+                        "success": false,
+                        "error": message,
+                    })),
+                }
+                for line in lines {
+                    writeln!(out, "{}", line).expect("Could not write JSON output.");
                 }
+                out.flush().expect("Could not write JSON output.");
             }
         }
     }
 
-    fn handle_command(&mut self, cmd: &ReplCommand) {
-        match cmd {
-            ReplCommand::Start => {
-                println!("doesnt do anything atm")
+    /// Turn the wait status after resuming the child into a stop event.
+    fn stop_event(&mut self, wait_status: WaitStatus) -> StopEvent {
+        self.selected_frame = 0;
+        match wait_status {
+            WaitStatus::Exited(_, exit_code) => {
+                self.target_process = None;
+                self.exit_code = exit_code;
+                StopEvent::Exited { exit_code }
+            }
+            WaitStatus::Signaled(_, signal, _) => {
+                self.target_process = None;
+                // Like a shell reports a process killed by a signal.
+                self.exit_code = 128 + signal as i32;
+                StopEvent::Terminated {
+                    signal: String::from(signal.as_str()),
+                }
             }
+            _ => {
+                let target = self.target_process.as_ref().unwrap();
+                let watchpoint = target.stopped_at_watchpoint.and_then(|(idx, old)| {
+                    let watchpoint = target.watchpoints.iter().find(|w| w.idx == idx)?;
+                    let dwarf_info = &target.debug_info.dwarf_info;
+                    Some(StopReason::Watchpoint {
+                        idx,
+                        expression: watchpoint.expression.clone(),
+                        old: dwarf_info.format_value(watchpoint.t, old),
+                        new: dwarf_info.format_value(watchpoint.t, watchpoint.value),
+                    })
+                });
+                let reason = watchpoint.unwrap_or_else(|| match wait_status {
+                    WaitStatus::Stopped(_, Signal::SIGTRAP) => target
+                        .stopped_at_breakpoint
+                        .and_then(|addr| target.breakpoints.get(&addr))
+                        .filter(|bp| bp.dprintf.is_none())
+                        .map_or(StopReason::Step, |bp| StopReason::Breakpoint(bp.idx)),
+                    WaitStatus::Stopped(_, signal) => StopReason::Signal(String::from(signal.as_str())),
+                    _ => StopReason::Step,
+                });
+                let address = target.get_rip();
+                StopEvent::Stopped {
+                    reason,
+                    address,
+                    location: target.location_at(address),
+                    source: target.source_lines_at(address, 1),
+                }
+            }
+        }
+    }
+
+    fn target(&mut self) -> Result<&mut Target, String> {
+        self.target_process
+            .as_mut()
+            .ok_or(String::from("The program is not being run."))
+    }
+
+    fn handle_command(&mut self, cmd: &ReplCommand) -> Result<Output, String> {
+        let wait_status = match cmd {
             ReplCommand::Continue => {
-                if let Some(target) = &mut self.target_process {
-                    target.cont().expect("Error during continue call.");
-                } else {
-                    let target_process = Target::create(&self.target_path)
-                        .expect("Could not instantiate target process.");
+                if self.target_process.is_none() {
+                    let target_process =
+                        Target::create(&self.target_path, &[], &self.debug_file_directories)
+                        .map_err(|e| format!("Could not instantiate target process: {}", e))?;
                     self.target_process = Some(target_process);
-                    if let Some(target) = &mut self.target_process {
-                        target.cont().expect("Error during continue call.");
+                }
+                loop {
+                    let target = self.target()?;
+                    target.cont().map_err(|e| format!("Error during continue call: {}", e))?;
+                    let wait_status =
+                        target.wait().map_err(|e| format!("Error during wait: {}", e))?;
+                    // dprintf breakpoints only print, the user never sees them stop.
+                    if !target.record_dprintf() {
+                        break wait_status;
                     }
+                    self.print_dprintf();
                 }
             }
-            ReplCommand::SetBp(addr) => {
-                if let Some(target) = &mut self.target_process {
-                    target
-                        .set_breakpoint(*addr)
-                        .expect("Error while setting breakpoint.");
+            ReplCommand::SingleStep(count) => self.repeat(*count, "step", Target::step)?,
+            ReplCommand::Step(count) => self.repeat(*count, "step", Target::step_line)?,
+            ReplCommand::Next(count) => self.repeat(*count, "next", Target::next_line)?,
+            ReplCommand::Finish => self
+                .target()?
+                .step_out()
+                .map_err(|e| format!("Error during finish call: {}", e))?,
+            ReplCommand::Commands(number) => {
+                let breakpoints = &self.target()?.breakpoints;
+                let idx = match number {
+                    Some(idx) => breakpoints.values().find(|bp| bp.idx == *idx).map(|bp| bp.idx),
+                    None => breakpoints.values().map(|bp| bp.idx).max(),
                 }
+                .ok_or(match number {
+                    Some(idx) => format!("No breakpoint number {}.", idx),
+                    None => String::from("No breakpoints."),
+                })?;
+                self.recording = Some((idx, Vec::new()));
+                // Only a human needs to be told how to go on.
+                return Ok(match self.editor {
+                    Some(_) => Output::Message(format!(
+                        "Type commands for breakpoint {}, one per line.\nEnd with a line saying just \"end\".",
+                        idx
+                    )),
+                    None => Output::Nothing,
+                });
             }
-            ReplCommand::SetBpName(name) => {
+            ReplCommand::Help(topic) => {
+                return crate::replcommand::help(topic.as_deref()).map(Output::Help)
+            }
+            ReplCommand::Frame(_)
+            | ReplCommand::Up(_)
+            | ReplCommand::Down(_)
+            | ReplCommand::InfoFrame
+            | ReplCommand::InfoLocals
+            | ReplCommand::InfoArgs => return self.handle_frame_command(cmd),
+            ReplCommand::SetDprintfLog(path) => {
+                self.dprintf_log = match path {
+                    Some(path) => Some(
+                        File::options()
+                            .create(true)
+                            .append(true)
+                            .open(path)
+                            .map_err(|e| format!("Could not open {}: {}", path, e))?,
+                    ),
+                    None => None,
+                };
+                return Ok(Output::Nothing);
+            }
+            ReplCommand::SetDebugFileDirectory(directories) => {
+                self.debug_file_directories = directories.clone();
                 if let Some(target) = &mut self.target_process {
-                    if let Some(f) = target.debug_info.dwarf_info.get_function_by_name(name) {
-                        let addr = f.address_range.first().unwrap().0 + target.base_address;
-                        target
-                            .set_breakpoint(addr)
-                            .expect("Error while setting breakpoint.");
-                    } else {
-                        println!("Could not find function with name {}", name);
+                    target.set_debug_file_directories(directories)?;
+                }
+                return Ok(Output::Nothing);
+            }
+            ReplCommand::SetPrettyPrinters(on) => {
+                self.pretty_printers = *on;
+                return Ok(Output::Nothing);
+            }
+            ReplCommand::MaintCacheInfo => {
+                let directory = symbolcache::directory().ok_or("No cache directory, HOME is not set.")?;
+                return Ok(Output::SymbolCache {
+                    directory: directory.to_string_lossy().to_string(),
+                    entries: symbolcache::entries(),
+                });
+            }
+            ReplCommand::MaintCacheClear => {
+                let removed = symbolcache::clear().map_err(|e| format!("Could not clear the symbol cache: {}", e))?;
+                return Ok(Output::Message(format!("Removed {} entries from the symbol cache.", removed)));
+            }
+            _ => return self.handle_inspect_command(cmd),
+        };
+        self.print_dprintf();
+        Ok(Output::Stopped(self.stop_event(wait_status)))
+    }
+
+    /// Print the messages of the dprintf breakpoints hit by the last resume.
+    fn print_dprintf(&mut self) {
+        let messages = match &mut self.target_process {
+            Some(target) => std::mem::take(&mut target.dprintf_output),
+            None => return,
+        };
+        for message in messages {
+            self.log_dprintf(&message);
+        }
+    }
+
+    fn log_dprintf(&mut self, message: &str) {
+        if let Some(log) = &mut self.dprintf_log {
+            if let Err(e) = log.write_all(message.as_bytes()) {
+                println!("Could not write dprintf log: {}", e);
+            }
+            return;
+        }
+        match &mut self.interpreter {
+            Interpreter::Console => {
+                print!("{}", message);
+                stdout().flush().ok();
+            }
+            Interpreter::Json(out) => {
+                let event = json!({ "type": "event", "event": "dprintf", "message": message });
+                writeln!(out, "{}", event).expect("Could not write JSON output.");
+            }
+        }
+    }
+
+    /// Resume `count` times, stopping early at breakpoints, signals and exit.
+    fn repeat(
+        &mut self,
+        count: usize,
+        name: &str,
+        resume: fn(&mut Target) -> Result<WaitStatus, nix::Error>,
+    ) -> Result<WaitStatus, String> {
+        let target = self.target()?;
+        let mut wait_status;
+        let mut remaining = count;
+        loop {
+            wait_status =
+                resume(target).map_err(|e| format!("Error during {} call: {}", name, e))?;
+            remaining -= 1;
+            let stepped = matches!(wait_status, WaitStatus::Stopped(_, Signal::SIGTRAP))
+                && target.stopped_at_breakpoint.is_none();
+            if remaining == 0 || !stepped {
+                return Ok(wait_status);
+            }
+        }
+    }
+
+    /// Commands that select a frame or show the selected one.
+    fn handle_frame_command(&mut self, cmd: &ReplCommand) -> Result<Output, String> {
+        let pretty = self.pretty_printers;
+        let target = self
+            .target_process
+            .as_ref()
+            .ok_or(String::from("The program is not being run."))?;
+        let mut frames = target.backtrace();
+        if frames.is_empty() {
+            return Err(String::from("No stack."));
+        }
+        let outermost = frames.len() - 1;
+        let selected = self.selected_frame.min(outermost);
+        let level = match cmd {
+            ReplCommand::Frame(Some(level)) if *level > outermost => {
+                return Err(format!("No frame at level {}.", level))
+            }
+            ReplCommand::Frame(Some(level)) => *level,
+            ReplCommand::Up(_) if selected == outermost => {
+                return Err(String::from("Initial frame selected; you cannot go up."))
+            }
+            ReplCommand::Up(count) => (selected + count).min(outermost),
+            ReplCommand::Down(_) if selected == 0 => {
+                return Err(String::from("Bottom (innermost) frame selected; you cannot go down."))
+            }
+            ReplCommand::Down(count) => selected.saturating_sub(*count),
+            _ => selected,
+        };
+        self.selected_frame = level;
+
+        match cmd {
+            ReplCommand::InfoFrame => {
+                let frame = &frames[level];
+                Ok(Output::FrameDetails(FrameDetails {
+                    level,
+                    inlined: frame.inlined,
+                    rip: frame.address,
+                    location: frame.location.clone(),
+                    cfa: frame.cfa(),
+                    saved_rip: target.read_word(frame.rbp + 8).ok(),
+                    callee_cfa: level.checked_sub(1).map(|callee| frames[callee].cfa()),
+                    caller_cfa: frames.get(level + 1).map(|caller| caller.cfa()),
+                    language: target
+                        .frame_function(frame)
+                        .and_then(|function| function.language.clone()),
+                    saved_registers: vec![("rbp", frame.rbp), ("rip", frame.rbp + 8)],
+                }))
+            }
+            ReplCommand::InfoLocals => {
+                let locals = target.frame_locals(&frames[level]);
+                if locals.is_empty() {
+                    return Ok(Output::Message(String::from("No locals.")));
+                }
+                Ok(Output::Variables(variable_infos(target, locals, pretty)))
+            }
+            ReplCommand::InfoArgs => {
+                let arguments = target.frame_arguments(&frames[level]);
+                if arguments.is_empty() {
+                    return Ok(Output::Message(String::from("No arguments.")));
+                }
+                Ok(Output::Variables(variable_infos(target, arguments, pretty)))
+            }
+            _ => {
+                let frame = frames.swap_remove(level);
+                let source = debuginfo::source_lines(&frame.location, 0);
+                Ok(Output::Frame {
+                    frame: frame_info(target, frame, pretty),
+                    source,
+                })
+            }
+        }
+    }
+
+    /// Commands that don't resume the child.
+    fn handle_inspect_command(&mut self, cmd: &ReplCommand) -> Result<Output, String> {
+        let selected_frame = self.selected_frame;
+        let pretty = self.pretty_printers;
+        let target = self.target()?;
+        match cmd {
+            // The function may be in a library that isn't loaded yet.
+            ReplCommand::SetBp(LocationSpec::Function(name))
+                if target.libraries_pending() && target.function_addresses(name).is_empty() =>
+            {
+                let idx = target.set_pending_breakpoint(name);
+                Ok(Output::BreakpointSet(pending_breakpoint_info(idx, name)))
+            }
+            ReplCommand::SetBp(location) => {
+                let addrs = resolve_locations(target, location)?;
+                set_breakpoint(target, &addrs)
+            }
+            ReplCommand::DeleteBp(breakpoint) => {
+                if let BreakpointRef::Number(idx) = breakpoint {
+                    let watchpoint = target
+                        .delete_watchpoint(*idx)
+                        .map_err(|e| format!("Error while deleting watchpoint: {}", e))?;
+                    if let Some(watchpoint) = watchpoint {
+                        return Ok(Output::BreakpointDeleted(watchpoint_info(&watchpoint)));
+                    }
+                    let pending = target.pending_breakpoints.iter().position(|bp| bp.idx == *idx);
+                    if let Some(pending) = pending {
+                        let bp = target.pending_breakpoints.remove(pending);
+                        return Ok(Output::BreakpointDeleted(pending_breakpoint_info(bp.idx, &bp.function)));
+                    }
+                }
+                let addrs = match breakpoint {
+                    BreakpointRef::Address(addr) => vec![*addr],
+                    BreakpointRef::Number(idx) => {
+                        let mut addrs: Vec<usize> = target
+                            .breakpoints
+                            .values()
+                            .filter(|bp| bp.idx == *idx)
+                            .map(|bp| bp.address)
+                            .collect();
+                        if addrs.is_empty() {
+                            return Err(format!("No breakpoint number {}.", idx));
+                        }
+                        addrs.sort();
+                        addrs
+                    }
+                };
+                // A breakpoint on an inlined function goes away with all its locations.
+                let mut deleted = Vec::new();
+                for addr in &addrs {
+                    match target.delete_breakpoint(*addr) {
+                        Ok(Some(bp)) => deleted.push(bp),
+                        Ok(None) => return Err(format!("No breakpoint at address {:#x}.", addr)),
+                        Err(e) => return Err(format!("Error while deleting breakpoint: {}", e)),
                     }
                 }
+                Ok(Output::BreakpointDeleted(breakpoint_info(target, &deleted[0])))
             }
-            ReplCommand::DeleteBp(addr) => {
-                if let Some(target) = &mut self.target_process {
-                    target
-                        .delete_breakpoint(*addr)
-                        .expect("Error while deleting breakpoint.");
+            ReplCommand::Dprintf {
+                location,
+                format,
+                arguments,
+            } => {
+                dprintf::check_arguments(format, arguments.len())?;
+                let mut addr = resolve_location(target, location)?;
+                if let LocationSpec::Function(name) = location {
+                    // The arguments are only in place once the prologue has run.
+                    let dwarf_info = &target.debug_info.dwarf_info;
+                    if let Some((low_pc, high_pc)) =
+                        dwarf_info.get_function_by_name(name).and_then(|f| f.address_range.first())
+                    {
+                        addr = dwarf_info.skip_prologue(*low_pc, *high_pc) + target.base_address;
+                    }
                 }
+                if let Some(bp) = target.breakpoints.get(&addr) {
+                    return Err(format!("Breakpoint {} is already at {:#x}.", bp.idx, addr));
+                }
+                target
+                    .set_breakpoint(addr)
+                    .map_err(|e| format!("Error while setting breakpoint: {}", e))?;
+                let bp = target.breakpoints.get_mut(&addr).unwrap();
+                bp.dprintf = Some(Dprintf {
+                    format: format.clone(),
+                    arguments: arguments.clone(),
+                });
+                Ok(Output::BreakpointSet(breakpoint_info(target, &target.breakpoints[&addr])))
             }
             ReplCommand::ListBps => {
-                if let Some(target) = &self.target_process {
-                    target.list_breakpoints();
-                }
+                let mut bps: Vec<BreakpointInfo> = target
+                    .breakpoints
+                    .values()
+                    .map(|bp| breakpoint_info(target, bp))
+                    .chain(
+                        target
+                            .pending_breakpoints
+                            .iter()
+                            .map(|bp| pending_breakpoint_info(bp.idx, &bp.function)),
+                    )
+                    .chain(target.watchpoints.iter().map(watchpoint_info))
+                    .collect();
+                bps.sort_by_key(|bp| bp.idx);
+                Ok(Output::Breakpoints(bps))
             }
             ReplCommand::GetRegs => {
-                if let Some(target) = &self.target_process {
+                let registers = target
+                    .registers()
+                    .map_err(|e| format!("Could not read registers: {}", e))?;
+                Ok(Output::Registers(
+                    registers
+                        .into_iter()
+                        .map(|(name, value)| RegisterInfo {
+                            name,
+                            value,
+                            pointee: match name {
+                                "rbp" | "rsp" => target.read_word(value as usize).ok(),
+                                _ => None,
+                            },
+                        })
+                        .collect(),
+                ))
+            }
+            ReplCommand::Backtrace => Ok(Output::Backtrace(
+                target
+                    .backtrace()
+                    .into_iter()
+                    .map(|frame| frame_info(target, frame, pretty))
+                    .collect(),
+            )),
+            ReplCommand::Disassemble { range, with_source } => {
+                let rip = target.get_rip();
+                let around = |addr| {
                     target
-                        .print_registers()
-                        .expect("Error during continue call.");
-                }
+                        .function_bounds(addr)
+                        .unwrap_or((addr, addr + DEFAULT_DISASSEMBLY_LENGTH))
+                };
+                let (start, end) = match range {
+                    None => around(rip),
+                    Some(DisassembleRange::Around(location)) => {
+                        around(resolve_location(target, location)?)
+                    }
+                    Some(DisassembleRange::Range(start, end)) => (*start, *end),
+                };
+                let instructions = target
+                    .disassemble(start, end - start)
+                    .map_err(|e| format!("Could not read memory: {}", e))?;
+                Ok(Output::Disassembly(instruction_infos(
+                    target,
+                    instructions,
+                    rip,
+                    *with_source,
+                )))
             }
-            ReplCommand::SingleStep => {
-                if let Some(target) = &self.target_process {
-                    target.step().expect("Error during step call.");
+            ReplCommand::InfoSymbol(expression) => {
+                let frame = target.backtrace().into_iter().nth(selected_frame);
+                let address = match &frame {
+                    Some(frame) => target.evaluate(expression, frame)?.value as usize,
+                    None => parse_number(expression)
+                        .ok_or(format!("Invalid ADDRESS \"{}\".", expression))?,
+                };
+                let (debug_info, base) = target.module_at(address);
+                let symbol = address
+                    .checked_sub(base)
+                    .and_then(|address| debug_info.symbols.lookup(address));
+                match symbol {
+                    Some((symbol, offset)) => Ok(Output::Symbol(SymbolInfo {
+                        name: symbol.name.clone(),
+                        offset,
+                        section: symbol.section.clone(),
+                    })),
+                    None => Ok(Output::Message(format!("No symbol matches {}.", expression))),
                 }
             }
-            ReplCommand::Backtrace => {
-                if let Some(target) = &self.target_process {
-                    target.print_backtrace();
-                }
+            ReplCommand::InfoFunctions(regex) => {
+                let regex = Regex::new(regex.as_deref().unwrap_or(""))
+                    .map_err(|e| format!("Invalid regular expression: {}", e))?;
+                Ok(Output::Functions(function_infos(target, &regex)))
             }
-            ReplCommand::GetVar => {
-                if let Some(target) = &self.target_process {
-                    let cfa = target.get_cfa();
-                    let fun = target.debug_info.dwarf_info.get_function_by_name("main").unwrap();
-                    let var = fun.local_variables.iter().find(|v| v.name == "a").unwrap();
-                    let addr = cfa as i64 + var.fbreg_offset;
-                    target.read_bytes(addr as usize, 4).unwrap();
-                }
+            ReplCommand::InfoSharedLibrary => Ok(Output::SharedLibraries(
+                target
+                    .libraries
+                    .iter()
+                    .map(|library| SharedLibraryInfo {
+                        from: library.start,
+                        to: library.end,
+                        has_debug_info: library.debug_info.dwarf_info.has_line_info(),
+                        path: library.path.clone(),
+                    })
+                    .collect(),
+            )),
+            ReplCommand::Print { expression, raw } => {
+                let frame = target
+                    .backtrace()
+                    .into_iter()
+                    .nth(selected_frame)
+                    .ok_or(String::from("No frame selected."))?;
+                let value = target.evaluate(expression, &frame)?;
+                let mut infos = variable_infos(target, vec![value], pretty && !raw);
+                Ok(Output::Value(infos.remove(0)))
             }
-            ReplCommand::Frame => todo!(),
-            _ => {
-                println!("Unhandled command: {:?}", cmd);
+            ReplCommand::Watch(expression) => {
+                let frame = target
+                    .backtrace()
+                    .into_iter()
+                    .nth(selected_frame)
+                    .ok_or(String::from("No frame selected."))?;
+                let idx = target.set_watchpoint(expression, &frame)?;
+                let watchpoint = target.watchpoints.iter().find(|w| w.idx == idx).unwrap();
+                Ok(Output::BreakpointSet(watchpoint_info(watchpoint)))
+            }
+            _ => Err(format!("Unhandled command: {:?}", cmd)),
+        }
+    }
+}
+
+/// Set a breakpoint at `addrs`, the first address and the other places an inlined
+/// function is at.
+fn set_breakpoint(target: &mut Target, addrs: &[usize]) -> Result<Output, String> {
+    let addr = addrs[0];
+    let exists = addrs.iter().all(|addr| target.breakpoints.contains_key(addr));
+    let idx = target
+        .set_breakpoint(addr)
+        .map_err(|e| format!("Error while setting breakpoint: {}", e))?;
+    for addr in &addrs[1..] {
+        target
+            .add_breakpoint_location(*addr, idx)
+            .map_err(|e| format!("Error while setting breakpoint: {}", e))?;
+    }
+    let info = breakpoint_info(target, &target.breakpoints[&addr]);
+    if exists {
+        Ok(Output::BreakpointExists(info))
+    } else {
+        Ok(Output::BreakpointSet(info))
+    }
+}
+
+/// Absolute address of a location, for a function the first of `resolve_locations`.
+fn resolve_location(target: &Target, location: &LocationSpec) -> Result<usize, String> {
+    resolve_locations(target, location).map(|addrs| addrs[0])
+}
+
+/// Absolute addresses of a location. A function has its own code and each place it is
+/// inlined at.
+fn resolve_locations(target: &Target, location: &LocationSpec) -> Result<Vec<usize>, String> {
+    let dwarf_info = &target.debug_info.dwarf_info;
+    let (file, line) = match location {
+        LocationSpec::Address(addr) => return Ok(vec![*addr]),
+        LocationSpec::Function(name) => {
+            let addrs = target.function_addresses(name);
+            if addrs.is_empty() {
+                return Err(format!("Could not find function with name {}", name));
             }
+            return Ok(addrs);
         }
+        LocationSpec::FileLine(file, line) => (file.clone(), *line),
+        LocationSpec::Line(line) => match target.get_current_location() {
+            Some(location) => (location.file, *line),
+            None => return Err(String::from("No source file for the current location.")),
+        },
+    };
+    match dwarf_info.get_address_for_line(&file, line) {
+        Some((_, addr)) => Ok(vec![addr + target.base_address]),
+        None => Err(format!("No code at {}:{}.", file, line)),
+    }
+}
+
+fn breakpoint_info(target: &Target, bp: &Breakpoint) -> BreakpointInfo {
+    BreakpointInfo {
+        idx: bp.idx,
+        address: bp.address,
+        locations: target.breakpoints.values().filter(|other| other.idx == bp.idx).count().max(1),
+        location: target.location_at(bp.address),
+        commands: bp.commands.clone(),
+        dprintf: bp
+            .dprintf
+            .as_ref()
+            .map(|dprintf| (dprintf.format.clone(), dprintf.arguments.clone())),
+        pending: None,
+        watch: None,
+    }
+}
+
+fn pending_breakpoint_info(idx: u32, function: &str) -> BreakpointInfo {
+    BreakpointInfo {
+        idx,
+        address: 0,
+        locations: 1,
+        location: None,
+        commands: Vec::new(),
+        dprintf: None,
+        pending: Some(String::from(function)),
+        watch: None,
+    }
+}
+
+fn watchpoint_info(watchpoint: &Watchpoint) -> BreakpointInfo {
+    BreakpointInfo {
+        idx: watchpoint.idx,
+        address: watchpoint.address,
+        locations: 1,
+        location: None,
+        commands: Vec::new(),
+        dprintf: None,
+        pending: None,
+        watch: Some(watchpoint.expression.clone()),
+    }
+}
+
+/// Number of the breakpoint a resume command stopped at.
+fn breakpoint_hit(output: &Output) -> Option<u32> {
+    match output {
+        Output::Stopped(StopEvent::Stopped {
+            reason: StopReason::Breakpoint(idx),
+            ..
+        }) => Some(*idx),
+        _ => None,
     }
 }
+
+/// Functions with debug information sorted by file and line, then the other function symbols.
+fn function_infos(target: &Target, regex: &Regex) -> Vec<FunctionInfo> {
+    let dwarf_info = &target.debug_info.dwarf_info;
+    // The index mostly has plain names, only the functions themselves the qualified ones.
+    let qualified = regex.as_str().contains("::");
+    let names: Vec<String> = dwarf_info
+        .function_names()
+        .into_iter()
+        .filter(|name| qualified || regex.is_match(name))
+        .collect();
+    let mut functions: Vec<FunctionInfo> = dwarf_info
+        .functions_with_names(&names)
+        .into_iter()
+        .filter(|function| {
+            let name = function.name();
+            regex.is_match(name) || !qualified && regex.is_match(name.rsplit("::").next().unwrap_or(name))
+        })
+        .filter_map(|function| {
+            let (low_pc, _) = *function.address_range.first()?;
+            let location = target.debug_info.get_location_at_addr(low_pc)?;
+            Some(FunctionInfo {
+                name: function.name().to_string(),
+                address: low_pc + target.base_address,
+                source: Some((location.file, location.line)),
+            })
+        })
+        .collect();
+    functions.sort_by(|a, b| (&a.source, a.address).cmp(&(&b.source, b.address)));
+    // Several DIEs can describe the same code, list it once.
+    functions.dedup_by_key(|function| function.address);
+
+    let mut symbols: Vec<FunctionInfo> = target
+        .debug_info
+        .symbols
+        .iter()
+        .filter(|symbol| regex.is_match(&symbol.name))
+        .filter(|symbol| dwarf_info.get_function_by_name(&symbol.name).is_none())
+        // Static C++ functions have no linkage name, only the symbol has the namespace.
+        .filter(|symbol| {
+            let function = dwarf_info.get_function_by_address(symbol.address);
+            function.and_then(|function| function.address_range.first()).map(|(low_pc, _)| *low_pc)
+                != Some(symbol.address)
+        })
+        .map(|symbol| FunctionInfo {
+            name: symbol.name.clone(),
+            address: symbol.address + target.base_address,
+            source: None,
+        })
+        .collect();
+    symbols.dedup_by(|a, b| a.name == b.name);
+    functions.extend(symbols);
+    functions
+}
+
+fn frame_info(target: &Target, frame: Frame, pretty: bool) -> FrameInfo {
+    FrameInfo {
+        level: frame.level,
+        address: frame.address,
+        inlined: frame.inlined,
+        arguments: variable_infos(target, target.frame_arguments(&frame), pretty),
+        locals: variable_infos(target, target.frame_locals(&frame), pretty),
+        location: frame.location,
+    }
+}
+
+fn variable_infos(target: &Target, values: Vec<VariableValue>, pretty: bool) -> Vec<VariableInfo> {
+    let dwarf_info = &target.debug_info.dwarf_info;
+    values
+        .into_iter()
+        .map(|v| VariableInfo {
+            type_name: dwarf_info.type_name(v.t),
+            display: target.format_value(&v, pretty),
+            name: v.name,
+            value: Some(v.value).filter(|_| !v.optimized_out),
+        })
+        .collect()
+}
+
+fn instruction_infos(
+    target: &Target,
+    instructions: Vec<Instruction>,
+    rip: usize,
+    with_source: bool,
+) -> Vec<InstructionInfo> {
+    let mut last_line = None;
+    instructions
+        .into_iter()
+        .map(|instruction| {
+            let mut source = Vec::new();
+            if with_source {
+                let line = target
+                    .location_at(instruction.address)
+                    .filter(|l| l.has_source())
+                    .map(|l| (l.file, l.line));
+                if line.is_some() && line != last_line {
+                    source = target.source_lines_at(instruction.address, 0);
+                    last_line = line;
+                }
+            }
+            InstructionInfo {
+                symbol: target.symbolize(instruction.address),
+                branch_target: instruction
+                    .branch_target
+                    .and_then(|addr| target.symbolize(addr)),
+                current: instruction.address == rip,
+                breakpoint: target.breakpoints.get(&instruction.address).map(|bp| bp.idx),
+                address: instruction.address,
+                bytes: instruction.bytes,
+                text: instruction.text,
+                source,
+            }
+        })
+        .collect()
+}
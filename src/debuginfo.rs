@@ -1,103 +1,219 @@
 use std::{
+    cell::RefCell,
+    collections::HashMap,
     fmt::Display,
     fs,
     io::{self, BufRead},
     rc::Rc,
 };
 
-use addr2line::{self, fallible_iterator::FallibleIterator};
-use gimli::{EndianReader, RunTimeEndian};
-use memmap2;
+use addr2line::LookupContinuation;
+use gimli::Reader as _;
+use object::Object;
 
+use crate::debugfile::{find_debug_file, map_file};
+use crate::demangle::demangle_name;
 use crate::gimliwrapper::GimliWrapper;
+use crate::splitdwarf::{self, Reader, SplitDwarf};
+use crate::symbols::SymbolTable;
 
+#[derive(Clone, PartialEq, Eq)]
 pub struct Location {
-    address: u64,
-    file: String,
-    line: u32,
+    pub address: u64,
+    pub file: String,
+    pub line: u32,
     pub function_name: String,
+    /// Offset into the function if the location only comes from the ELF symbol
+    /// table, `None` if there is DWARF line information for it.
+    pub symbol_offset: Option<usize>,
+}
+
+pub struct SourceLine {
+    pub line: u32,
+    pub text: String,
+    /// True for the line the address belongs to.
+    pub current: bool,
 }
 
 pub struct DebugInfo {
-    context: addr2line::Context<EndianReader<RunTimeEndian, Rc<[u8]>>>,
-    target: String,
+    context: addr2line::Context<Reader>,
+    split_dwarf: Rc<SplitDwarf>,
+    /// Locations already looked up. Stepping, backtraces and stop events ask for the
+    /// same addresses again and again.
+    location_cache: RefCell<HashMap<usize, Vec<Location>>>,
+    _target: String,
     pub dwarf_info: GimliWrapper,
+    pub symbols: SymbolTable,
+    /// The separate file the DWARF was read from, if the target has none itself.
+    pub debug_file: Option<String>,
 }
 
 impl Display for Location {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let file_name = self.file.split("/").last().unwrap();
-        write!(
-            f,
-            "{:#x} {}() in {}, line {}",
-            self.address, self.function_name, file_name, self.line
-        )
+        match self.symbol_offset {
+            Some(0) => write!(f, "{:#x} {}", self.address, self.function_name),
+            Some(offset) => write!(f, "{:#x} {}+{:#x}", self.address, self.function_name, offset),
+            None => {
+                let file_name = self.file.split("/").last().unwrap();
+                write!(
+                    f,
+                    "{:#x} {}() in {}, line {}",
+                    self.address, self.function_name, file_name, self.line
+                )
+            }
+        }
+    }
+}
+
+impl Location {
+    /// True if the location has a source file and line.
+    pub fn has_source(&self) -> bool {
+        self.symbol_offset.is_none()
     }
 }
 
 impl DebugInfo {
-    pub fn create(target: &str) -> DebugInfo {
-        let file = fs::File::open(target).unwrap();
-        let map = unsafe { memmap2::Mmap::map(&file).unwrap() };
-        let object = object::File::parse(&*map).unwrap();
-        let context = addr2line::Context::new(&object).unwrap();
-        let dwarf_info = GimliWrapper::create(target);
-        println!(
-            "Successfully loaded debug information for file {}.",
-            &target
-        );
-        let di = DebugInfo {
-            context: context,
-            target: String::from(target),
-            dwarf_info,
+    /// Load the DWARF and symbols of `target`. Stripped files get their DWARF from a
+    /// separate debug file, searched for in `debug_file_directories` among others.
+    pub fn create(target: &str, debug_file_directories: &[String]) -> Result<DebugInfo, String> {
+        let error = |e: &dyn Display| format!("{}: {}", target, e);
+        let file = fs::File::open(target).map_err(|e| error(&e))?;
+        let map = Rc::new(unsafe { memmap2::Mmap::map(&file) }.map_err(|e| error(&e))?);
+        let object = object::File::parse(&**map).map_err(|e| error(&e))?;
+
+        let debug_file = match object.section_by_name(".debug_info") {
+            Some(_) => None,
+            None => find_debug_file(target, &object, debug_file_directories),
         };
+        let debug_map = debug_file.as_deref().and_then(map_file).map(Rc::new);
+        let debug_object = debug_map.as_ref().and_then(|map| object::File::parse(&***map).ok());
 
-        return di;
+        let mut objects = vec![&object];
+        objects.extend(debug_object.as_ref());
+        let dwarf_object = objects.last().unwrap();
+        let dwarf_map = match &debug_object {
+            Some(_) => debug_map.as_ref().unwrap(),
+            None => &map,
+        };
+        let dwarf = splitdwarf::load_dwarf(dwarf_map, dwarf_object, false).map_err(|e| error(&e))?;
+        let context = addr2line::Context::from_dwarf(splitdwarf::share_dwarf(&dwarf)).map_err(|e| error(&e))?;
+        // The debug file keeps the full .symtab, the target its .dynsym and PLT.
+        let symbols = SymbolTable::create(&objects);
+        let split_dwarf = Rc::new(SplitDwarf::open(target));
+        let path = debug_file.as_deref().unwrap_or(target);
+        let dwarf_info = GimliWrapper::create(dwarf, dwarf_object, split_dwarf.clone(), path);
+        Ok(DebugInfo {
+            context,
+            split_dwarf,
+            location_cache: RefCell::new(HashMap::new()),
+            _target: String::from(target),
+            dwarf_info,
+            symbols,
+            debug_file,
+        })
     }
 
+    /// Source location of `addr`, or the ELF symbol containing it if there is no DWARF for it.
     pub fn get_location_at_addr(&self, addr: usize) -> Option<Location> {
-        let frames = self
-            .context
-            .find_frames(addr as u64)
-            .expect("Could not get frames.");
-        let frames = frames.iterator();
-
-        for frame in frames {
-            match frame {
-                Ok(f) => {
-                    let function_name = f.function.unwrap().name.escape_ascii().to_string();
-                    let location = f.location.unwrap();
-                    return Some(Location {
+        self.get_locations_at_addr(addr).into_iter().next()
+    }
+
+    /// The source locations of `addr`, innermost first: the location in each function
+    /// inlined there, then that of the call in the function it was inlined into. Code
+    /// without DWARF has the ELF symbol containing it.
+    pub fn get_locations_at_addr(&self, addr: usize) -> Vec<Location> {
+        if let Some(locations) = self.location_cache.borrow().get(&addr) {
+            return locations.clone();
+        }
+        let locations = self.find_locations(addr);
+        self.location_cache.borrow_mut().insert(addr, locations.clone());
+        locations
+    }
+
+    fn find_locations(&self, addr: usize) -> Vec<Location> {
+        let mut lookup = self.context.find_frames(addr as u64);
+        // Units built with -gsplit-dwarf need their split unit to find the function.
+        let frames = loop {
+            match lookup {
+                addr2line::LookupResult::Output(frames) => break frames,
+                addr2line::LookupResult::Load { load, continuation } => {
+                    let comp_dir = load.comp_dir.and_then(|dir| dir.to_string_lossy().ok().map(String::from));
+                    let path = load.path.and_then(|path| path.to_string_lossy().ok().map(String::from));
+                    let dwarf =
+                        self.split_dwarf
+                            .load(load.dwo_id, comp_dir.as_deref(), path.as_deref(), &load.parent);
+                    lookup = continuation.resume(dwarf);
+                }
+            }
+        };
+        let mut locations = Vec::new();
+        if let Ok(mut frames) = frames {
+            loop {
+                match frames.next() {
+                    Ok(Some(addr2line::Frame {
+                        function: Some(function),
+                        location: Some(location),
+                        ..
+                    })) => locations.push(Location {
                         address: addr as u64,
                         file: String::from(location.file.unwrap_or("")),
-                        function_name,
+                        function_name: demangle_name(&function.name.escape_ascii().to_string()),
                         line: location.line.unwrap_or(0),
-                    });
-                }
-
-                Err(e) => {
-                    println!("Error during get location iterator {}", e);
-                    return None;
+                        symbol_offset: None,
+                    }),
+                    Ok(Some(_)) => {}
+                    Ok(None) => break,
+                    Err(e) => {
+                        println!("Error during get location iterator {}", e);
+                        break;
+                    }
                 }
             }
         }
-        None
+        if !locations.is_empty() {
+            return locations;
+        }
+
+        match self.symbols.lookup(addr) {
+            Some((symbol, offset)) => vec![Location {
+                address: addr as u64,
+                file: String::new(),
+                line: 0,
+                function_name: symbol.name.clone(),
+                symbol_offset: Some(offset),
+            }],
+            None => Vec::new(),
+        }
     }
 
-    pub fn print_code_at_addr(&self, addr: usize, range: usize) {
-        let location = self
-            .get_location_at_addr(addr)
-            .expect("Could not get location for address.");
-        let source_file = fs::File::open(location.file).expect("Could not open source code.");
-        // let mut lines: Vec<String> = Vec::new();
-
-        for (idx, line) in io::BufReader::new(source_file).lines().enumerate() {
-            if let Ok(line) = line {
-                let diff = (idx + 1).abs_diff(location.line as usize);
-                if diff <= range {
-                    println!("{}\t{}", if diff == 0 { "⇒" } else { "  " }, line.as_str());
-                }
+    /// Source lines around the line belonging to `addr`, at most `range` lines before and after.
+    pub fn get_source_lines(&self, addr: usize, range: usize) -> Vec<SourceLine> {
+        match self.get_location_at_addr(addr) {
+            Some(location) => source_lines(&location, range),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Source lines around the line of `location`, at most `range` lines before and after.
+pub fn source_lines(location: &Location, range: usize) -> Vec<SourceLine> {
+    let source_file = match fs::File::open(&location.file) {
+        Ok(source_file) => source_file,
+        Err(_) => return Vec::new(),
+    };
+
+    let mut lines = Vec::new();
+    for (idx, line) in io::BufReader::new(source_file).lines().enumerate() {
+        if let Ok(line) = line {
+            let diff = (idx + 1).abs_diff(location.line as usize);
+            if diff <= range {
+                lines.push(SourceLine {
+                    line: (idx + 1) as u32,
+                    text: line,
+                    current: diff == 0,
+                });
             }
         }
     }
+    lines
 }
@@ -0,0 +1,112 @@
+use cpp_demangle::DemangleOptions;
+
+/// The readable name of an Itanium C++ or a Rust legacy or v0 mangled symbol, qualified but
+/// without the parameters of C++ functions and the hash of Rust ones. `_ZN2ns5Class6methodEv`
+/// becomes `ns::Class::method`. `None` if `name` isn't mangled.
+pub fn demangle(name: &str) -> Option<String> {
+    // Legacy Rust symbols are valid C++ ones, with a `17h...E` hash as the last part.
+    if let Ok(symbol) = rustc_demangle::try_demangle(name) {
+        return Some(inherent_impl_path(&format!("{:#}", symbol)));
+    }
+    if !name.starts_with("_Z") {
+        return None;
+    }
+    let symbol = cpp_demangle::Symbol::new(name).ok()?;
+    let demangled = symbol
+        .demangle(&DemangleOptions::new().no_params().no_return_type())
+        .ok()?;
+    // Without the parameters the suffix of clones like `_Z5checki.cold` is dropped too.
+    match name.split_once('.') {
+        Some((_, suffix)) => Some(format!("{} [clone .{}]", demangled, suffix)),
+        None => Some(demangled),
+    }
+}
+
+/// `Type::method` for the `<Type>::method` v0 symbols have for methods of inherent impls,
+/// like legacy ones. Trait impls keep their `<Type as Trait>::method`.
+fn inherent_impl_path(name: &str) -> String {
+    let inner = match name.strip_prefix('<') {
+        Some(inner) => inner,
+        None => return String::from(name),
+    };
+    let mut depth = 1;
+    for (idx, c) in inner.char_indices() {
+        match c {
+            '<' => depth += 1,
+            '>' => depth -= 1,
+            _ => {}
+        }
+        if depth == 0 {
+            let (path, rest) = (&inner[..idx], &inner[idx + 1..]);
+            if path.contains(" as ") || !rest.starts_with("::") {
+                break;
+            }
+            return format!("{}{}", path, rest);
+        }
+    }
+    String::from(name)
+}
+
+/// `name` demangled, or as it is if it isn't mangled.
+pub fn demangle_name(name: &str) -> String {
+    demangle(name).unwrap_or_else(|| String::from(name))
+}
+
+/// True if `query` names the function or variable with the qualified name `name`: all of
+/// it, or its last parts after a `::`. `method`, `Class::method` and `ns::Class::method`
+/// all name `ns::Class::method`, `ass::method` doesn't. A leading `::` asks for a name in
+/// the global scope, `::check` doesn't name `ns::check`.
+pub fn name_matches(name: &str, query: &str) -> bool {
+    if let Some(query) = query.strip_prefix("::") {
+        return name == query;
+    }
+    name == query || name.strip_suffix(query).is_some_and(|scope| scope.ends_with("::"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn demangle_cpp() {
+        assert_eq!(demangle("_ZN2ns5Class6methodEv").unwrap(), "ns::Class::method");
+        assert_eq!(demangle("_Z5checki").unwrap(), "check");
+        assert_eq!(demangle("_Z5checki.cold").unwrap(), "check [clone .cold]");
+        assert_eq!(demangle("main"), None);
+        assert_eq!(demangle("_Zinvalid"), None);
+        assert_eq!(demangle_name("main"), "main");
+    }
+
+    #[test]
+    fn demangle_rust() {
+        assert_eq!(demangle("_ZN1r5Point4norm17h0dad5c79781d3156E").unwrap(), "r::Point::norm");
+        assert_eq!(demangle("_RNvMCs6GmmlP4bgsG_1rNtB2_5Point4norm").unwrap(), "r::Point::norm");
+        assert_eq!(
+            demangle("_RNvXs0_NtCsgY6Mt91CT9J_14rustc_demangle2v0NtB5_5IdentNtNtCsgEmfK2I1SDS_4core3fmt7Display3fmt")
+                .unwrap(),
+            "<rustc_demangle::v0::Ident as core::fmt::Display>::fmt"
+        );
+    }
+
+    #[test]
+    fn inherent_impl_paths() {
+        assert_eq!(inherent_impl_path("<r::Point>::norm"), "r::Point::norm");
+        assert_eq!(inherent_impl_path("<alloc::vec::Vec<u8>>::len"), "alloc::vec::Vec<u8>::len");
+        assert_eq!(inherent_impl_path("<r::Point as core::fmt::Debug>::fmt"), "<r::Point as core::fmt::Debug>::fmt");
+        assert_eq!(inherent_impl_path("<[u8]>"), "<[u8]>");
+        assert_eq!(inherent_impl_path("r::main"), "r::main");
+    }
+
+    #[test]
+    fn names_match() {
+        assert!(name_matches("ns::Class::method", "method"));
+        assert!(name_matches("ns::Class::method", "Class::method"));
+        assert!(name_matches("ns::Class::method", "ns::Class::method"));
+        assert!(!name_matches("ns::Class::method", "ass::method"));
+        assert!(!name_matches("ns::Class::method", "other"));
+        assert!(name_matches("check", "::check"));
+        assert!(!name_matches("ns::check", "::check"));
+        assert!(name_matches("check [clone .cold]", "check [clone .cold]"));
+        assert!(!name_matches("check [clone .cold]", "check"));
+    }
+}
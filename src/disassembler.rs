@@ -0,0 +1,59 @@
+use iced_x86::{Decoder, DecoderError, DecoderOptions, FlowControl, Formatter, IntelFormatter, OpKind};
+
+/// Longest possible x86-64 instruction in bytes.
+pub const MAX_INSTRUCTION_LENGTH: usize = 15;
+
+/// A decoded machine instruction.
+pub struct Instruction {
+    pub address: usize,
+    pub bytes: Vec<u8>,
+    /// Intel syntax, e.g. `mov rbp, rsp`.
+    pub text: String,
+    /// Destination of a direct call or jump.
+    pub branch_target: Option<usize>,
+}
+
+/// Decode x86-64 machine code located at `address`.
+/// Decoding stops at an instruction that is cut off at the end of `bytes`.
+pub fn disassemble(bytes: &[u8], address: usize) -> Vec<Instruction> {
+    let mut decoder = Decoder::with_ip(64, bytes, address as u64, DecoderOptions::NONE);
+    let mut formatter = IntelFormatter::new();
+    let options = formatter.options_mut();
+    options.set_hex_prefix("0x");
+    options.set_hex_suffix("");
+    options.set_uppercase_hex(false);
+    options.set_first_operand_char_index(8);
+    options.set_space_after_operand_separator(true);
+    options.set_branch_leading_zeros(false);
+
+    let mut instructions = Vec::new();
+    let mut instruction = iced_x86::Instruction::default();
+    while decoder.can_decode() {
+        let offset = decoder.position();
+        decoder.decode_out(&mut instruction);
+        if decoder.last_error() == DecoderError::NoMoreBytes {
+            break;
+        }
+
+        let mut text = String::new();
+        if instruction.is_invalid() {
+            text.push_str("(bad)");
+        } else {
+            formatter.format(&instruction, &mut text);
+        }
+        let branch_target = match (instruction.flow_control(), instruction.op0_kind()) {
+            (
+                FlowControl::Call | FlowControl::UnconditionalBranch | FlowControl::ConditionalBranch,
+                OpKind::NearBranch16 | OpKind::NearBranch32 | OpKind::NearBranch64,
+            ) => Some(instruction.near_branch_target() as usize),
+            _ => None,
+        };
+        instructions.push(Instruction {
+            address: instruction.ip() as usize,
+            bytes: bytes[offset..offset + instruction.len()].to_vec(),
+            text,
+            branch_target,
+        });
+    }
+    instructions
+}
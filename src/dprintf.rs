@@ -0,0 +1,440 @@
+use std::iter::Peekable;
+use std::str::Chars;
+
+/// Largest width or precision of a conversion. Larger ones would only make huge strings.
+const MAX_WIDTH: usize = 4096;
+
+/// A value for a conversion of a printf format.
+pub enum PrintfArg {
+    Integer(u64),
+    Float(f64),
+    String(String),
+}
+
+/// A parsed `%` conversion, e.g. `%-08.3lx`.
+struct Conversion {
+    left: bool,
+    plus: bool,
+    space: bool,
+    zero: bool,
+    alternate: bool,
+    width: usize,
+    precision: Option<usize>,
+    /// Size in bytes given by the length modifier, 4 without one.
+    size: u32,
+    kind: char,
+}
+
+enum Piece {
+    Text(String),
+    Conversion(Conversion),
+}
+
+/// The conversion characters of `format` that take an argument, in order.
+pub fn conversions(format: &str) -> Result<Vec<char>, String> {
+    Ok(parse(format)?
+        .iter()
+        .filter_map(|piece| match piece {
+            Piece::Conversion(c) => Some(c.kind),
+            Piece::Text(_) => None,
+        })
+        .collect())
+}
+
+/// Check that a dprintf gives one argument per conversion of `format`.
+pub fn check_arguments(format: &str, count: usize) -> Result<(), String> {
+    let conversions = conversions(format)?;
+    if conversions.len() != count {
+        return Err(format!(
+            "Wrong number of arguments: the format takes {}, got {}.",
+            conversions.len(),
+            count
+        ));
+    }
+    Ok(())
+}
+
+/// Read the NUL terminated string at `addr` for `%s`, at most `max_length` bytes.
+/// `read_word` returns the word of memory at an address.
+pub fn read_string<E>(
+    read_word: impl Fn(usize) -> Result<Vec<u8>, E>,
+    addr: usize,
+    max_length: usize,
+) -> Result<String, E> {
+    let mut bytes = Vec::new();
+    while bytes.len() < max_length {
+        let word = read_word(addr + bytes.len())?;
+        match word.iter().position(|b| *b == 0) {
+            Some(end) => {
+                bytes.extend(&word[..end]);
+                break;
+            }
+            None => bytes.extend(word),
+        }
+    }
+    bytes.truncate(max_length);
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Format like C's printf. `args` must match `conversions(format)`.
+pub fn format(format: &str, args: &[PrintfArg]) -> Result<String, String> {
+    let mut args = args.iter();
+    let mut result = String::new();
+    for piece in parse(format)? {
+        let conversion = match piece {
+            Piece::Text(text) => {
+                result.push_str(&text);
+                continue;
+            }
+            Piece::Conversion(conversion) => conversion,
+        };
+        let arg = args.next().ok_or("Too few arguments for format.")?;
+        let (sign, body) = match (conversion.kind, arg) {
+            ('s', PrintfArg::String(s)) => match conversion.precision {
+                Some(precision) => (String::new(), s.chars().take(precision).collect()),
+                None => (String::new(), s.clone()),
+            },
+            ('c', PrintfArg::Integer(v)) => (String::new(), String::from(*v as u8 as char)),
+            ('p', PrintfArg::Integer(v)) => (String::new(), format!("{:#x}", v)),
+            ('d' | 'i', PrintfArg::Integer(v)) => {
+                let v = sign_extend(*v, conversion.size);
+                let sign = if v < 0 {
+                    "-"
+                } else if conversion.plus {
+                    "+"
+                } else if conversion.space {
+                    " "
+                } else {
+                    ""
+                };
+                (String::from(sign), integer_digits(v.unsigned_abs(), &conversion))
+            }
+            ('u' | 'x' | 'X' | 'o', PrintfArg::Integer(v)) => {
+                let v = truncate(*v, conversion.size);
+                let prefix = match conversion.kind {
+                    'x' if conversion.alternate && v != 0 => "0x",
+                    'X' if conversion.alternate && v != 0 => "0X",
+                    'o' if conversion.alternate => "0",
+                    _ => "",
+                };
+                (String::from(prefix), integer_digits(v, &conversion))
+            }
+            ('f' | 'F' | 'e' | 'E' | 'g' | 'G', PrintfArg::Float(v)) => {
+                let sign = if v.is_sign_negative() {
+                    "-"
+                } else if conversion.plus {
+                    "+"
+                } else if conversion.space {
+                    " "
+                } else {
+                    ""
+                };
+                (String::from(sign), float_digits(v.abs(), &conversion))
+            }
+            _ => return Err(format!("Argument does not match %{}.", conversion.kind)),
+        };
+        result.push_str(&pad(sign, body, &conversion));
+    }
+    Ok(result)
+}
+
+fn parse(format: &str) -> Result<Vec<Piece>, String> {
+    let mut pieces = Vec::new();
+    let mut text = String::new();
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            text.push(c);
+            continue;
+        }
+        if chars.peek() == Some(&'%') {
+            chars.next();
+            text.push('%');
+            continue;
+        }
+
+        let mut conversion = Conversion {
+            left: false,
+            plus: false,
+            space: false,
+            zero: false,
+            alternate: false,
+            width: 0,
+            precision: None,
+            size: 4,
+            kind: ' ',
+        };
+        while let Some(flag) = chars.next_if(|c| "-+ 0#".contains(*c)) {
+            match flag {
+                '-' => conversion.left = true,
+                '+' => conversion.plus = true,
+                ' ' => conversion.space = true,
+                '0' => conversion.zero = true,
+                _ => conversion.alternate = true,
+            }
+        }
+        conversion.width = parse_width(&mut chars, "Width")?;
+        if chars.next_if_eq(&'.').is_some() {
+            conversion.precision = Some(parse_width(&mut chars, "Precision")?);
+        }
+        while let Some(length) = chars.next_if(|c| "hlLjzt".contains(*c)) {
+            conversion.size = match (length, conversion.size) {
+                ('h', 2) => 1,
+                ('h', _) => 2,
+                _ => 8,
+            };
+        }
+        conversion.kind = match chars.next() {
+            Some(kind) if "diuxXocspfFeEgG".contains(kind) => kind,
+            Some(kind) => return Err(format!("Unsupported conversion %{}.", kind.escape_debug())),
+            None => return Err(String::from("Incomplete conversion at the end of the format.")),
+        };
+
+        if !text.is_empty() {
+            pieces.push(Piece::Text(std::mem::take(&mut text)));
+        }
+        pieces.push(Piece::Conversion(conversion));
+    }
+    if !text.is_empty() {
+        pieces.push(Piece::Text(text));
+    }
+    Ok(pieces)
+}
+
+/// Read the digits of a width or precision, 0 if there are none.
+fn parse_width(chars: &mut Peekable<Chars>, what: &str) -> Result<usize, String> {
+    let mut width: usize = 0;
+    while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+        width = width
+            .checked_mul(10)
+            .and_then(|width| width.checked_add(digit.to_digit(10).unwrap() as usize))
+            .filter(|width| *width <= MAX_WIDTH)
+            .ok_or_else(|| format!("{} larger than {}.", what, MAX_WIDTH))?;
+    }
+    Ok(width)
+}
+
+fn sign_extend(value: u64, size: u32) -> i64 {
+    let shift = 64 - 8 * size;
+    ((value << shift) as i64) >> shift
+}
+
+fn truncate(value: u64, size: u32) -> u64 {
+    if size >= 8 {
+        value
+    } else {
+        value & ((1 << (8 * size)) - 1)
+    }
+}
+
+fn integer_digits(value: u64, conversion: &Conversion) -> String {
+    let digits = match conversion.kind {
+        'x' => format!("{:x}", value),
+        'X' => format!("{:X}", value),
+        'o' => format!("{:o}", value),
+        _ => format!("{}", value),
+    };
+    match conversion.precision {
+        Some(0) if value == 0 => String::new(),
+        Some(precision) => format!("{:0>width$}", digits, width = precision),
+        None => digits,
+    }
+}
+
+fn float_digits(value: f64, conversion: &Conversion) -> String {
+    let precision = conversion.precision.unwrap_or(6);
+    if !value.is_finite() {
+        let text = if value.is_nan() { "nan" } else { "inf" };
+        return if conversion.kind.is_ascii_uppercase() {
+            text.to_uppercase()
+        } else {
+            String::from(text)
+        };
+    }
+    let text = match conversion.kind.to_ascii_lowercase() {
+        'f' => format!("{:.*}", precision, value),
+        'e' => exponent_notation(value, precision),
+        _ => {
+            // %g: the shorter of %e and %f with `precision` significant digits.
+            let precision = precision.max(1);
+            let exponent = if value == 0.0 {
+                0
+            } else {
+                value.abs().log10().floor() as i32
+            };
+            let text = if exponent < -4 || exponent >= precision as i32 {
+                exponent_notation(value, precision - 1)
+            } else {
+                format!("{:.*}", (precision as i32 - 1 - exponent).max(0) as usize, value)
+            };
+            if conversion.alternate {
+                text
+            } else {
+                strip_zeros(&text)
+            }
+        }
+    };
+    if conversion.kind.is_ascii_uppercase() {
+        text.to_uppercase()
+    } else {
+        text
+    }
+}
+
+/// `1.500000e+01` like C, Rust would write `1.500000e1`.
+fn exponent_notation(value: f64, precision: usize) -> String {
+    let text = format!("{:.*e}", precision, value);
+    let (mantissa, exponent) = text.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    let sign = if exponent < 0 { '-' } else { '+' };
+    format!("{}e{}{:02}", mantissa, sign, exponent.abs())
+}
+
+/// Remove trailing zeros of the fraction, `%g` style.
+fn strip_zeros(text: &str) -> String {
+    let (mantissa, exponent) = match text.find('e') {
+        Some(idx) => text.split_at(idx),
+        None => (text, ""),
+    };
+    let mantissa = if mantissa.contains('.') {
+        mantissa.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        mantissa
+    };
+    format!("{}{}", mantissa, exponent)
+}
+
+fn pad(sign: String, body: String, conversion: &Conversion) -> String {
+    let len = sign.chars().count() + body.chars().count();
+    let fill = conversion.width.saturating_sub(len);
+    let numeric = conversion.kind != 's' && conversion.kind != 'c';
+    if conversion.left {
+        format!("{}{}{}", sign, body, " ".repeat(fill))
+    } else if conversion.zero && numeric {
+        format!("{}{}{}", sign, "0".repeat(fill), body)
+    } else {
+        format!("{}{}{}", " ".repeat(fill), sign, body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn int(format_str: &str, value: i64) -> String {
+        format(format_str, &[PrintfArg::Integer(value as u64)]).unwrap()
+    }
+
+    #[test]
+    fn integer_flags_and_width() {
+        assert_eq!(int("%d", -5), "-5");
+        assert_eq!(int("%5d|", 42), "   42|");
+        assert_eq!(int("%-5d|", 42), "42   |");
+        assert_eq!(int("%05d", -42), "-0042");
+        assert_eq!(int("%+d", 7), "+7");
+        assert_eq!(int("% d", 7), " 7");
+        assert_eq!(int("%.3d", 7), "007");
+        assert_eq!(int("%.0d|", 0), "|");
+        assert_eq!(int("%#x %%", 255), "0xff %");
+        assert_eq!(int("%#X", 0), "0");
+        assert_eq!(int("%#o", 8), "010");
+        assert_eq!(int("%-08.3lx|", 0x1f), "01f     |");
+    }
+
+    #[test]
+    fn length_modifiers() {
+        assert_eq!(int("%d", 0xffff_ffff), "-1");
+        assert_eq!(int("%ld", 0xffff_ffff), "4294967295");
+        assert_eq!(int("%u", -1), "4294967295");
+        assert_eq!(int("%lu", -1), "18446744073709551615");
+        assert_eq!(int("%hd", 0x1_8000), "-32768");
+        assert_eq!(int("%hhx", 0x1234), "34");
+    }
+
+    #[test]
+    fn other_conversions() {
+        assert_eq!(int("%c", 'A' as i64), "A");
+        assert_eq!(int("%p", 0x1000), "0x1000");
+        let float = |f: &str, v: f64| format(f, &[PrintfArg::Float(v)]).unwrap();
+        assert_eq!(float("%f", 1.5), "1.500000");
+        assert_eq!(float("%8.2f|", -1.005), "   -1.00|");
+        assert_eq!(float("%e", 15.0), "1.500000e+01");
+        assert_eq!(float("%g", 0.0001), "0.0001");
+        assert_eq!(float("%g", 1e-5), "1e-05");
+        assert_eq!(float("%g", 100000.0), "100000");
+        assert_eq!(float("%G", f64::INFINITY), "INF");
+        let string = |f: &str| format(f, &[PrintfArg::String(String::from("hello"))]).unwrap();
+        assert_eq!(string("[%s]"), "[hello]");
+        assert_eq!(string("[%7s]"), "[  hello]");
+        assert_eq!(string("[%-7s]"), "[hello  ]");
+        assert_eq!(string("[%.2s]"), "[he]");
+        assert_eq!(string("[%07s]"), "[  hello]");
+    }
+
+    #[test]
+    fn conversions_of_format() {
+        assert_eq!(conversions("x=%d %% %-5.2s %lu\n").unwrap(), vec!['d', 's', 'u']);
+        assert!(conversions("no conversions").unwrap().is_empty());
+    }
+
+    #[test]
+    fn format_errors() {
+        assert_eq!(conversions("%q").err().unwrap(), "Unsupported conversion %q.");
+        assert_eq!(
+            conversions("abc %-5").err().unwrap(),
+            "Incomplete conversion at the end of the format."
+        );
+        assert_eq!(
+            conversions("%99999999999999999999d").err().unwrap(),
+            "Width larger than 4096."
+        );
+        assert_eq!(conversions("%.4097f").err().unwrap(), "Precision larger than 4096.");
+        assert!(conversions("%4096.4096f").is_ok());
+        assert_eq!(
+            format("%d %d", &[PrintfArg::Integer(1)]).err().unwrap(),
+            "Too few arguments for format."
+        );
+        assert_eq!(
+            format("%d", &[PrintfArg::String(String::new())]).err().unwrap(),
+            "Argument does not match %d."
+        );
+    }
+
+    #[test]
+    fn argument_count() {
+        assert!(check_arguments("%d and %s", 2).is_ok());
+        assert!(check_arguments("100%%", 0).is_ok());
+        assert_eq!(
+            check_arguments("%d and %s", 1).err().unwrap(),
+            "Wrong number of arguments: the format takes 2, got 1."
+        );
+        assert_eq!(
+            check_arguments("%d", 3).err().unwrap(),
+            "Wrong number of arguments: the format takes 1, got 3."
+        );
+    }
+
+    /// Memory holding `bytes` at 0x1000, read a word at a time.
+    fn memory(bytes: &[u8]) -> impl Fn(usize) -> Result<Vec<u8>, String> + '_ {
+        move |addr| {
+            let offset = addr.checked_sub(0x1000).filter(|o| o + 8 <= bytes.len());
+            match offset {
+                Some(offset) => Ok(bytes[offset..offset + 8].to_vec()),
+                None => Err(format!("cannot read {:#x}", addr)),
+            }
+        }
+    }
+
+    #[test]
+    fn string_from_memory() {
+        let bytes = b"hello, world!\0\0\0trailing";
+        assert_eq!(read_string(memory(bytes), 0x1000, 200).unwrap(), "hello, world!");
+        assert_eq!(read_string(memory(bytes), 0x1007, 200).unwrap(), "world!");
+        assert_eq!(read_string(memory(bytes), 0x1000, 5).unwrap(), "hello");
+        assert_eq!(read_string(memory(b"\0abcdefg"), 0x1000, 200).unwrap(), "");
+        assert_eq!(
+            read_string(memory(b"no terminator"), 0x1000, 200).err().unwrap(),
+            "cannot read 0x1008"
+        );
+    }
+}
@@ -1,13 +1,20 @@
-use std::{
-    borrow,
-    collections::HashMap,
-    fs,
-};
+use std::cell::{Cell, OnceCell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use gimli::{DebuggingInformationEntry, Dwarf, EndianSlice, RunTimeEndian};
-use memmap2;
+use gimli::{
+    DebugTypeSignature, DebuggingInformationEntry, Dwarf, EntriesTreeNode, FileEntry, LineProgramHeader,
+    Reader as _, Section, Unit, UnitOffset, UnitSectionOffset, UnitType,
+};
 use object::{Object, ObjectSection};
 
+use crate::demangle::{self, name_matches};
+use crate::nameindex::{self, NameIndex, NameKind};
+use crate::splitdwarf::{self, Reader, SplitDwarf};
+use crate::symbolcache::{self, SymbolIndex};
+use crate::util::Progress;
+
 
 #[derive(Debug, Clone)]
 pub enum Type {
@@ -19,6 +26,8 @@ pub enum Type {
         ref_addr: usize,
     },
     Pointer {
+        /// Rust names its references and boxes, e.g. `&str` or `alloc::boxed::Box<i32, ...>`.
+        name: Option<String>,
         byte_size: u64,
         to: usize,
         ref_addr: usize,
@@ -28,20 +37,127 @@ pub enum Type {
         to: usize,
         ref_addr: usize,
     },
+    /// A struct, class or union, which includes the tuples, enums and fat pointers of Rust.
+    Struct {
+        /// Qualified with the namespaces and types it is declared in, e.g.
+        /// `alloc::vec::Vec<i32, alloc::alloc::Global>`.
+        name: String,
+        byte_size: u64,
+        members: Vec<Member>,
+        /// Names and types of the parameters of a generic type, like the `T` of `Vec<T>`.
+        template_parameters: Vec<(String, usize)>,
+        /// The variants of a Rust enum.
+        variant_part: Option<VariantPart>,
+        ref_addr: usize,
+    },
+    Array {
+        to: usize,
+        /// Number of elements, of all dimensions together. `None` for flexible array members.
+        count: Option<u64>,
+        ref_addr: usize,
+    },
+    Typedef {
+        name: String,
+        to: usize,
+        ref_addr: usize,
+    },
+    Enumeration {
+        name: String,
+        byte_size: u64,
+        /// Names and values of the enumerators.
+        enumerators: Vec<(String, i64)>,
+        ref_addr: usize,
+    },
+}
+
+/// A data member of a struct, or a base class with an empty name.
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub name: String,
+    pub t: usize,
+    /// Offset in bytes from the start of the struct.
+    pub offset: u64,
+}
+
+/// A `DW_TAG_variant_part`, the variants of a Rust enum.
+#[derive(Debug, Clone)]
+pub struct VariantPart {
+    /// The member the discriminant is stored in, `None` if there is a single variant.
+    pub discriminant: Option<Member>,
+    pub variants: Vec<Variant>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Variant {
+    /// Value of the discriminant selecting this variant, `None` for the one selected by
+    /// all other values, like `Some` of an `Option<&T>` whose discriminant is the pointer.
+    pub discr_value: Option<u64>,
+    /// Member with the fields of the variant, named after it, e.g. `Some`.
+    pub member: Member,
 }
 
 #[derive(Debug)]
 pub struct FormalParameter {
     pub name: String,
     pub t: usize,
-    pub fbreg_offset: i64,
+    /// `None` if it has no location the debugger understands, like an optimized out one.
+    pub location: Option<VariableLocation>,
 }
 
 #[derive(Debug)]
 pub struct Variable {
     pub name: String,
     pub t: usize,
-    pub fbreg_offset: i64,
+    /// `None` if it has no location the debugger understands, like an optimized out one.
+    pub location: Option<VariableLocation>,
+}
+
+/// Where a local variable or parameter is kept, from its `DW_AT_location`.
+#[derive(Debug)]
+pub enum VariableLocation {
+    /// `DW_OP_fbreg`, an offset from the frame base of the function.
+    FrameOffset(i64),
+    /// A location list: the module relative start and end (exclusive) pc of each entry
+    /// and its frame base offset, `None` if it isn't a `DW_OP_fbreg`.
+    List(Vec<(usize, usize, Option<i64>)>),
+}
+
+impl VariableLocation {
+    /// The frame base offset of the variable at the module relative `pc`, `None` if it
+    /// isn't on the stack there.
+    pub fn frame_offset(&self, pc: usize) -> Option<i64> {
+        match self {
+            VariableLocation::FrameOffset(offset) => Some(*offset),
+            VariableLocation::List(entries) => entries
+                .iter()
+                .find(|(start, end, _)| (*start..*end).contains(&pc))
+                .and_then(|(_, _, offset)| *offset),
+        }
+    }
+}
+
+/// A variable with a static address: a global, a file `static` or a `static` local.
+#[derive(Debug)]
+pub struct GlobalVariable {
+    pub name: String,
+    pub t: usize,
+    /// Module relative address.
+    pub address: usize,
+    /// Name of the compilation unit defining it, e.g. `src/main.c`.
+    pub file: String,
+    /// The function a `static` local is declared in.
+    pub function: Option<String>,
+    /// True if other compilation units can refer to it, false for `static` ones.
+    pub external: bool,
+}
+
+/// A `DW_TAG_lexical_block`, the scope of the variables declared in it.
+#[derive(Debug)]
+pub struct Block {
+    /// Low and high pc (inclusive) of each range of the block.
+    pub address_range: Vec<(usize, usize)>,
+    pub variables: Vec<Variable>,
+    pub blocks: Vec<Block>,
 }
 
 #[derive(Debug)]
@@ -49,35 +165,243 @@ pub struct Function {
     name: String,
     pub t: usize,
     pub formal_parameters: Vec<FormalParameter>,
+    /// Variables of the outermost scope of the function.
     pub local_variables: Vec<Variable>,
+    /// The nested scopes with the other local variables.
+    pub blocks: Vec<Block>,
+    /// Low and high pc (inclusive) of each part of the function, the entry first.
+    /// Empty for declarations.
     pub address_range: Vec<(usize, usize)>,
+    /// Source language of the compilation unit, e.g. `c`.
+    pub language: Option<String>,
+    /// What the frame offsets of its variables are relative to. Inlined instances use the
+    /// frame base of the function they are inlined into.
+    pub frame_base: FrameBase,
+}
+
+/// The `DW_AT_frame_base` of a function.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameBase {
+    /// `DW_OP_call_frame_cfa`, what GCC uses.
+    Cfa,
+    /// The value of a register, rbp or rsp with LLVM.
+    Register(gimli::Register),
+}
+
+/// A `DW_TAG_inlined_subroutine`, the code of a function inlined at a call site.
+#[derive(Debug)]
+pub struct InlinedCall {
+    /// The inlined instance, with the name, parameters and variables of the inlined function.
+    pub function: Function,
+    /// The first instruction of the inlined code, where a breakpoint on the function goes.
+    pub entry_pc: usize,
+}
+
+/// A row of the line number program, see DWARF v4 section 6.2.
+#[derive(Debug, Clone)]
+pub struct LineRow {
+    pub address: usize,
+    pub file: String,
+    pub line: u64,
+    pub is_stmt: bool,
+}
+
+impl Block {
+    fn contains(&self, pc: usize) -> bool {
+        self.address_range
+            .iter()
+            .any(|(low_pc, high_pc)| (*low_pc..=*high_pc).contains(&pc))
+    }
+}
+
+impl Function {
+    /// The qualified name, like `ns::Class::method`, for languages that have one.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The local variables in scope at `pc`, innermost scope first, without those
+    /// shadowed by a variable of the same name in an inner scope.
+    pub fn locals_at(&self, pc: usize) -> Vec<&Variable> {
+        let mut scopes = vec![&self.local_variables];
+        let mut blocks = &self.blocks;
+        while let Some(block) = blocks.iter().find(|block| block.contains(pc)) {
+            scopes.push(&block.variables);
+            blocks = &block.blocks;
+        }
+
+        let mut locals: Vec<&Variable> = Vec::new();
+        for variable in scopes.into_iter().rev().flatten() {
+            if !locals.iter().any(|local| local.name == variable.name) {
+                locals.push(variable);
+            }
+        }
+        locals
+    }
 }
 
 impl Type {
+    #[allow(dead_code)]
     pub fn void() -> Type {
         Type::Pointer {
+            name: None,
             byte_size: 0,
             ref_addr: 0,
             to: 0,
         }
     }
+
+    /// The key of the type, see `SectionBase`.
+    fn ref_addr(&self) -> usize {
+        match self {
+            Type::Base { ref_addr, .. }
+            | Type::Pointer { ref_addr, .. }
+            | Type::Const { ref_addr, .. }
+            | Type::Struct { ref_addr, .. }
+            | Type::Array { ref_addr, .. }
+            | Type::Typedef { ref_addr, .. }
+            | Type::Enumeration { ref_addr, .. } => *ref_addr,
+        }
+    }
+}
+
+/// The types of a file by their key, all printing a value needs of its debug information.
+pub trait TypeLookup {
+    fn get_type(&self, t: usize) -> Option<&Type>;
+
+    fn get_type_byte_size(&self, t: usize) -> Option<u64> {
+        match self.get_type(t)? {
+            Type::Base { byte_size, .. }
+            | Type::Pointer { byte_size, .. }
+            | Type::Struct { byte_size, .. }
+            | Type::Enumeration { byte_size, .. } => Some(*byte_size),
+            // const types rarely have a size of their own
+            Type::Const { byte_size: 0, to, .. } | Type::Typedef { to, .. } => self.get_type_byte_size(*to),
+            Type::Const { byte_size, .. } => Some(*byte_size),
+            Type::Array { to, count, .. } => Some(count.unwrap_or(0) * self.get_type_byte_size(*to)?),
+        }
+    }
+
+    /// Render the C name of a type, e.g. `const char*`, or the qualified name of a struct.
+    fn type_name(&self, t: usize) -> String {
+        match self.get_type(t) {
+            Some(Type::Base { name, .. }) => name.clone(),
+            Some(Type::Pointer { name: Some(name), .. }) => name.clone(),
+            Some(Type::Pointer { to, .. }) => format!("{}*", self.type_name(*to)),
+            Some(Type::Const { to, .. }) => format!("const {}", self.type_name(*to)),
+            Some(Type::Struct { name, .. }) if name.is_empty() => String::from("{...}"),
+            Some(Type::Struct { name, .. }) | Some(Type::Typedef { name, .. }) | Some(Type::Enumeration { name, .. }) => {
+                name.clone()
+            }
+            Some(Type::Array { to, count, .. }) => {
+                format!("{}[{}]", self.type_name(*to), count.map(|c| c.to_string()).unwrap_or_default())
+            }
+            None => String::from("void"),
+        }
+    }
+
+    /// The type `t` is an alias of, itself if it isn't a typedef or const type.
+    fn resolve_type(&self, t: usize) -> usize {
+        match self.get_type(t) {
+            Some(Type::Typedef { to, .. }) | Some(Type::Const { to, .. }) => self.resolve_type(*to),
+            _ => t,
+        }
+    }
+
+    /// Format a raw value according to its type (signed, unsigned, float, enum or pointer).
+    fn format_value(&self, t: usize, value: u64) -> String {
+        match self.get_type(t) {
+            Some(Type::Base { is_float: true, byte_size: 4, .. }) => {
+                format!("{}", f32::from_bits(value as u32))
+            }
+            Some(Type::Base { is_float: true, byte_size: 8, .. }) => {
+                format!("{}", f64::from_bits(value))
+            }
+            Some(Type::Base { is_signed: true, byte_size, .. }) => {
+                let shift = 64 - 8 * (*byte_size).clamp(1, 8) as u32;
+                format!("{}", ((value << shift) as i64) >> shift)
+            }
+            Some(Type::Base { .. }) => format!("{}", value),
+            Some(Type::Const { to, .. }) | Some(Type::Typedef { to, .. }) => self.format_value(*to, value),
+            Some(Type::Enumeration { byte_size, enumerators, .. }) => {
+                let mask = 1_u64.checked_shl(8 * *byte_size as u32).map(|v| v - 1).unwrap_or(!0);
+                match enumerators.iter().find(|(_, v)| *v as u64 & mask == value & mask) {
+                    Some((name, _)) => name.clone(),
+                    None => format!("{}", value),
+                }
+            }
+            _ => format!("{:#x}", value),
+        }
+    }
 }
 
 pub struct GimliWrapper {
-    target: String,
+    /// The DWARF of the file, its section data is shared with addr2line.
+    dwarf: Dwarf<Reader>,
+    split_dwarf: Rc<SplitDwarf>,
+    /// Where the sections of the file start among the keys of types, see `SectionBase`.
+    base: SectionBase,
+    /// The compilation and type units. Their DIEs and line tables are only read the
+    /// first time something in them is asked for.
+    units: Vec<LazyUnit>,
+    /// Names, source files and address ranges of `units`, possibly from the symbol cache.
+    index: SymbolIndex,
+    /// Offsets of the types of type units by their signature.
+    signatures: RefCell<HashMap<DebugTypeSignature, usize>>,
+    /// Where the sections of the next split DWARF file start.
+    next_base: Cell<usize>,
+    /// First and last (exclusive) key of the split units read so far with their index
+    /// in `units`, sorted as they are numbered in the order they are read.
+    split_keys: RefCell<Vec<(usize, usize, usize)>>,
+}
+
+/// A compilation or type unit of which only the header and root DIE are read up front.
+struct LazyUnit {
+    unit: Unit<Reader>,
+    /// Path of its source file, see `unit_file_name`.
+    file: String,
+    /// First and last (exclusive) key of its DIEs, see `SectionBase`.
+    keys: (usize, usize),
+    data: OnceCell<UnitData>,
+}
+
+/// Everything read from the DIEs and the line table of a unit.
+#[derive(Default)]
+struct UnitData {
+    /// Types by the offset of their DIE, see `SectionBase`.
     types: HashMap<usize, Type>,
     functions: Vec<Function>,
+    /// Inlined instances of the functions, also those inlined into other inlined code.
+    inlined_calls: Vec<InlinedCall>,
+    /// Low pc, high pc and index in `functions` of every range of every function, sorted
+    /// by low pc.
+    function_index: Vec<(usize, usize, usize)>,
+    /// The same for the ranges of `inlined_calls`.
+    inlined_index: Vec<(usize, usize, usize)>,
+    globals: Vec<GlobalVariable>,
+    /// `extern` declarations of variables defined in another unit.
+    declarations: Vec<Declaration>,
+    /// The rows of the line table, sorted by address.
+    lines: Vec<LineRow>,
 }
 
 impl GimliWrapper {
-    pub fn create(target: &str) -> GimliWrapper {
+    /// Index the DWARF of `object`, the file at `path`. Only the unit headers and the name
+    /// and address indices are read here, the rest when it is needed.
+    pub fn create(dwarf: Dwarf<Reader>, object: &object::File, split_dwarf: Rc<SplitDwarf>, path: &str) -> GimliWrapper {
+        let base = SectionBase::new(0, &dwarf);
         let mut di = GimliWrapper {
-            target: String::from(target),
-            types: HashMap::new(),
-            functions: Vec::new(),
+            next_base: Cell::new(base.end(&dwarf)),
+            dwarf,
+            split_dwarf,
+            base,
+            units: Vec::new(),
+            index: SymbolIndex::default(),
+            signatures: RefCell::new(HashMap::new()),
+            split_keys: RefCell::new(Vec::new()),
         };
-        di.collect_info().expect("Error while collecting debug info.");
-        return di;
+        di.index(object, path).expect("Error while indexing debug info.");
+        di
     }
 
     #[allow(dead_code)]
@@ -86,20 +410,21 @@ impl GimliWrapper {
             Type::Base { name, is_float:_, is_signed:_, byte_size:_, ref_addr:_ } => {
                 print!("{}", name.as_str());
             },
-            Type::Pointer { byte_size:_, to, ref_addr:_ } => {
-                self.print_type(self.types.get(to).unwrap());
+            Type::Pointer { name:_, byte_size:_, to, ref_addr:_ } => {
+                self.print_type(self.get_type(*to).unwrap());
                 print!("*");
             },
             Type::Const { byte_size:_, to, ref_addr:_ } => {
                 print!("const ");
-                self.print_type(self.types.get(to).unwrap());
+                self.print_type(self.get_type(*to).unwrap());
             },
+            _ => print!("{}", self.type_name(t.ref_addr())),
         }
     }
 
     #[allow(dead_code)]
     pub fn print_function(&self, function: &Function){
-        if let Some(t) = self.types.get(&function.t) {
+        if let Some(t) = self.get_type(function.t) {
             self.print_type(t);
             print!(" ");
         } else {
@@ -109,7 +434,7 @@ impl GimliWrapper {
         print!("(");
         let mut first = true;
         for formal_parameter in &function.formal_parameters {
-            let t = self.types.get(&formal_parameter.t).unwrap();
+            let t = self.get_type(formal_parameter.t).unwrap();
             if !first {
                 print!(", ");
             }
@@ -120,163 +445,893 @@ impl GimliWrapper {
         print!(")");
     }
 
+    /// The function `fname`, which may be partially qualified like `Class::method`. One
+    /// with exactly that name wins over those in other scopes.
     pub fn get_function_by_name(&self, fname: &str) -> Option<&Function> {
-        self.functions.iter().find(|f| f.name == fname)
+        let functions = self.functions_by_name(fname);
+        functions.iter().find(|f| f.name == fname).or(functions.first()).copied()
     }
 
-    pub fn get_type(&self, t: usize) -> Option<&Type> {
-        self.types.get(&t)
+    /// The functions with code of the units read so far, after reading those that define
+    /// one of `names`.
+    pub fn functions_with_names(&self, names: &[String]) -> Vec<&Function> {
+        for name in names {
+            self.load_units(&self.index.function_units, name);
+        }
+        self.loaded_units()
+            .flat_map(|data| &data.functions)
+            .filter(|f| !f.address_range.is_empty())
+            .collect()
+    }
+
+    /// The functions with code that `name` refers to, see `demangle::name_matches`.
+    pub fn functions_by_name(&self, name: &str) -> Vec<&Function> {
+        self.load_units(&self.index.function_units, name);
+        self.loaded_units()
+            .flat_map(|data| &data.functions)
+            .filter(|f| !f.address_range.is_empty() && name_matches(&f.name, name))
+            .collect()
+    }
+
+    /// The function with code at the module relative address `pc`.
+    pub fn get_function_by_address(&self, pc: usize) -> Option<&Function> {
+        let data = self.unit_at(pc)?;
+        let (_, _, idx) = range_at(&data.function_index, pc)?;
+        Some(&data.functions[idx])
+    }
+
+    /// The innermost inlined instance of the function `name` that contains `pc`.
+    pub fn get_inlined_call(&self, name: &str, pc: usize) -> Option<&InlinedCall> {
+        let data = self.unit_at(pc)?;
+        // Inlined code is part of the range of the function it was inlined into.
+        let (function_low_pc, _, _) = range_at(&data.function_index, pc)?;
+        let start = data.inlined_index.partition_point(|(low_pc, _, _)| *low_pc < function_low_pc);
+        let end = data.inlined_index.partition_point(|(low_pc, _, _)| *low_pc <= pc);
+        data.inlined_index[start..end]
+            .iter()
+            .filter(|(_, high_pc, _)| pc <= *high_pc)
+            .map(|(_, _, idx)| &data.inlined_calls[*idx])
+            .filter(|call| call.function.name == name)
+            .min_by_key(|call| {
+                call.function.address_range.iter().map(|(low, high)| high - low).sum::<usize>()
+            })
+    }
+
+    /// Entry addresses of the places the function `name` is inlined at.
+    pub fn inlined_sites(&self, name: &str) -> Vec<usize> {
+        self.load_units(&self.index.function_units, name);
+        let mut sites: Vec<usize> = self
+            .loaded_units()
+            .flat_map(|data| &data.inlined_calls)
+            .filter(|call| name_matches(&call.function.name, name))
+            .map(|call| call.entry_pc)
+            .collect();
+        sites.sort();
+        sites.dedup();
+        sites
     }
 
-    pub fn get_type_byte_size(&self, t: usize) -> Option<u64> {
-        match self.types.get(&t)? {
-            Type::Base { name:_, is_float:_, is_signed:_, byte_size, ref_addr:_ } => Some(*byte_size),
-            Type::Pointer { byte_size, to:_, ref_addr:_ } => Some(*byte_size),
-            Type::Const { byte_size, to:_, ref_addr:_ } => Some(*byte_size),
+    /// The global variable `name` as code of `function` in `file` sees it: a `static`
+    /// local of the function, then a variable of the file, then an external one.
+    /// `scope::name` is the variable of a file or the `static` local of a function.
+    pub fn get_global(&self, name: &str, function: Option<&str>, file: Option<&str>) -> Option<&GlobalVariable> {
+        if let Some((scope, name)) = name.rsplit_once("::") {
+            self.load_units(&self.index.variable_units, name);
+            self.load_units(&self.index.function_units, scope);
+            for (idx, unit) in self.units.iter().enumerate() {
+                if file_matches(&unit.file, scope) {
+                    self.unit_data(idx);
+                }
+            }
+            let candidates = || self.loaded_units().flat_map(|data| &data.globals).filter(move |g| g.name == name);
+            // An `extern` declaration in the file refers to the definition in another one.
+            let declared = || {
+                self.loaded_units()
+                    .flat_map(|data| &data.declarations)
+                    .any(|d| d.name == name && file_matches(&d.file, scope))
+            };
+            return candidates()
+                .find(|g| match &g.function {
+                    Some(function) => name_matches(function, scope),
+                    None => file_matches(&g.file, scope),
+                })
+                .or_else(|| candidates().find(|g| g.function.is_none() && g.external && declared()));
         }
+        self.load_units(&self.index.variable_units, name);
+        let candidates = || self.loaded_units().flat_map(|data| &data.globals).filter(move |g| g.name == name);
+        candidates()
+            .find(|g| function.is_some() && g.function.as_deref() == function)
+            .or_else(|| {
+                candidates().find(|g| {
+                    g.function.is_none() && file.is_some_and(|file| file_matches(&g.file, file))
+                })
+            })
+            .or_else(|| candidates().find(|g| g.function.is_none() && g.external))
+            .or_else(|| candidates().find(|g| g.function.is_none()))
     }
 
-    fn collect_info(&mut self) -> Result<(), gimli::Error> {
-        let file = fs::File::open(&self.target).unwrap();
-        let map = unsafe { memmap2::Mmap::map(&file).unwrap() };
-        let object = object::File::parse(&*map).unwrap();
+    /// The value as a double if `t` is a floating point type.
+    pub fn float_value(&self, t: usize, value: u64) -> Option<f64> {
+        match self.get_type(t) {
+            Some(Type::Base { is_float: true, byte_size: 4, .. }) => {
+                Some(f32::from_bits(value as u32) as f64)
+            }
+            Some(Type::Base { is_float: true, byte_size: 8, .. }) => Some(f64::from_bits(value)),
+            Some(Type::Const { to, .. }) | Some(Type::Typedef { to, .. }) => self.float_value(*to, value),
+            _ => None,
+        }
+    }
 
-        let endian = if object.is_little_endian() {
-            gimli::RunTimeEndian::Little
-        } else {
-            gimli::RunTimeEndian::Little
+    /// The first statement after the prologue of a function, where its arguments are in place.
+    pub fn skip_prologue(&self, low_pc: usize, high_pc: usize) -> usize {
+        let lines = match self.unit_at(low_pc) {
+            Some(data) => &data.lines,
+            None => return low_pc,
         };
+        let start = lines.partition_point(|row| row.address <= low_pc);
+        lines[start..]
+            .iter()
+            .take_while(|row| row.address <= high_pc)
+            .find(|row| row.is_stmt)
+            .map_or(low_pc, |row| row.address)
+    }
+
+    /// Returns true if `addr` is the start of a statement in the line table.
+    pub fn is_statement_start(&self, addr: usize) -> bool {
+        let lines = match self.unit_at(addr) {
+            Some(data) => &data.lines,
+            None => return false,
+        };
+        let start = lines.partition_point(|row| row.address < addr);
+        lines[start..]
+            .iter()
+            .take_while(|row| row.address == addr)
+            .any(|row| row.is_stmt)
+    }
+
+    /// Resolve `file:line` to an address. If `line` has no code, the next line
+    /// that has code is used instead. Returns the resolved line and the address.
+    pub fn get_address_for_line(&self, file: &str, line: u64) -> Option<(u64, usize)> {
+        let rows: Vec<&LineRow> = self
+            .index
+            .files
+            .iter()
+            .enumerate()
+            .filter(|(_, files)| files.iter().any(|path| file_matches(path, file)))
+            .flat_map(|(idx, _)| &self.unit_data(idx).lines)
+            .filter(|row| row.is_stmt && row.line >= line && file_matches(&row.file, file))
+            .collect();
+        let resolved_line = rows.iter().map(|row| row.line).min()?;
+        let address = rows
+            .iter()
+            .filter(|row| row.line == resolved_line)
+            .map(|row| row.address)
+            .min()?;
+        Some((resolved_line, address))
+    }
+
+    /// False if the file has no line tables, e.g. a stripped library.
+    pub fn has_line_info(&self) -> bool {
+        self.units.iter().any(|unit| unit.unit.line_program.is_some())
+    }
 
-        // Load a section and return as `Cow<[u8]>`.
-        let load_section = |id: gimli::SectionId| -> Result<borrow::Cow<[u8]>, gimli::Error> {
-            match object.section_by_name(id.name()) {
-                Some(ref section) => Ok(section
-                    .uncompressed_data()
-                    .unwrap_or(borrow::Cow::Borrowed(&[][..]))),
-                None => Ok(borrow::Cow::Borrowed(&[][..])),
+    pub fn function_names(&self) -> Vec<String> {
+        self.index.function_units.keys().cloned().collect()
+    }
+
+    /// Names of the variables of all files, without `static` locals.
+    pub fn global_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.index.variable_units.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    /// File names (without directory) of all files in the line tables.
+    pub fn source_files(&self) -> Vec<String> {
+        let mut files: Vec<String> = self
+            .index
+            .files
+            .iter()
+            .flatten()
+            .map(|file| String::from(file.rsplit('/').next().unwrap_or(file)))
+            .collect();
+        files.sort();
+        files.dedup();
+        files
+    }
+
+    /// Read the unit headers and find out which unit defines which name and which one
+    /// has the code at an address, from `.debug_names` or `.gdb_index` and `.debug_aranges`
+    /// if the file has them. Without a name index the DIEs of all units are scanned once,
+    /// for the names only. The result is kept in the symbol cache for the next start.
+    fn index(&mut self, object: &object::File, path: &str) -> Result<(), gimli::Error> {
+        let mut headers = self.dwarf.units();
+        while let Some(header) = headers.next()? {
+            let unit = self.dwarf.unit(header)?;
+            self.add_unit(unit)?;
+        }
+        let mut headers = self.dwarf.type_units();
+        while let Some(header) = headers.next()? {
+            let unit = self.dwarf.unit(header)?;
+            self.add_unit(unit)?;
+        }
+        if self.units.is_empty() {
+            return Ok(());
+        }
+        if let Some(index) = symbolcache::load(object, path, self.units.len()) {
+            self.index = index;
+            return Ok(());
+        }
+
+        for idx in 0..self.units.len() {
+            let files = self.line_program_files(&self.units[idx].unit)?;
+            self.index.files.push(files);
+        }
+        // Units a name index doesn't list, for example those of object files linked
+        // without one, are scanned like all units are without an index.
+        let mut covered = vec![false; self.units.len()];
+        let name_index = match read_name_index(object) {
+            Some(Ok(name_index)) => Some(name_index),
+            Some(Err(message)) => {
+                println!("Ignoring the name index of {}: {}", path, message);
+                None
             }
+            None => None,
         };
+        if let Some(name_index) = name_index {
+            let units: HashMap<usize, usize> = self
+                .units
+                .iter()
+                .enumerate()
+                .filter_map(|(idx, unit)| Some((unit.unit.header.offset().as_debug_info_offset()?.0, idx)))
+                .collect();
+            for offset in &name_index.units {
+                if let Some(idx) = units.get(offset) {
+                    covered[*idx] = true;
+                }
+            }
+            for entry in name_index.entries {
+                if let Some(idx) = units.get(&entry.unit_offset) {
+                    self.add_name(entry.kind, entry.name, *idx);
+                }
+            }
+        }
+        let rest: Vec<usize> = (0..self.units.len())
+            .filter(|idx| !covered[*idx] && type_signature(&self.units[*idx].unit).is_none())
+            .collect();
+        if !rest.is_empty() {
+            let mut progress = Progress::new(&format!("Reading symbols from {}...", path));
+            for (done, idx) in rest.iter().enumerate() {
+                progress.update(done, rest.len());
+                for (kind, name) in self.scan_names(*idx)? {
+                    self.add_name(kind, name, *idx);
+                }
+            }
+            progress.finish();
+        }
+
+        self.index.unit_ranges = self.read_unit_ranges()?;
+        // Without a writable cache directory every start indexes the file again. That is
+        // said once, not for each library.
+        static STORE_FAILED: AtomicBool = AtomicBool::new(false);
+        if let Err(e) = symbolcache::store(object, path, &self.index) {
+            if !STORE_FAILED.swap(true, Ordering::Relaxed) {
+                println!("Could not save the symbol index of {} in the cache: {}", path, e);
+            }
+        }
+        Ok(())
+    }
+
+    fn add_unit(&mut self, unit: Unit<Reader>) -> Result<(), gimli::Error> {
+        let start = self.base.offset(unit.header.offset());
+        let end = start + unit.header.length_including_self();
+        if let Some((signature, offset)) = type_signature(&unit) {
+            self.signatures
+                .borrow_mut()
+                .insert(signature, self.base.offset(offset.to_unit_section_offset(&unit)));
+        }
+        self.units.push(LazyUnit {
+            file: unit_file_name(&unit)?,
+            keys: (start, end),
+            unit,
+            data: OnceCell::new(),
+        });
+        Ok(())
+    }
+
+    /// Paths of the files in the header of the line program of a compilation unit.
+    fn line_program_files(&self, unit: &Unit<Reader>) -> Result<Vec<String>, gimli::Error> {
+        let mut files = Vec::new();
+        if let (Some(program), None) = (&unit.line_program, type_signature(unit)) {
+            let header = program.header();
+            for file in header.file_names() {
+                files.push(file_path(&self.dwarf, unit, header, file)?);
+            }
+        }
+        Ok(files)
+    }
+
+    fn add_name(&mut self, kind: NameKind, name: String, idx: usize) {
+        if kind == NameKind::Unknown {
+            self.add_name(NameKind::Function, name.clone(), idx);
+            self.add_name(NameKind::Variable, name, idx);
+            return;
+        }
+        let index = match kind {
+            NameKind::Function => &mut self.index.function_units,
+            _ => &mut self.index.variable_units,
+        };
+        // Index entries of C++ have the qualified name, the DIEs the plain one.
+        let plain = name.rsplit_once("::").map(|(_, plain)| String::from(plain));
+        for name in plain.into_iter().chain([name]) {
+            let units = index.entry(name).or_default();
+            if units.last() != Some(&idx) {
+                units.push(idx);
+            }
+        }
+    }
 
-        // Load all of the sections.
-        let dwarf_cow = gimli::Dwarf::load(&load_section)?;
+    /// The names of the functions and variables a unit defines, the split unit's for
+    /// skeleton units.
+    fn scan_names(&self, idx: usize) -> Result<Vec<(NameKind, String)>, gimli::Error> {
+        let unit = &self.units[idx].unit;
+        let mut names = Vec::new();
+        match self.split_dwarf.unit(unit, &self.dwarf) {
+            Some((split, split_unit)) => scan_names(&split, &split_unit, &mut names)?,
+            None => scan_names(&self.dwarf, unit, &mut names)?,
+        }
+        Ok(names)
+    }
 
-        // Borrow a `Cow<[u8]>` to create an `EndianSlice`.
-        let borrow_section: &dyn for<'a> Fn(
-            &'a borrow::Cow<[u8]>,
-        )
-            -> gimli::EndianSlice<'a, gimli::RunTimeEndian> =
-            &|section| gimli::EndianSlice::new(&*section, endian);
+    /// The address ranges of the compilation units from `.debug_aranges`, or from the
+    /// `DW_AT_ranges` or low and high pc of the units it doesn't cover.
+    fn read_unit_ranges(&self) -> Result<Vec<(usize, usize, usize)>, gimli::Error> {
+        let units: HashMap<usize, usize> = self
+            .units
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, unit)| Some((unit.unit.header.offset().as_debug_info_offset()?.0, idx)))
+            .collect();
+        let mut ranges = Vec::new();
+        let mut covered = vec![false; self.units.len()];
+        let mut headers = self.dwarf.debug_aranges.headers();
+        while let Some(header) = headers.next()? {
+            let idx = match units.get(&header.debug_info_offset().0) {
+                Some(idx) => *idx,
+                None => continue,
+            };
+            let mut entries = header.entries();
+            while let Some(entry) = entries.next()? {
+                if entry.length() > 0 {
+                    ranges.push((entry.address() as usize, (entry.address() + entry.length() - 1) as usize, idx));
+                    covered[idx] = true;
+                }
+            }
+        }
+        for (idx, unit) in self.units.iter().enumerate() {
+            if covered[idx] || type_signature(&unit.unit).is_some() {
+                continue;
+            }
+            let mut iter = self.dwarf.unit_ranges(&unit.unit)?;
+            while let Some(range) = iter.next()? {
+                if range.end > range.begin {
+                    ranges.push((range.begin as usize, range.end as usize - 1, idx));
+                }
+            }
+        }
+        // Code the linker discarded is left at address 0.
+        ranges.retain(|(low_pc, _, _)| *low_pc != 0);
+        ranges.sort();
+        Ok(ranges)
+    }
 
-        // Create `EndianSlice`s for all of the sections.
-        let dwarf = dwarf_cow.borrow(&borrow_section);
+    /// Read the units `index` has for `name`.
+    fn load_units(&self, index: &HashMap<String, Vec<usize>>, name: &str) {
+        // Name indices have the plain names, some also the qualified ones.
+        let plain = name.rsplit("::").next().unwrap_or(name);
+        for idx in index.get(name).into_iter().chain(index.get(plain)).flatten() {
+            self.unit_data(*idx);
+        }
+    }
 
-        // Iterate over the compilation units.
-        let mut iter = dwarf.units();
+    /// The units read so far.
+    fn loaded_units(&self) -> impl Iterator<Item = &UnitData> {
+        self.units.iter().filter_map(|unit| unit.data.get())
+    }
 
-        while let Some(header) = iter.next()? {
-            let unit = dwarf.unit(header)?;
-            // println!("Unit: {:?}", unit.name);
+    /// The unit with the code at `pc`.
+    fn unit_at(&self, pc: usize) -> Option<&UnitData> {
+        let (_, _, idx) = range_at(&self.index.unit_ranges, pc)?;
+        Some(self.unit_data(idx))
+    }
 
-            let mut types: Vec<Type> = Vec::new();
-            let mut functions: Vec<Function> = Vec::new();
+    /// Index in `units` of the unit with the DIE of a type key.
+    fn unit_of_key(&self, key: usize) -> Option<usize> {
+        let end = self.units.partition_point(|unit| unit.keys.0 <= key);
+        match end.checked_sub(1).map(|idx| (idx, self.units[idx].keys)) {
+            Some((idx, (_, end))) if key < end => Some(idx),
+            _ => {
+                let split_keys = self.split_keys.borrow();
+                let end = split_keys.partition_point(|(start, _, _)| *start <= key);
+                let (_, end, idx) = *split_keys[..end].last()?;
+                (key < end).then_some(idx)
+            }
+        }
+    }
 
-            // 1) Read base types
-            let mut _depth = 0;
-            let mut entries = unit.entries();
-            while let Some((delta_depth, entry)) = entries.next_dfs()? {
-                _depth += delta_depth;
+    /// The data of unit `idx`, read the first time it is asked for.
+    fn unit_data(&self, idx: usize) -> &UnitData {
+        self.units[idx].data.get_or_init(|| {
+            self.read_unit(idx).unwrap_or_else(|e| {
+                println!("Error while reading debug info of {}: {}", self.units[idx].file, e);
+                UnitData::default()
+            })
+        })
+    }
 
-                match entry.tag() {
-                    gimli::DW_TAG_base_type => {
-                        types.push(self.process_base_type(entry, &dwarf)?);
-                    }
-                    _ => {} // println!("Skipping <{}><{:#x}> {}", depth, entry.offset().0, entry.tag());
+    fn read_unit(&self, idx: usize) -> Result<UnitData, gimli::Error> {
+        let unit = &self.units[idx].unit;
+        let mut data = UnitData::default();
+        // With -gsplit-dwarf the executable only has a skeleton unit with the line table,
+        // the DIEs are in the split unit.
+        match self.split_dwarf.unit(unit, &self.dwarf) {
+            Some((split, split_unit)) => {
+                let split_base = SectionBase::new(self.next_base.get(), &split);
+                self.next_base.set(split_base.end(&split));
+                self.split_keys
+                    .borrow_mut()
+                    .push((split_base.debug_info, split_base.end(&split), idx));
+                // A .dwo file also has the type units its compilation unit refers to.
+                let mut split_units: Vec<_> = all_units(&split)?
+                    .into_iter()
+                    .filter(|unit| type_signature(unit).is_some())
+                    .collect();
+                self.register_type_units(&split_units, split_base);
+                split_units.push(split_unit);
+                for split_unit in &split_units {
+                    self.process_unit(&split, split_unit, split_base, &mut data)?;
                 }
             }
+            None => self.process_unit(&self.dwarf, unit, self.base, &mut data)?,
+        }
+        if type_signature(unit).is_none() {
+            self.process_line_program(unit, &mut data)?;
+        }
 
-            // 2) Read pointer types
-            let mut _depth = 0;
-            let mut entries = unit.entries();
-            while let Some((delta_depth, entry)) = entries.next_dfs()? {
-                _depth += delta_depth;
+        data.lines.sort_by_key(|row| row.address);
+        data.function_index = range_index(data.functions.iter());
+        data.inlined_index = range_index(data.inlined_calls.iter().map(|call| &call.function));
+        Ok(data)
+    }
 
-                match entry.tag() {
-                    gimli::DW_TAG_pointer_type => {
-                        types.push(self.process_pointer_type(entry)?);
-                    }
-                    gimli::DW_TAG_const_type => {
-                        types.push(self.process_const_type(entry)?);
+    /// Remember where the type of each type unit is, for `DW_FORM_ref_sig8` references.
+    fn register_type_units(&self, units: &[Unit<Reader>], base: SectionBase) {
+        for unit in units {
+            if let Some((signature, offset)) = type_signature(unit) {
+                self.signatures
+                    .borrow_mut()
+                    .insert(signature, base.offset(offset.to_unit_section_offset(unit)));
+            }
+        }
+    }
+
+    fn process_unit(
+        &self,
+        dwarf: &Dwarf<Reader>,
+        unit: &Unit<Reader>,
+        base: SectionBase,
+        data: &mut UnitData,
+    ) -> Result<(), gimli::Error> {
+        let mut types: Vec<Type> = Vec::new();
+
+        // 1) Read the types, with the names of the namespaces and types enclosing them
+        let mut scopes: Vec<(isize, String)> = Vec::new();
+        let mut depth = 0;
+        let mut entries = unit.entries();
+        while let Some((delta_depth, entry)) = entries.next_dfs()? {
+            depth += delta_depth;
+            while scopes.last().is_some_and(|(scope_depth, _)| *scope_depth >= depth) {
+                scopes.pop();
+            }
+            let qualified_name = || -> Result<String, gimli::Error> {
+                let name = match entry.attr(gimli::DW_AT_name)? {
+                    Some(attr) => self.resolve_dw_at_name(&attr, dwarf, unit).unwrap_or_default(),
+                    None => String::new(),
+                };
+                Ok(match scopes.last() {
+                    Some((_, scope)) if !name.is_empty() => format!("{}::{}", scope, name),
+                    _ => name,
+                })
+            };
+            match entry.tag() {
+                gimli::DW_TAG_base_type => {
+                    types.push(self.process_base_type(entry, dwarf, unit, base)?);
+                }
+                gimli::DW_TAG_pointer_type | gimli::DW_TAG_reference_type | gimli::DW_TAG_rvalue_reference_type => {
+                    types.push(self.process_pointer_type(entry, dwarf, unit, base)?);
+                }
+                gimli::DW_TAG_const_type => {
+                    types.push(self.process_const_type(entry, unit, base)?);
+                }
+                gimli::DW_TAG_structure_type | gimli::DW_TAG_class_type | gimli::DW_TAG_union_type => {
+                    let name = qualified_name()?;
+                    if entry.attr_value(gimli::DW_AT_declaration)?.is_none() {
+                        types.push(self.process_struct_type(entry, dwarf, unit, base, name.clone())?);
                     }
-                    _ => {} // println!("Skipping <{}><{:#x}> {}", depth, entry.offset().0, entry.tag());
+                    scopes.push((depth, name));
+                }
+                gimli::DW_TAG_enumeration_type => {
+                    let name = qualified_name()?;
+                    types.push(self.process_enumeration_type(entry, dwarf, unit, base, name.clone())?);
+                    scopes.push((depth, name));
+                }
+                gimli::DW_TAG_namespace => {
+                    let name = qualified_name()?;
+                    let name = if name.is_empty() { String::from("(anonymous namespace)") } else { name };
+                    scopes.push((depth, name));
+                }
+                gimli::DW_TAG_array_type => {
+                    types.push(self.process_array_type(entry, unit, base)?);
+                }
+                gimli::DW_TAG_typedef => {
+                    let (_, to, _) = self.entry_attributes(entry, dwarf, unit, base)?;
+                    types.push(Type::Typedef {
+                        name: qualified_name()?,
+                        to,
+                        ref_addr: base.offset(entry.offset().to_unit_section_offset(unit)),
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        // 2) Read functions with their parameters and the variables of their scopes, and
+        // the variables with a static address
+        let (functions, inlined_calls) = (data.functions.len(), data.inlined_calls.len());
+        let mut tree = unit.entries_tree(None)?;
+        self.process_functions(tree.root()?, dwarf, unit, base, data)?;
+
+        let language = match unit.entries().next_dfs()? {
+            Some((_, root)) => match root.attr_value(gimli::DW_AT_language)? {
+                Some(gimli::AttributeValue::Language(language)) => language_name(language),
+                _ => None,
+            },
+            None => None,
+        };
+        let inlined = data.inlined_calls[inlined_calls..].iter_mut().map(|call| &mut call.function);
+        for function in data.functions[functions..].iter_mut().chain(inlined) {
+            function.language = language.clone();
+        }
+
+        for typ in types {
+            data.types.insert(typ.ref_addr(), typ);
+        }
+
+        Ok(())
+    }
+
+    fn process_line_program(&self, unit: &Unit<Reader>, data: &mut UnitData) -> Result<(), gimli::Error> {
+        let program = match &unit.line_program {
+            Some(program) => program.clone(),
+            None => return Ok(()),
+        };
+
+        let mut rows = program.rows();
+        while let Some((header, row)) = rows.next_row()? {
+            if row.end_sequence() {
+                continue;
+            }
+            let file = match row.file(header) {
+                Some(file) => file_path(&self.dwarf, unit, header, file)?,
+                None => String::new(),
+            };
+            data.lines.push(LineRow {
+                address: row.address() as usize,
+                file,
+                line: row.line().map(|l| l.get()).unwrap_or(0),
+                is_stmt: row.is_stmt(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Collect the functions and global variables below `node`, also those in namespaces
+    /// or classes.
+    fn process_functions(
+        &self,
+        node: EntriesTreeNode<Reader>,
+        dwarf: &Dwarf<Reader>,
+        unit: &Unit<Reader>,
+        base: SectionBase,
+        items: &mut UnitData,
+    ) -> Result<(), gimli::Error> {
+        let mut children = node.children();
+        while let Some(child) = children.next()? {
+            match child.entry().tag() {
+                gimli::DW_TAG_subprogram => self.process_function(child, dwarf, unit, base, items)?,
+                gimli::DW_TAG_variable => {
+                    self.process_global(child.entry(), dwarf, unit, base, None, items)?;
                 }
+                gimli::DW_TAG_lexical_block => {}
+                _ => self.process_functions(child, dwarf, unit, base, items)?,
             }
+        }
+        Ok(())
+    }
+
+    fn process_function(
+        &self,
+        node: EntriesTreeNode<Reader>,
+        dwarf: &Dwarf<Reader>,
+        unit: &Unit<Reader>,
+        base: SectionBase,
+        items: &mut UnitData,
+    ) -> Result<(), gimli::Error> {
+        let function = self.process_scope(node, dwarf, unit, base, items)?;
+        items.functions.push(function);
+        Ok(())
+    }
 
-            // 3) Read everything else
-            let mut _depth = 0;
-            let mut entries = unit.entries();
-            while let Some((delta_depth, entry)) = entries.next_dfs()? {
-                _depth += delta_depth;
+    fn process_inlined_call(
+        &self,
+        node: EntriesTreeNode<Reader>,
+        dwarf: &Dwarf<Reader>,
+        unit: &Unit<Reader>,
+        base: SectionBase,
+        items: &mut UnitData,
+    ) -> Result<(), gimli::Error> {
+        let entry_pc = node.entry().attr_value(gimli::DW_AT_entry_pc)?;
+        let function = self.process_scope(node, dwarf, unit, base, items)?;
+        let low_pc = match function.address_range.first() {
+            Some((low_pc, _)) => *low_pc,
+            None => return Ok(()),
+        };
+        let entry_pc = match entry_pc {
+            // DWARF 5 allows an offset from the low pc.
+            Some(gimli::AttributeValue::Udata(offset)) => low_pc + offset as usize,
+            Some(value) => dwarf.attr_address(unit, value)?.map_or(low_pc, |pc| pc as usize),
+            None => low_pc,
+        };
+        items.inlined_calls.push(InlinedCall { function, entry_pc });
+        Ok(())
+    }
 
-                match entry.tag() {
-                    gimli::DW_TAG_subprogram => {
-                        functions.push(self.process_subprogram(entry, &dwarf)?);
+    /// Read a subprogram or an inlined instance of one with the parameters and the
+    /// variables of its scopes.
+    fn process_scope(
+        &self,
+        node: EntriesTreeNode<Reader>,
+        dwarf: &Dwarf<Reader>,
+        unit: &Unit<Reader>,
+        base: SectionBase,
+        items: &mut UnitData,
+    ) -> Result<Function, gimli::Error> {
+        let mut function = self.process_subprogram(node.entry(), dwarf, unit, base)?;
+        // Concrete instances list their parameters in any order, the abstract one in
+        // the order of the declaration.
+        let mut parameters = Vec::new();
+        let mut children = node.children();
+        while let Some(child) = children.next()? {
+            match child.entry().tag() {
+                gimli::DW_TAG_formal_parameter => {
+                    let entry = child.entry();
+                    let order = match entry.attr_value(gimli::DW_AT_abstract_origin)? {
+                        Some(gimli::AttributeValue::UnitRef(offset)) => offset,
+                        _ => entry.offset(),
+                    };
+                    parameters.push((order, self.process_formal_parameter(entry, dwarf, unit, base)?));
+                }
+                gimli::DW_TAG_variable => {
+                    let entry = child.entry();
+                    if !self.process_global(entry, dwarf, unit, base, Some(&function.name), items)? {
+                        let variable = self.process_variable(entry, dwarf, unit, base)?;
+                        function.local_variables.push(variable);
                     }
-                    gimli::DW_TAG_formal_parameter => {
-                        if let Some(function) = functions.last_mut() {
-                            let fp =
-                                self.process_formal_parameter(entry, &dwarf, unit.encoding())?;
-                            function.formal_parameters.push(fp);
-                        }
+                }
+                gimli::DW_TAG_lexical_block => {
+                    let block = self.process_lexical_block(child, dwarf, unit, base, &function.name, items)?;
+                    function.blocks.push(block);
+                }
+                gimli::DW_TAG_subprogram => self.process_function(child, dwarf, unit, base, items)?,
+                gimli::DW_TAG_inlined_subroutine => self.process_inlined_call(child, dwarf, unit, base, items)?,
+                _ => {}
+            }
+        }
+        parameters.sort_by_key(|(order, _)| *order);
+        function.formal_parameters = parameters.into_iter().map(|(_, parameter)| parameter).collect();
+        Ok(function)
+    }
+
+    fn process_lexical_block(
+        &self,
+        node: EntriesTreeNode<Reader>,
+        dwarf: &Dwarf<Reader>,
+        unit: &Unit<Reader>,
+        base: SectionBase,
+        function: &str,
+        items: &mut UnitData,
+    ) -> Result<Block, gimli::Error> {
+        let mut block = Block {
+            address_range: self.address_ranges(node.entry(), dwarf, unit)?,
+            variables: Vec::new(),
+            blocks: Vec::new(),
+        };
+        let mut children = node.children();
+        while let Some(child) = children.next()? {
+            match child.entry().tag() {
+                gimli::DW_TAG_variable => {
+                    let entry = child.entry();
+                    if !self.process_global(entry, dwarf, unit, base, Some(function), items)? {
+                        block.variables.push(self.process_variable(entry, dwarf, unit, base)?);
                     }
-                    gimli::DW_TAG_variable => {
-                        if let Some(function) = functions.last_mut() {
-                            let fp = self.process_variable(entry, &dwarf, unit.encoding())?;
-                            function.local_variables.push(fp);
-                        }
+                }
+                gimli::DW_TAG_lexical_block => {
+                    let inner = self.process_lexical_block(child, dwarf, unit, base, function, items)?;
+                    block.blocks.push(inner);
+                }
+                // GNU C allows functions nested in a block.
+                gimli::DW_TAG_subprogram => self.process_function(child, dwarf, unit, base, items)?,
+                gimli::DW_TAG_inlined_subroutine => self.process_inlined_call(child, dwarf, unit, base, items)?,
+                _ => {}
+            }
+        }
+        Ok(block)
+    }
+
+    /// Add a variable with a static address to the globals, a `static` local if it is
+    /// in `function`, and remember `extern` declarations. Returns false for other
+    /// variables, which are locals.
+    fn process_global(
+        &self,
+        entry: &DebuggingInformationEntry<Reader>,
+        dwarf: &Dwarf<Reader>,
+        unit: &Unit<Reader>,
+        base: SectionBase,
+        function: Option<&str>,
+        items: &mut UnitData,
+    ) -> Result<bool, gimli::Error> {
+        let address = self.static_address(entry, dwarf, unit)?;
+        let is_declaration = entry.attr_value(gimli::DW_AT_declaration)?.is_some();
+        if address.is_none() && !is_declaration {
+            return Ok(false);
+        }
+
+        let (name, t, external) = self.entry_attributes(entry, dwarf, unit, base)?;
+        let file = unit_file_name(unit)?;
+        match address {
+            Some(address) => items.globals.push(GlobalVariable {
+                name,
+                t,
+                address,
+                file,
+                function: function.map(String::from),
+                external,
+            }),
+            None => items.declarations.push(Declaration { name, file }),
+        }
+        Ok(true)
+    }
+
+    /// The address of a `DW_OP_addr` or `DW_OP_addrx` location. `None` for variables on the
+    /// stack or in registers and for thread local ones.
+    fn static_address(
+        &self,
+        entry: &DebuggingInformationEntry<Reader>,
+        dwarf: &Dwarf<Reader>,
+        unit: &Unit<Reader>,
+    ) -> Result<Option<usize>, gimli::Error> {
+        let mut es = match entry.attr_value(gimli::DW_AT_location)? {
+            Some(gimli::AttributeValue::Exprloc(gimli::Expression(es))) => es,
+            _ => return Ok(None),
+        };
+        let address = match gimli::Operation::parse(&mut es, unit.encoding()) {
+            Ok(gimli::Operation::Address { address }) => address,
+            Ok(gimli::Operation::AddressIndex { index }) => dwarf.address(unit, index)?,
+            _ => return Ok(None),
+        };
+        // Thread local variables go on with an operation adding the thread pointer.
+        if !es.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(address as usize))
+    }
+
+    /// Name, type and `DW_AT_external` of a variable, parameter or subprogram. A definition
+    /// that is separate from its declaration has them at the declaration its
+    /// `DW_AT_specification` refers to, inlined and out-of-line instances at the abstract
+    /// instance their `DW_AT_abstract_origin` refers to.
+    fn entry_attributes(
+        &self,
+        entry: &DebuggingInformationEntry<Reader>,
+        dwarf: &Dwarf<Reader>,
+        unit: &Unit<Reader>,
+        base: SectionBase,
+    ) -> Result<(String, usize, bool), gimli::Error> {
+        let (mut name, mut t, mut external) = (String::new(), 0, false);
+        for origin in [gimli::DW_AT_specification, gimli::DW_AT_abstract_origin] {
+            let attributes = referenced_entry(entry, origin, dwarf, unit, |origin, unit| {
+                self.entry_attributes(origin, dwarf, unit, base)
+            })?;
+            if let Some(attributes) = attributes {
+                (name, t, external) = attributes;
+            }
+        }
+
+        let mut attrs = entry.attrs();
+        while let Some(attr) = attrs.next()? {
+            match attr.name() {
+                gimli::DW_AT_name => {
+                    name = self
+                        .resolve_dw_at_name(&attr, dwarf, unit)
+                        .unwrap_or_default();
+                }
+                gimli::DW_AT_type => {
+                    if let Some(offset) = self.type_ref(attr.value(), unit, base) {
+                        t = offset;
                     }
-                    _ => {} // println!("Skipping <{}><{:#x}> {}", depth, entry.offset().0, entry.tag());
-                }
-            }
-
-            for typ in types {
-                let ref_addr = *match &typ {
-                    Type::Base {
-                        name: _,
-                        is_float: _,
-                        is_signed: _,
-                        byte_size: _,
-                        ref_addr,
-                    } => ref_addr,
-                    Type::Pointer {
-                        byte_size: _,
-                        to: _,
-                        ref_addr,
-                    } => ref_addr,
-                    Type::Const {
-                        byte_size: _,
-                        to: _,
-                        ref_addr,
-                    } => ref_addr,
-                };
-                self.types.insert(ref_addr, typ);
+                }
+                gimli::DW_AT_external => external = true,
+                _ => {}
             }
+        }
+        Ok((name, t, external))
+    }
 
-            self.functions.extend(functions);
+    /// The `DW_AT_linkage_name` of a subprogram, or that of the declaration or abstract
+    /// instance it refers to.
+    fn linkage_name(
+        &self,
+        entry: &DebuggingInformationEntry<Reader>,
+        dwarf: &Dwarf<Reader>,
+        unit: &Unit<Reader>,
+    ) -> Result<Option<String>, gimli::Error> {
+        for name in [gimli::DW_AT_linkage_name, gimli::DW_AT_MIPS_linkage_name] {
+            if let Some(attr) = entry.attr(name)? {
+                return Ok(self.resolve_dw_at_name(&attr, dwarf, unit));
+            }
+        }
+        for origin in [gimli::DW_AT_specification, gimli::DW_AT_abstract_origin] {
+            let name = referenced_entry(entry, origin, dwarf, unit, |origin, unit| {
+                self.linkage_name(origin, dwarf, unit)
+            })?;
+            if let Some(name) = name {
+                return Ok(name);
+            }
         }
+        Ok(None)
+    }
 
-        Ok(())
+    /// The key of the type an attribute like `DW_AT_type` refers to, in this unit, anywhere
+    /// in `.debug_info` or in a type unit.
+    fn type_ref(
+        &self,
+        value: gimli::AttributeValue<Reader>,
+        unit: &Unit<Reader>,
+        base: SectionBase,
+    ) -> Option<usize> {
+        match value {
+            gimli::AttributeValue::UnitRef(offset) => {
+                Some(base.offset(offset.to_unit_section_offset(unit)))
+            }
+            gimli::AttributeValue::DebugInfoRef(offset) => Some(base.debug_info + offset.0),
+            gimli::AttributeValue::DebugTypesRef(signature) => self.signatures.borrow().get(&signature).copied(),
+            _ => None,
+        }
     }
 
     fn process_base_type(
         &self,
-        entry: &DebuggingInformationEntry<EndianSlice<RunTimeEndian>, usize>,
-        dwarf: &Dwarf<EndianSlice<RunTimeEndian>>,
+        entry: &DebuggingInformationEntry<Reader>,
+        dwarf: &Dwarf<Reader>,
+        unit: &Unit<Reader>,
+        base: SectionBase,
     ) -> Result<Type, gimli::Error> {
         let mut is_float = false;
         let mut is_signed = false;
         let mut name = String::from("");
         let mut byte_size = 0;
-        let ref_addr = entry.offset().0;
+        let ref_addr = base.offset(entry.offset().to_unit_section_offset(unit));
 
         let mut attrs = entry.attrs();
         while let Some(attr) = attrs.next()? {
@@ -297,8 +1352,8 @@ impl GimliWrapper {
                 }
                 gimli::DW_AT_name => {
                     name = self
-                        .resolve_dw_at_name(&attr, dwarf)
-                        .unwrap_or(String::new());
+                        .resolve_dw_at_name(&attr, dwarf, unit)
+                        .unwrap_or_default();
                 }
                 _ => {}
             }
@@ -314,26 +1369,27 @@ impl GimliWrapper {
 
     fn resolve_dw_at_name(
         &self,
-        attr: &gimli::Attribute<EndianSlice<RunTimeEndian>>,
-        dwarf: &Dwarf<EndianSlice<RunTimeEndian>>,
+        attr: &gimli::Attribute<Reader>,
+        dwarf: &Dwarf<Reader>,
+        unit: &Unit<Reader>,
     ) -> Option<String> {
-        match attr.value() {
-            gimli::AttributeValue::String(slice) => Some(slice.to_string_lossy().to_string()),
-            gimli::AttributeValue::DebugStrRef(offset) => match dwarf.debug_str.get_str(offset) {
-                Ok(n) => Some(n.to_string_lossy().to_string()),
-                Err(_) => None,
-            },
-            _ => None,
-        }
+        // DW_FORM_strx indexes .debug_str_offsets, DW_FORM_line_strp points into .debug_line_str.
+        let name = dwarf.attr_string(unit, attr.value()).ok()?;
+        Some(name.to_string_lossy().ok()?.to_string())
     }
 
     fn process_pointer_type(
         &self,
-        entry: &DebuggingInformationEntry<EndianSlice<RunTimeEndian>, usize>,
+        entry: &DebuggingInformationEntry<Reader>,
+        dwarf: &Dwarf<Reader>,
+        unit: &Unit<Reader>,
+        base: SectionBase,
     ) -> Result<Type, gimli::Error> {
-        let mut byte_size = 0;
+        let mut name = None;
+        // Rust pointers have no size of their own.
+        let mut byte_size = unit.encoding().address_size as u64;
         let mut to = 0;
-        let ref_addr = entry.offset().0;
+        let ref_addr = base.offset(entry.offset().to_unit_section_offset(unit));
 
         let mut attrs = entry.attrs();
         while let Some(attr) = attrs.next()? {
@@ -345,18 +1401,19 @@ impl GimliWrapper {
                         .expect("Could not get udata_value");
                 }
                 gimli::DW_AT_type => {
-                    if let gimli::AttributeValue::UnitRef(gimli::UnitOffset(offset)) = attr.value()
-                    {
+                    if let Some(offset) = self.type_ref(attr.value(), unit, base) {
                         to = offset;
                     } else {
                         println!("Could not get base_type offset for pointer type.");
                     }
                 }
+                gimli::DW_AT_name => name = self.resolve_dw_at_name(&attr, dwarf, unit),
                 _ => {}
             }
         }
 
         Ok(Type::Pointer {
+            name,
             byte_size,
             to,
             ref_addr,
@@ -365,11 +1422,13 @@ impl GimliWrapper {
 
     fn process_const_type(
         &self,
-        entry: &DebuggingInformationEntry<EndianSlice<RunTimeEndian>, usize>,
+        entry: &DebuggingInformationEntry<Reader>,
+        unit: &Unit<Reader>,
+        base: SectionBase,
     ) -> Result<Type, gimli::Error> {
         let mut byte_size = 0;
         let mut to = 0;
-        let ref_addr = entry.offset().0;
+        let ref_addr = base.offset(entry.offset().to_unit_section_offset(unit));
 
         let mut attrs = entry.attrs();
         while let Some(attr) = attrs.next()? {
@@ -381,8 +1440,7 @@ impl GimliWrapper {
                         .expect("Could not get udata_value");
                 }
                 gimli::DW_AT_type => {
-                    if let gimli::AttributeValue::UnitRef(gimli::UnitOffset(offset)) = attr.value()
-                    {
+                    if let Some(offset) = self.type_ref(attr.value(), unit, base) {
                         to = offset;
                     } else {
                         println!("Could not get base_type offset for pointer type.");
@@ -399,171 +1457,621 @@ impl GimliWrapper {
         })
     }
 
-    fn process_subprogram(
+    fn process_struct_type(
         &self,
-        entry: &DebuggingInformationEntry<EndianSlice<RunTimeEndian>, usize>,
-        dwarf: &Dwarf<EndianSlice<RunTimeEndian>>,
-    ) -> Result<Function, gimli::Error> {
-        let mut name = String::new();
-        let mut t = 0;
-        let mut low_pc = 0;
-        let mut high_pc = 0;
-        let mut high_offset = None;
+        entry: &DebuggingInformationEntry<Reader>,
+        dwarf: &Dwarf<Reader>,
+        unit: &Unit<Reader>,
+        base: SectionBase,
+        name: String,
+    ) -> Result<Type, gimli::Error> {
+        let mut members = Vec::new();
+        let mut template_parameters = Vec::new();
+        let mut variant_part = None;
 
-        let mut attrs = entry.attrs();
-        while let Some(attr) = attrs.next()? {
-            // println!("   {}: {:?}", attr.name(), attr.value());
-            match attr.name() {
-                gimli::DW_AT_name => {
-                    name = self
-                        .resolve_dw_at_name(&attr, dwarf)
-                        .unwrap_or(String::new());
-                }
-                gimli::DW_AT_high_pc => {
-                    match attr.value() {
-                        gimli::AttributeValue::Udata(v) => high_offset = Some(v),
-                        gimli::AttributeValue::Addr(v) => high_pc = v,
-                        _ => println!("Unsupported high_pc format")
-                    }
+        let mut tree = unit.entries_tree(Some(entry.offset()))?;
+        let mut children = tree.root()?.children();
+        while let Some(child) = children.next()? {
+            match child.entry().tag() {
+                gimli::DW_TAG_member | gimli::DW_TAG_inheritance => {
+                    members.extend(self.process_member(child.entry(), dwarf, unit, base)?);
                 }
-                gimli::DW_AT_low_pc => {
-                    if let gimli::AttributeValue::Addr(v) = attr.value() {
-                        low_pc = v;
-                    } else {
-                        println!("could not read low_pc")
-                    }
+                gimli::DW_TAG_template_type_parameter => {
+                    let (name, t, _) = self.entry_attributes(child.entry(), dwarf, unit, base)?;
+                    template_parameters.push((name, t));
                 }
-                gimli::DW_AT_type => {
-                    if let gimli::AttributeValue::UnitRef(gimli::UnitOffset(offset)) = attr.value()
-                    {
-                        t = offset;
-                    } else {
-                        println!("Could not get base_type offset for pointer type.");
+                gimli::DW_TAG_variant_part => {
+                    variant_part = Some(self.process_variant_part(child, dwarf, unit, base)?);
+                }
+                _ => {}
+            }
+        }
+
+        Ok(Type::Struct {
+            name,
+            byte_size: udata_attr(entry, gimli::DW_AT_byte_size)?.unwrap_or(0),
+            members,
+            template_parameters,
+            variant_part,
+            ref_addr: base.offset(entry.offset().to_unit_section_offset(unit)),
+        })
+    }
+
+    /// A data member or base class, `None` for the static members of C++ classes.
+    fn process_member(
+        &self,
+        entry: &DebuggingInformationEntry<Reader>,
+        dwarf: &Dwarf<Reader>,
+        unit: &Unit<Reader>,
+        base: SectionBase,
+    ) -> Result<Option<Member>, gimli::Error> {
+        if entry.attr_value(gimli::DW_AT_declaration)?.is_some() {
+            return Ok(None);
+        }
+        let (name, t, _) = self.entry_attributes(entry, dwarf, unit, base)?;
+        let offset = match entry.attr_value(gimli::DW_AT_data_member_location)? {
+            // DWARF 2 style DW_OP_plus_uconst
+            Some(gimli::AttributeValue::Exprloc(gimli::Expression(mut es))) => {
+                match gimli::Operation::parse(&mut es, unit.encoding()) {
+                    Ok(gimli::Operation::PlusConstant { value }) => value,
+                    _ => 0,
+                }
+            }
+            Some(value) => value.udata_value().unwrap_or(0),
+            // The members of unions
+            None => 0,
+        };
+        Ok(Some(Member { name, t, offset }))
+    }
+
+    /// The discriminant and variants of a Rust enum. See DWARF v5 section 5.7.10.
+    fn process_variant_part(
+        &self,
+        node: EntriesTreeNode<Reader>,
+        dwarf: &Dwarf<Reader>,
+        unit: &Unit<Reader>,
+        base: SectionBase,
+    ) -> Result<VariantPart, gimli::Error> {
+        let discr = node.entry().attr_value(gimli::DW_AT_discr)?;
+        let mut discriminant = None;
+        let mut variants = Vec::new();
+
+        let mut children = node.children();
+        while let Some(child) = children.next()? {
+            let entry = child.entry();
+            match entry.tag() {
+                gimli::DW_TAG_member if discr == Some(gimli::AttributeValue::UnitRef(entry.offset())) => {
+                    discriminant = self.process_member(entry, dwarf, unit, base)?;
+                }
+                gimli::DW_TAG_variant => {
+                    let discr_value = match entry.attr_value(gimli::DW_AT_discr_value)? {
+                        Some(value) => value.udata_value().or(value.sdata_value().map(|v| v as u64)),
+                        None => None,
+                    };
+                    let mut members = child.children();
+                    while let Some(member) = members.next()? {
+                        if member.entry().tag() != gimli::DW_TAG_member {
+                            continue;
+                        }
+                        if let Some(member) = self.process_member(member.entry(), dwarf, unit, base)? {
+                            variants.push(Variant { discr_value, member });
+                            break;
+                        }
                     }
                 }
                 _ => {}
             }
         }
+        Ok(VariantPart { discriminant, variants })
+    }
 
-        if let Some(offset) = high_offset {
-            // 2.17.2 Contiguous Address Range
-            // if offset -> low + offset is one past the last instruction
-            // if addr -> high is the last instruction
-            high_pc = low_pc + offset - 1;
+    fn process_array_type(
+        &self,
+        entry: &DebuggingInformationEntry<Reader>,
+        unit: &Unit<Reader>,
+        base: SectionBase,
+    ) -> Result<Type, gimli::Error> {
+        let to = match entry.attr_value(gimli::DW_AT_type)? {
+            Some(value) => self.type_ref(value, unit, base).unwrap_or(0),
+            None => 0,
+        };
+        // One subrange per dimension, `int a[2][3]` has 6 elements.
+        let mut count = Some(1);
+        let mut tree = unit.entries_tree(Some(entry.offset()))?;
+        let mut children = tree.root()?.children();
+        while let Some(child) = children.next()? {
+            let subrange = child.entry();
+            if subrange.tag() != gimli::DW_TAG_subrange_type {
+                continue;
+            }
+            let length = match udata_attr(subrange, gimli::DW_AT_count)? {
+                Some(length) => Some(length),
+                None => {
+                    let lower_bound = udata_attr(subrange, gimli::DW_AT_lower_bound)?.unwrap_or(0);
+                    udata_attr(subrange, gimli::DW_AT_upper_bound)?
+                        .map(|upper_bound| (upper_bound + 1).saturating_sub(lower_bound))
+                }
+            };
+            count = count.zip(length).map(|(count, length)| count * length);
         }
+        Ok(Type::Array {
+            to,
+            count,
+            ref_addr: base.offset(entry.offset().to_unit_section_offset(unit)),
+        })
+    }
+
+    fn process_enumeration_type(
+        &self,
+        entry: &DebuggingInformationEntry<Reader>,
+        dwarf: &Dwarf<Reader>,
+        unit: &Unit<Reader>,
+        base: SectionBase,
+        name: String,
+    ) -> Result<Type, gimli::Error> {
+        let mut enumerators = Vec::new();
+        let mut tree = unit.entries_tree(Some(entry.offset()))?;
+        let mut children = tree.root()?.children();
+        while let Some(child) = children.next()? {
+            let enumerator = child.entry();
+            if enumerator.tag() != gimli::DW_TAG_enumerator {
+                continue;
+            }
+            let value = match enumerator.attr_value(gimli::DW_AT_const_value)? {
+                Some(gimli::AttributeValue::Sdata(value)) => value,
+                Some(value) => value.udata_value().unwrap_or(0) as i64,
+                None => continue,
+            };
+            let name = match enumerator.attr(gimli::DW_AT_name)? {
+                Some(attr) => self.resolve_dw_at_name(&attr, dwarf, unit).unwrap_or_default(),
+                None => String::new(),
+            };
+            enumerators.push((name, value));
+        }
+        Ok(Type::Enumeration {
+            name,
+            byte_size: udata_attr(entry, gimli::DW_AT_byte_size)?.unwrap_or(0),
+            enumerators,
+            ref_addr: base.offset(entry.offset().to_unit_section_offset(unit)),
+        })
+    }
 
+    fn process_subprogram(
+        &self,
+        entry: &DebuggingInformationEntry<Reader>,
+        dwarf: &Dwarf<Reader>,
+        unit: &Unit<Reader>,
+        base: SectionBase,
+    ) -> Result<Function, gimli::Error> {
+        let (name, t, _) = self.entry_attributes(entry, dwarf, unit, base)?;
+        // The linkage name has the namespaces and classes, like the names addr2line finds.
+        let name = match self.linkage_name(entry, dwarf, unit)? {
+            Some(linkage_name) => demangle::demangle_name(&linkage_name),
+            None => name,
+        };
         Ok(Function {
-            address_range: vec![(low_pc as usize, high_pc as usize)],
+            address_range: self.address_ranges(entry, dwarf, unit)?,
             formal_parameters: Vec::new(),
             local_variables: Vec::new(),
-            name: name,
-            t: t,
+            blocks: Vec::new(),
+            name,
+            t,
+            language: None,
+            frame_base: frame_base(entry, unit)?,
         })
     }
 
-    fn process_formal_parameter(
+    /// The pc ranges of a function or lexical block, low and high pc inclusive.
+    fn address_ranges(
         &self,
-        entry: &DebuggingInformationEntry<EndianSlice<RunTimeEndian>, usize>,
-        dwarf: &Dwarf<EndianSlice<RunTimeEndian>>,
-        encoding: gimli::Encoding,
-    ) -> Result<FormalParameter, gimli::Error> {
-        let mut name = String::new();
-        let mut t = 0;
-        let mut fbreg_offset = 0;
+        entry: &DebuggingInformationEntry<Reader>,
+        dwarf: &Dwarf<Reader>,
+        unit: &Unit<Reader>,
+    ) -> Result<Vec<(usize, usize)>, gimli::Error> {
+        let mut ranges = Vec::new();
+        // 2.17.3 Non-Contiguous Address Ranges, e.g. a function with a .cold part.
+        if let Some(value) = entry.attr_value(gimli::DW_AT_ranges)? {
+            if let Some(offset) = dwarf.attr_ranges_offset(unit, value)? {
+                let mut iter = dwarf.ranges(unit, offset)?;
+                while let Some(range) = iter.next()? {
+                    if range.end > range.begin {
+                        ranges.push((range.begin as usize, range.end as usize - 1));
+                    }
+                }
+            }
+            return Ok(ranges);
+        }
 
-        // println!("<{:x}> {}", entry.offset().0, entry.tag());
-        let mut attrs = entry.attrs();
-        while let Some(attr) = attrs.next()? {
-            // println!("   {}: {:?}", attr.name(), attr.value());
-            match attr.name() {
-                gimli::DW_AT_name => {
-                    name = self
-                        .resolve_dw_at_name(&attr, dwarf)
-                        .unwrap_or(String::new());
+        // Declarations have no code.
+        let low_pc = match entry.attr_value(gimli::DW_AT_low_pc)? {
+            // DW_FORM_addrx indexes .debug_addr, see attr_address.
+            Some(value) => match dwarf.attr_address(unit, value)? {
+                Some(v) => v,
+                None => {
+                    println!("could not read low_pc");
+                    return Ok(ranges);
                 }
-                gimli::DW_AT_type => {
-                    if let gimli::AttributeValue::UnitRef(gimli::UnitOffset(offset)) = attr.value()
-                    {
-                        t = offset;
-                    } else {
-                        println!("Could not get base_type offset for pointer type.");
-                    }
+            },
+            None => return Ok(ranges),
+        };
+        let high_pc = match entry.attr_value(gimli::DW_AT_high_pc)? {
+            // 2.17.2 Contiguous Address Range
+            // if offset -> low + offset is one past the last instruction
+            // if addr -> high is the last instruction
+            Some(gimli::AttributeValue::Udata(offset)) => low_pc + offset.max(1) - 1,
+            Some(value) => match dwarf.attr_address(unit, value)? {
+                Some(v) => v,
+                None => {
+                    println!("Unsupported high_pc format");
+                    low_pc
                 }
-                gimli::DW_AT_location => {
-                    if let gimli::AttributeValue::Exprloc(gimli::Expression(es)) = &mut attr.value()
-                    {
-                        match gimli::Operation::parse(es, encoding) {
-                            Ok(gimli::Operation::FrameOffset { offset }) => {
-                                fbreg_offset = offset;
-                            }
-                            _ => {
-                                println!("Could not parse DW_AT_location operation.");
-                            }
-                        }
-                    } else {
-                        println!("Could not interpret DW_AT_location");
-                    }
+            },
+            None => low_pc,
+        };
+        ranges.push((low_pc as usize, high_pc as usize));
+        Ok(ranges)
+    }
+
+    /// The location of a variable from its `DW_AT_location`, an expression or a location
+    /// list. Only `DW_OP_fbreg` locations are understood.
+    fn variable_location(
+        &self,
+        value: gimli::AttributeValue<Reader>,
+        dwarf: &Dwarf<Reader>,
+        unit: &Unit<Reader>,
+    ) -> Result<Option<VariableLocation>, gimli::Error> {
+        if let gimli::AttributeValue::Exprloc(gimli::Expression(mut es)) = value {
+            return Ok(match gimli::Operation::parse(&mut es, unit.encoding()) {
+                Ok(gimli::Operation::FrameOffset { offset }) => Some(VariableLocation::FrameOffset(offset)),
+                _ => {
+                    println!("Could not parse DW_AT_location operation.");
+                    None
                 }
-                _ => {}
+            });
+        }
+        // DW_FORM_sec_offset or DW_FORM_loclistx into .debug_loc or .debug_loclists.
+        let offset = match dwarf.attr_locations_offset(unit, value)? {
+            Some(offset) => offset,
+            None => {
+                println!("Could not interpret DW_AT_location");
+                return Ok(None);
             }
+        };
+        let mut entries = Vec::new();
+        let mut locations = dwarf.locations(unit, offset)?;
+        while let Some(location) = locations.next()? {
+            let mut es = location.data.0;
+            let offset = match gimli::Operation::parse(&mut es, unit.encoding()) {
+                Ok(gimli::Operation::FrameOffset { offset }) => Some(offset),
+                _ => None,
+            };
+            entries.push((location.range.begin as usize, location.range.end as usize, offset));
         }
+        Ok(Some(VariableLocation::List(entries)))
+    }
+
+    fn process_formal_parameter(
+        &self,
+        entry: &DebuggingInformationEntry<Reader>,
+        dwarf: &Dwarf<Reader>,
+        unit: &Unit<Reader>,
+        base: SectionBase,
+    ) -> Result<FormalParameter, gimli::Error> {
+        let (name, t, _) = self.entry_attributes(entry, dwarf, unit, base)?;
         Ok(FormalParameter {
             name,
             t,
-            fbreg_offset,
+            location: self.location(entry, dwarf, unit)?,
         })
     }
 
     fn process_variable(
         &self,
-        entry: &DebuggingInformationEntry<EndianSlice<RunTimeEndian>, usize>,
-        dwarf: &Dwarf<EndianSlice<RunTimeEndian>>,
-        encoding: gimli::Encoding,
+        entry: &DebuggingInformationEntry<Reader>,
+        dwarf: &Dwarf<Reader>,
+        unit: &Unit<Reader>,
+        base: SectionBase,
     ) -> Result<Variable, gimli::Error> {
-        let mut name = String::new();
-        let mut t = 0;
-        let mut fbreg_offset = 0;
+        let (name, t, _) = self.entry_attributes(entry, dwarf, unit, base)?;
+        Ok(Variable {
+            name,
+            t,
+            location: self.location(entry, dwarf, unit)?,
+        })
+    }
 
-        // println!("<{:x}> {}", entry.offset().0, entry.tag());
-        let mut attrs = entry.attrs();
-        while let Some(attr) = attrs.next()? {
-            // println!("   {}: {:?}", attr.name(), attr.value());
-            match attr.name() {
-                gimli::DW_AT_name => {
-                    name = self
-                        .resolve_dw_at_name(&attr, dwarf)
-                        .unwrap_or(String::new());
-                }
-                gimli::DW_AT_type => {
-                    if let gimli::AttributeValue::UnitRef(gimli::UnitOffset(offset)) = attr.value()
-                    {
-                        t = offset;
-                    } else {
-                        println!("Could not get base_type offset for pointer type.");
-                    }
+    /// The `DW_AT_location` of `entry`, `None` if it has none.
+    fn location(
+        &self,
+        entry: &DebuggingInformationEntry<Reader>,
+        dwarf: &Dwarf<Reader>,
+        unit: &Unit<Reader>,
+    ) -> Result<Option<VariableLocation>, gimli::Error> {
+        match entry.attr_value(gimli::DW_AT_location)? {
+            Some(value) => self.variable_location(value, dwarf, unit),
+            None => Ok(None),
+        }
+    }
+}
+
+impl TypeLookup for GimliWrapper {
+    fn get_type(&self, t: usize) -> Option<&Type> {
+        self.unit_data(self.unit_of_key(t)?).types.get(&t)
+    }
+}
+
+/// An `extern` declaration, which refers to the definition in another unit.
+struct Declaration {
+    name: String,
+    /// Name of the compilation unit with the declaration.
+    file: String,
+}
+
+/// Types are keyed by the offset of their DIE in `.debug_info`, which unlike the unit
+/// relative offsets of references is unique across units. `.debug_types` and the
+/// sections of split DWARF files are numbered as if they followed `.debug_info`.
+#[derive(Clone, Copy)]
+struct SectionBase {
+    debug_info: usize,
+    debug_types: usize,
+}
+
+impl SectionBase {
+    fn new(start: usize, dwarf: &Dwarf<Reader>) -> SectionBase {
+        SectionBase {
+            debug_info: start,
+            debug_types: start + dwarf.debug_info.reader().len(),
+        }
+    }
+
+    /// Where the sections of the next file start.
+    fn end(&self, dwarf: &Dwarf<Reader>) -> usize {
+        self.debug_types + dwarf.debug_types.reader().len()
+    }
+
+    fn offset(&self, offset: UnitSectionOffset) -> usize {
+        match offset {
+            UnitSectionOffset::DebugInfoOffset(offset) => self.debug_info + offset.0,
+            UnitSectionOffset::DebugTypesOffset(offset) => self.debug_types + offset.0,
+        }
+    }
+}
+
+/// The ranges of `functions` with their index, sorted by low pc. Functions the linker
+/// discarded are left at address 0.
+fn range_index<'a>(functions: impl Iterator<Item = &'a Function>) -> Vec<(usize, usize, usize)> {
+    let mut index: Vec<(usize, usize, usize)> = functions
+        .enumerate()
+        .flat_map(|(idx, function)| {
+            function
+                .address_range
+                .iter()
+                .filter(|(low_pc, _)| *low_pc != 0)
+                .map(move |(low_pc, high_pc)| (*low_pc, *high_pc, idx))
+        })
+        .collect();
+    index.sort();
+    index
+}
+
+/// The entry of a sorted range index containing `pc`.
+fn range_at(index: &[(usize, usize, usize)], pc: usize) -> Option<(usize, usize, usize)> {
+    let end = index.partition_point(|(low_pc, _, _)| *low_pc <= pc);
+    let (low_pc, high_pc, idx) = *index[..end].last()?;
+    (pc <= high_pc).then_some((low_pc, high_pc, idx))
+}
+
+/// The names of the functions and variables in `.debug_names` or `.gdb_index`, `None` if
+/// the file has neither.
+fn read_name_index(object: &object::File) -> Option<Result<NameIndex, String>> {
+    let section = |name| object.section_by_name(name)?.uncompressed_data().ok();
+    if let Some(data) = section(".debug_names") {
+        let debug_str = section(".debug_str").unwrap_or_default();
+        return Some(nameindex::read_debug_names(&data, &debug_str, splitdwarf::endian(object)));
+    }
+    Some(nameindex::read_gdb_index(&section(".gdb_index")?))
+}
+
+/// Add the names of the functions and variables `unit` defines to `names`, also those
+/// in namespaces, but not what is local to a function. Classes only have the declarations
+/// of their members, the definitions follow at namespace scope. The attributes of all
+/// other DIEs are skipped without reading them.
+fn scan_names(
+    dwarf: &Dwarf<Reader>,
+    unit: &Unit<Reader>,
+    names: &mut Vec<(NameKind, String)>,
+) -> Result<(), gimli::Error> {
+    let mut entries = unit.entries_raw(None)?;
+    if let Some(root) = entries.read_abbreviation()? {
+        entries.skip_attributes(root.attributes())?;
+    }
+    // Depths at which DIEs are in the unit or a namespace.
+    let mut scopes = vec![1];
+    while !entries.is_empty() {
+        let depth = entries.next_depth();
+        let abbreviation = match entries.read_abbreviation()? {
+            Some(abbreviation) => abbreviation,
+            None => continue,
+        };
+        while scopes.last().is_some_and(|scope| *scope > depth) {
+            scopes.pop();
+        }
+        let kind = match abbreviation.tag() {
+            _ if scopes.last() != Some(&depth) => None,
+            gimli::DW_TAG_subprogram => Some(NameKind::Function),
+            gimli::DW_TAG_variable => Some(NameKind::Variable),
+            gimli::DW_TAG_namespace | gimli::DW_TAG_module => {
+                if abbreviation.has_children() {
+                    scopes.push(depth + 1);
                 }
-                gimli::DW_AT_location => {
-                    if let gimli::AttributeValue::Exprloc(gimli::Expression(es)) = &mut attr.value()
-                    {
-                        match gimli::Operation::parse(es, encoding) {
-                            Ok(gimli::Operation::FrameOffset { offset }) => {
-                                fbreg_offset = offset;
-                            }
-                            _ => {
-                                println!("Could not parse DW_AT_location operation.");
-                            }
-                        }
-                    } else {
-                        println!("Could not interpret DW_AT_location");
-                    }
+                None
+            }
+            _ => None,
+        };
+        let kind = match kind {
+            Some(kind) => kind,
+            None => {
+                entries.skip_attributes(abbreviation.attributes())?;
+                continue;
+            }
+        };
+
+        let (mut name, mut origin, mut declaration) = (None, None, false);
+        for spec in abbreviation.attributes() {
+            let attr = entries.read_attribute(*spec)?;
+            match (attr.name(), attr.value()) {
+                (gimli::DW_AT_name, value) => name = Some(value),
+                // A definition separate from its declaration is named there.
+                (gimli::DW_AT_specification | gimli::DW_AT_abstract_origin, gimli::AttributeValue::UnitRef(offset)) => {
+                    origin = Some(offset)
                 }
+                (gimli::DW_AT_declaration, _) => declaration = true,
                 _ => {}
             }
         }
-        Ok(Variable {
-            name,
-            t,
-            fbreg_offset,
-        })
+        if let (None, Some(offset)) = (&name, origin) {
+            name = unit.entry(offset)?.attr_value(gimli::DW_AT_name)?;
+        }
+        if let (Some(name), false) = (name, declaration) {
+            names.push((kind, dwarf.attr_string(unit, name)?.to_string_lossy()?.into_owned()));
+        }
+    }
+    Ok(())
+}
+
+/// Full path of a file of a line program.
+fn file_path(
+    dwarf: &Dwarf<Reader>,
+    unit: &Unit<Reader>,
+    header: &LineProgramHeader<Reader>,
+    file: &FileEntry<Reader>,
+) -> Result<String, gimli::Error> {
+    let name = dwarf.attr_string(unit, file.path_name())?;
+    let mut path = std::path::PathBuf::new();
+    if let Some(comp_dir) = &unit.comp_dir {
+        path.push(comp_dir.to_string_lossy()?.as_ref());
+    }
+    if let Some(dir) = file.directory(header) {
+        path.push(dwarf.attr_string(unit, dir)?.to_string_lossy()?.as_ref());
+    }
+    path.push(name.to_string_lossy()?.as_ref());
+    Ok(path.to_string_lossy().to_string())
+}
+
+/// Path of the source file of a compilation unit, relative to its directory if that
+/// is unknown.
+fn unit_file_name(unit: &Unit<Reader>) -> Result<String, gimli::Error> {
+    let mut path = std::path::PathBuf::new();
+    if let Some(comp_dir) = &unit.comp_dir {
+        path.push(comp_dir.to_string_lossy()?.as_ref());
+    }
+    if let Some(name) = &unit.name {
+        let name = name.to_string_lossy()?;
+        path.push(name.trim_start_matches("./"));
+    }
+    Ok(path.to_string_lossy().to_string())
+}
+
+/// Call `f` with the entry the reference attribute `name` of `entry` refers to and its
+/// unit. A `DW_FORM_ref_addr` reference, as LTO emits for abstract origins, may be into
+/// another unit. `None` if `entry` has no such reference.
+fn referenced_entry<T>(
+    entry: &DebuggingInformationEntry<Reader>,
+    name: gimli::DwAt,
+    dwarf: &Dwarf<Reader>,
+    unit: &Unit<Reader>,
+    f: impl FnOnce(&DebuggingInformationEntry<Reader>, &Unit<Reader>) -> Result<T, gimli::Error>,
+) -> Result<Option<T>, gimli::Error> {
+    match entry.attr_value(name)? {
+        Some(gimli::AttributeValue::UnitRef(offset)) => f(&unit.entry(offset)?, unit).map(Some),
+        Some(gimli::AttributeValue::DebugInfoRef(offset)) => {
+            let mut headers = dwarf.units();
+            while let Some(header) = headers.next()? {
+                if let Some(unit_offset) = offset.to_unit_offset(&header) {
+                    let unit = dwarf.unit(header)?;
+                    return f(&unit.entry(unit_offset)?, &unit).map(Some);
+                }
+            }
+            Ok(None)
+        }
+        _ => Ok(None),
+    }
+}
+
+/// The compilation and type units of `.debug_info`, followed by those of `.debug_types`.
+fn all_units(dwarf: &Dwarf<Reader>) -> Result<Vec<Unit<Reader>>, gimli::Error> {
+    let mut units = Vec::new();
+    let mut headers = dwarf.units();
+    while let Some(header) = headers.next()? {
+        units.push(dwarf.unit(header)?);
+    }
+    let mut headers = dwarf.type_units();
+    while let Some(header) = headers.next()? {
+        units.push(dwarf.unit(header)?);
+    }
+    Ok(units)
+}
+
+/// The signature of a type unit and the offset of its type.
+fn type_signature(unit: &Unit<Reader>) -> Option<(DebugTypeSignature, UnitOffset)> {
+    match unit.header.type_() {
+        UnitType::Type { type_signature, type_offset }
+        | UnitType::SplitType { type_signature, type_offset } => Some((type_signature, type_offset)),
+        _ => None,
+    }
+}
+
+/// The `DW_AT_frame_base` of a function: a register if its expression is just
+/// `DW_OP_regN`, otherwise the CFA.
+fn frame_base(entry: &DebuggingInformationEntry<Reader>, unit: &Unit<Reader>) -> Result<FrameBase, gimli::Error> {
+    if let Some(gimli::AttributeValue::Exprloc(gimli::Expression(mut es))) = entry.attr_value(gimli::DW_AT_frame_base)? {
+        if let Ok(gimli::Operation::Register { register }) = gimli::Operation::parse(&mut es, unit.encoding()) {
+            return Ok(FrameBase::Register(register));
+        }
+    }
+    Ok(FrameBase::Cfa)
+}
+
+/// The value of an unsigned constant attribute like `DW_AT_byte_size`.
+fn udata_attr(entry: &DebuggingInformationEntry<Reader>, name: gimli::DwAt) -> Result<Option<u64>, gimli::Error> {
+    Ok(entry.attr_value(name)?.and_then(|value| value.udata_value()))
+}
+
+/// Compare a path from the line table with a user supplied path. Matches if one
+/// is a suffix of the other, starting at a path component.
+fn file_matches(row_file: &str, query: &str) -> bool {
+    let is_suffix = |long: &str, short: &str| {
+        long == short || (long.ends_with(short) && long[..long.len() - short.len()].ends_with('/'))
+    };
+    !query.is_empty() && (is_suffix(row_file, query) || is_suffix(query, row_file))
+}
+
+/// Short name of a DW_LANG constant the way gdb shows it, e.g. `c++` for DW_LANG_C_plus_plus_11.
+fn language_name(language: gimli::DwLang) -> Option<String> {
+    let name = language.static_string()?.strip_prefix("DW_LANG_")?;
+    Some(match name {
+        "C" | "C89" | "C99" | "C11" | "C17" => String::from("c"),
+        name if name.starts_with("C_plus_plus") => String::from("c++"),
+        name => name.to_lowercase(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_offset_at_pc() {
+        assert_eq!(VariableLocation::FrameOffset(-20).frame_offset(0x1234), Some(-20));
+
+        let list = VariableLocation::List(vec![(0x10, 0x20, Some(-24)), (0x20, 0x28, None), (0x30, 0x40, Some(-32))]);
+        assert_eq!(list.frame_offset(0x10), Some(-24));
+        assert_eq!(list.frame_offset(0x1f), Some(-24));
+        // In a register there.
+        assert_eq!(list.frame_offset(0x20), None);
+        // Not covered by any entry.
+        assert_eq!(list.frame_offset(0x2c), None);
+        assert_eq!(list.frame_offset(0x40), None);
+        assert_eq!(list.frame_offset(0x3f), Some(-32));
     }
 }
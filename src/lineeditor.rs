@@ -0,0 +1,199 @@
+use std::env;
+use std::path::PathBuf;
+
+use rustyline::completion::{Completer, Pair};
+use rustyline::config::{CompletionType, Config};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+
+use crate::replcommand::{self, COMMANDS};
+
+const HISTORY_FILE: &str = ".mini_dbg_history";
+const HISTORY_SIZE: usize = 1000;
+
+/// Names the completer offers besides the command names.
+/// Refreshed before every prompt because they depend on the state of the target.
+#[derive(Default)]
+pub struct Completions {
+    pub functions: Vec<String>,
+    pub source_files: Vec<String>,
+    /// Arguments and locals of the current frame.
+    pub variables: Vec<String>,
+    /// Address and number of every breakpoint.
+    pub breakpoints: Vec<(usize, u32)>,
+}
+
+struct ReplHelper {
+    completions: Completions,
+}
+
+/// Reads commands with line editing, history and tab completion.
+pub struct LineEditor {
+    editor: Editor<ReplHelper, DefaultHistory>,
+    history_path: Option<PathBuf>,
+}
+
+impl LineEditor {
+    pub fn create() -> Result<LineEditor, ReadlineError> {
+        let config = Config::builder()
+            .max_history_size(HISTORY_SIZE)?
+            .history_ignore_dups(true)?
+            .completion_type(CompletionType::List)
+            .build();
+        let mut editor = Editor::with_config(config)?;
+        editor.set_helper(Some(ReplHelper {
+            completions: Completions::default(),
+        }));
+
+        let history_path = env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE));
+        if let Some(path) = &history_path {
+            // The file doesn't exist on the first run.
+            editor.load_history(path).ok();
+        }
+        Ok(LineEditor {
+            editor,
+            history_path,
+        })
+    }
+
+    pub fn set_completions(&mut self, completions: Completions) {
+        if let Some(helper) = self.editor.helper_mut() {
+            helper.completions = completions;
+        }
+    }
+
+    /// Read the next line, `None` at the end of input or on Ctrl-D.
+    pub fn read_line(&mut self, prompt: &str) -> Option<String> {
+        loop {
+            match self.editor.readline(prompt) {
+                Ok(line) => {
+                    if !line.trim().is_empty() {
+                        self.editor.add_history_entry(line.as_str()).ok();
+                    }
+                    return Some(line);
+                }
+                // Ctrl-C only discards the current line.
+                Err(ReadlineError::Interrupted) => continue,
+                Err(_) => return None,
+            }
+        }
+    }
+
+    pub fn save_history(&mut self) {
+        if let Some(path) = &self.history_path {
+            if let Err(e) = self.editor.save_history(path) {
+                println!("Could not save history to {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+impl ReplHelper {
+    /// Candidates for an argument of `command`.
+    fn candidates(&self, command: &[&str], word: &str) -> Vec<Pair> {
+        let names = |names: &mut dyn Iterator<Item = &String>| -> Vec<Pair> {
+            names
+                .filter(|name| name.starts_with(word))
+                .map(|name| Pair {
+                    display: name.clone(),
+                    replacement: name.clone(),
+                })
+                .collect()
+        };
+        let completions = &self.completions;
+
+        // Resolve abbreviations and aliases like the parser does.
+        let mut specs = COMMANDS;
+        let mut spec = None;
+        for part in command {
+            if specs.is_empty() {
+                break;
+            }
+            match replcommand::find_command(specs, part, "") {
+                Ok(found) => {
+                    specs = found.subcommands;
+                    spec = Some(found);
+                }
+                Err(_) => return Vec::new(),
+            }
+        }
+        if !specs.is_empty() {
+            return command_pairs(specs.iter().map(|s| s.name), word);
+        }
+
+        match spec.map(|s| s.name) {
+            Some("break" | "dprintf") => {
+                // Source files complete to `file:` so the line number can follow.
+                let files: Vec<String> = completions
+                    .source_files
+                    .iter()
+                    .map(|file| format!("{}:", file))
+                    .collect();
+                let mut pairs = names(&mut completions.functions.iter());
+                pairs.extend(names(&mut files.iter()));
+                pairs
+            }
+            Some("disassemble") => names(&mut completions.functions.iter()),
+            Some("delete") => completions
+                .breakpoints
+                .iter()
+                .map(|(address, idx)| (idx.to_string(), address))
+                .filter(|(idx, _)| idx.starts_with(word))
+                .map(|(idx, address)| Pair {
+                    display: format!("{} (at {:#x})", idx, address),
+                    replacement: idx,
+                })
+                .collect(),
+            Some("help") => command_pairs(COMMANDS.iter().map(|s| s.name), word),
+            _ => names(&mut completions.variables.iter()),
+        }
+    }
+}
+
+/// Command names starting with `word`, followed by a space.
+fn command_pairs<'a>(names: impl Iterator<Item = &'a str>, word: &str) -> Vec<Pair> {
+    names
+        .filter(|name| name.starts_with(word))
+        .map(|name| Pair {
+            display: String::from(name),
+            replacement: format!("{} ", name),
+        })
+        .collect()
+}
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let line = &line[..pos];
+        let start = line.rfind(char::is_whitespace).map_or(0, |idx| idx + 1);
+        let word = &line[start..];
+        let previous: Vec<&str> = line[..start].split_whitespace().collect();
+
+        let candidates = if previous.is_empty() {
+            command_pairs(replcommand::command_names().into_iter(), word)
+        } else {
+            self.candidates(&previous, word)
+        };
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {}
+
+impl Validator for ReplHelper {}
+
+impl Helper for ReplHelper {}
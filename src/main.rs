@@ -1,15 +1,76 @@
+mod dap;
 mod debugger;
+mod debugfile;
 mod debuginfo;
+mod demangle;
+mod disassembler;
+mod dprintf;
+mod output;
+mod prettyprint;
 mod replcommand;
+mod solib;
+mod splitdwarf;
+mod symbolcache;
+mod symbols;
 mod target;
+mod tui;
 mod util;
 mod gimliwrapper;
+mod nameindex;
+mod lineeditor;
 
-use crate::debugger::Debugger;
+use crate::dap::DapServer;
+use crate::debugger::{Debugger, Script};
 
 fn main() {
-    println!("🚀 mini-dbg v0.1");
+    let mut target = String::from("a.out");
+    let mut dap = false;
+    let mut json = false;
+    let mut tui = false;
+    let mut batch = false;
+    let mut read_init_files = true;
+    let mut scripts = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--dap" => dap = true,
+            "--tui" => tui = true,
+            "--interpreter=json" => json = true,
+            "--interpreter=console" => json = false,
+            "--batch" => batch = true,
+            "-nx" => read_init_files = false,
+            "-x" => scripts.push(Script::File(args.next().expect("-x needs a FILE."))),
+            "-ex" => scripts.push(Script::Command(args.next().expect("-ex needs a COMMAND."))),
+            _ => target = arg,
+        }
+    }
 
-    let mut debugger = Debugger::create(String::from("a.out"));
-    debugger.run().unwrap();
+    if dap {
+        let mut server = DapServer::create().expect("Could not set up DAP server.");
+        server.run().unwrap();
+        return;
+    }
+
+    let mut debugger = Debugger::create(target);
+    for script in scripts {
+        debugger.add_script(script);
+    }
+    if !read_init_files {
+        debugger.skip_init_files();
+    }
+    if tui {
+        debugger.run_tui().unwrap();
+        return;
+    }
+    if json {
+        debugger
+            .use_json_interpreter()
+            .expect("Could not set up JSON interpreter.");
+    } else if !batch {
+        println!("🚀 mini-dbg v0.1");
+    }
+    if batch {
+        debugger.use_batch_mode();
+    }
+    std::process::exit(debugger.run());
 }
@@ -0,0 +1,344 @@
+use gimli::{EndianSlice, Format, Reader as _, RunTimeEndian};
+
+/// What kind of entity an index entry names.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NameKind {
+    Function,
+    Variable,
+    /// Any kind of entity, gold writes its `.gdb_index` without the kinds.
+    Unknown,
+}
+
+/// A function or variable defined in the compilation unit at `unit_offset` in `.debug_info`.
+pub struct IndexEntry {
+    pub name: String,
+    pub kind: NameKind,
+    pub unit_offset: usize,
+}
+
+/// The names of a `.gdb_index` or `.debug_names` section.
+#[derive(Default)]
+pub struct NameIndex {
+    /// Offsets in `.debug_info` of the compilation units the index covers, the other
+    /// units have to be scanned for their names.
+    pub units: Vec<usize>,
+    pub entries: Vec<IndexEntry>,
+}
+
+type Slice<'a> = EndianSlice<'a, RunTimeEndian>;
+
+/// Symbol kinds in the attributes of a `.gdb_index` CU vector entry.
+const GDB_INDEX_NONE: u32 = 0;
+const GDB_INDEX_VARIABLE: u32 = 2;
+const GDB_INDEX_FUNCTION: u32 = 3;
+
+/// Read the `.gdb_index` section gdb and linkers like gold (`--gdb-index`) write, versions
+/// 7 to 9, which have the kind of each symbol. Older versions are an error.
+pub fn read_gdb_index(data: &[u8]) -> Result<NameIndex, String> {
+    // The index is always little endian.
+    let version = Slice::new(data, RunTimeEndian::Little)
+        .read_u32()
+        .map_err(|_| String::from("truncated .gdb_index"))?;
+    if !(7..=9).contains(&version) {
+        return Err(format!("unsupported .gdb_index version {}", version));
+    }
+    read_gdb_index_tables(data, version).ok_or_else(|| String::from("malformed .gdb_index"))
+}
+
+fn read_gdb_index_tables(data: &[u8], version: u32) -> Option<NameIndex> {
+    let mut header = Slice::new(data, RunTimeEndian::Little);
+    header.skip(4).ok()?;
+    // Version 9 added the offset of a shortcut table between the symbol table and the
+    // constant pool.
+    let mut offsets = vec![0usize; if version >= 9 { 6 } else { 5 }];
+    for offset in &mut offsets {
+        *offset = header.read_u32().ok()? as usize;
+    }
+    let (cu_list, types_list, symbol_table, symbol_table_end) = (offsets[0], offsets[1], offsets[3], offsets[4]);
+    let constant_pool = *offsets.last()?;
+
+    let at = |offset: usize| data.get(offset..).map(|rest| Slice::new(rest, RunTimeEndian::Little));
+    let mut units = Vec::new();
+    let mut list = at(cu_list)?;
+    for _ in 0..types_list.checked_sub(cu_list)? / 16 {
+        units.push(list.read_u64().ok()? as usize);
+        list.read_u64().ok()?;
+    }
+
+    let mut entries = Vec::new();
+    let mut table = at(symbol_table)?;
+    for _ in 0..symbol_table_end.checked_sub(symbol_table)? / 8 {
+        let name_offset = table.read_u32().ok()? as usize;
+        let vector_offset = table.read_u32().ok()? as usize;
+        if name_offset == 0 && vector_offset == 0 {
+            continue;
+        }
+        let name = at(constant_pool + name_offset)?.read_null_terminated_slice().ok()?;
+        let name = name.to_string_lossy();
+        let mut vector = at(constant_pool + vector_offset)?;
+        for _ in 0..vector.read_u32().ok()? {
+            let value = vector.read_u32().ok()?;
+            let kind = match (value >> 28) & 7 {
+                GDB_INDEX_VARIABLE => NameKind::Variable,
+                GDB_INDEX_FUNCTION => NameKind::Function,
+                GDB_INDEX_NONE => NameKind::Unknown,
+                _ => continue,
+            };
+            // Indices past the CUs are type units.
+            if let Some(unit_offset) = units.get((value & 0xff_ffff) as usize) {
+                entries.push(IndexEntry {
+                    name: String::from(name.as_ref()),
+                    kind,
+                    unit_offset: *unit_offset,
+                });
+            }
+        }
+    }
+    Some(NameIndex { units, entries })
+}
+
+/// Attribute codes of `.debug_names` entries, DWARF 5 section 6.1.1.4.8.
+const DW_IDX_COMPILE_UNIT: u64 = 1;
+
+/// Read the DWARF 5 name index `.debug_names`, one or more name tables, for example one
+/// per compilation unit if the linker did not merge them. Names are in `debug_str`.
+pub fn read_debug_names(data: &[u8], debug_str: &[u8], endian: RunTimeEndian) -> Result<NameIndex, String> {
+    let malformed = || String::from("malformed .debug_names");
+    let mut section = Slice::new(data, endian);
+    let mut index = NameIndex::default();
+    while !section.is_empty() {
+        let (length, format) = section.read_initial_length().map_err(|_| malformed())?;
+        let mut table = section.split(length).map_err(|_| malformed())?;
+        let version = table.read_u16().map_err(|_| malformed())?;
+        if version != 5 {
+            return Err(format!("unsupported .debug_names version {}", version));
+        }
+        read_name_table(&mut table, format, debug_str, endian, &mut index).ok_or_else(malformed)?;
+    }
+    Ok(index)
+}
+
+/// Read a name table after its version.
+fn read_name_table(
+    table: &mut Slice,
+    format: Format,
+    debug_str: &[u8],
+    endian: RunTimeEndian,
+    index: &mut NameIndex,
+) -> Option<()> {
+    table.read_u16().ok()?;
+    let comp_unit_count = table.read_u32().ok()? as usize;
+    let local_type_unit_count = table.read_u32().ok()? as usize;
+    let foreign_type_unit_count = table.read_u32().ok()? as usize;
+    let bucket_count = table.read_u32().ok()? as usize;
+    let name_count = table.read_u32().ok()? as usize;
+    let abbrev_table_size = table.read_u32().ok()? as usize;
+    let augmentation_string_size = table.read_u32().ok()? as usize;
+    table.skip(augmentation_string_size).ok()?;
+
+    let mut units = Vec::new();
+    for _ in 0..comp_unit_count {
+        units.push(table.read_offset(format).ok()?);
+    }
+    let offset_size = format.word_size() as usize;
+    table
+        .skip(local_type_unit_count * offset_size + foreign_type_unit_count * 8 + bucket_count * 4)
+        .ok()?;
+    if bucket_count > 0 {
+        table.skip(name_count * 4).ok()?;
+    }
+    let mut string_offsets = table.split(name_count * offset_size).ok()?;
+    let mut entry_offsets = table.split(name_count * offset_size).ok()?;
+    let abbreviations = read_abbreviations(&mut table.split(abbrev_table_size).ok()?)?;
+    let pool = *table;
+
+    for _ in 0..name_count {
+        let string_offset = string_offsets.read_offset(format).ok()?;
+        let entry_offset = entry_offsets.read_offset(format).ok()?;
+        let name = Slice::new(debug_str.get(string_offset..)?, endian)
+            .read_null_terminated_slice()
+            .ok()?;
+        let name = name.to_string_lossy();
+        let mut entry = pool;
+        entry.skip(entry_offset).ok()?;
+        // The entries of a name end with abbreviation code 0.
+        loop {
+            let code = entry.read_uleb128().ok()?;
+            if code == 0 {
+                break;
+            }
+            let (tag, attributes) = abbreviations.iter().find(|(c, _, _)| *c == code).map(|(_, t, a)| (t, a))?;
+            // Without DW_IDX_compile_unit an entry belongs to the only unit of the table.
+            let mut unit = (units.len() == 1).then_some(0);
+            for (index, form) in attributes {
+                let value = read_form(&mut entry, *form, format)?;
+                if *index == DW_IDX_COMPILE_UNIT {
+                    unit = Some(value as usize);
+                }
+            }
+            let kind = match gimli::DwTag(*tag as u16) {
+                gimli::DW_TAG_subprogram => NameKind::Function,
+                gimli::DW_TAG_variable => NameKind::Variable,
+                _ => continue,
+            };
+            if let Some(unit_offset) = unit.and_then(|unit| units.get(unit)) {
+                index.entries.push(IndexEntry {
+                    name: String::from(name.as_ref()),
+                    kind,
+                    unit_offset: *unit_offset,
+                });
+            }
+        }
+    }
+    index.units.extend(units);
+    Some(())
+}
+
+/// Code, tag and the index attributes with their form of an abbreviation of a name table.
+type Abbreviation = (u64, u64, Vec<(u64, u64)>);
+
+fn read_abbreviations(table: &mut Slice) -> Option<Vec<Abbreviation>> {
+    let mut abbreviations = Vec::new();
+    loop {
+        let code = table.read_uleb128().ok()?;
+        if code == 0 {
+            return Some(abbreviations);
+        }
+        let tag = table.read_uleb128().ok()?;
+        let mut attributes = Vec::new();
+        loop {
+            let index = table.read_uleb128().ok()?;
+            let form = table.read_uleb128().ok()?;
+            if index == 0 && form == 0 {
+                break;
+            }
+            attributes.push((index, form));
+        }
+        abbreviations.push((code, tag, attributes));
+    }
+}
+
+/// The value of an entry attribute, 0 for forms that are not a number.
+fn read_form(entry: &mut Slice, form: u64, format: Format) -> Option<u64> {
+    let form = gimli::DwForm(form as u16);
+    Some(match form {
+        gimli::DW_FORM_flag_present => 0,
+        gimli::DW_FORM_data1 | gimli::DW_FORM_ref1 | gimli::DW_FORM_flag => entry.read_u8().ok()? as u64,
+        gimli::DW_FORM_data2 | gimli::DW_FORM_ref2 => entry.read_u16().ok()? as u64,
+        gimli::DW_FORM_data4 | gimli::DW_FORM_ref4 => entry.read_u32().ok()? as u64,
+        gimli::DW_FORM_data8 | gimli::DW_FORM_ref8 | gimli::DW_FORM_ref_sig8 => entry.read_u64().ok()?,
+        gimli::DW_FORM_udata | gimli::DW_FORM_ref_udata => entry.read_uleb128().ok()?,
+        gimli::DW_FORM_sdata => entry.read_sleb128().ok()? as u64,
+        gimli::DW_FORM_data16 => {
+            entry.skip(16).ok()?;
+            0
+        }
+        gimli::DW_FORM_sec_offset => entry.read_offset(format).ok()? as u64,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(index: &NameIndex) -> Vec<(&str, NameKind, usize)> {
+        index.entries.iter().map(|e| (e.name.as_str(), e.kind, e.unit_offset)).collect()
+    }
+
+    fn u32s(values: &[u32]) -> Vec<u8> {
+        values.iter().flat_map(|v| v.to_le_bytes()).collect()
+    }
+
+    /// A `.gdb_index` with two CUs, at 0 and 0x6a, and the name `main`, a function of the
+    /// first and a variable of the second.
+    fn gdb_index(version: u32) -> Vec<u8> {
+        let header_size = if version >= 9 { 28 } else { 24 };
+        let cu_list = header_size;
+        let symbol_table = cu_list + 32;
+        let shortcut_table = symbol_table + 16;
+        let constant_pool = if version >= 9 { shortcut_table + 8 } else { shortcut_table };
+        let mut data = u32s(&[version, cu_list, symbol_table, symbol_table, symbol_table]);
+        if version >= 9 {
+            data.extend(u32s(&[shortcut_table]));
+        }
+        data.extend(u32s(&[constant_pool]));
+        data.extend([0u64, 0x6a, 0x6a, 0x30].iter().flat_map(|v| v.to_le_bytes()));
+        // Two slots, the second one empty, the vector first in the pool, then the name.
+        data.extend(u32s(&[12, 0, 0, 0]));
+        if version >= 9 {
+            data.extend(u32s(&[1, 2]));
+        }
+        data.extend(u32s(&[2, GDB_INDEX_FUNCTION << 28, GDB_INDEX_VARIABLE << 28 | 1]));
+        data.extend(b"main\0");
+        data
+    }
+
+    #[test]
+    fn gdb_index_versions() {
+        for version in [7, 8, 9] {
+            let index = read_gdb_index(&gdb_index(version)).unwrap();
+            assert_eq!(index.units, [0, 0x6a]);
+            assert_eq!(names(&index), [("main", NameKind::Function, 0), ("main", NameKind::Variable, 0x6a)]);
+        }
+        assert_eq!(read_gdb_index(&gdb_index(6)).err().unwrap(), "unsupported .gdb_index version 6");
+        assert_eq!(read_gdb_index(&gdb_index(10)).err().unwrap(), "unsupported .gdb_index version 10");
+        assert_eq!(read_gdb_index(&[7, 0]).err().unwrap(), "truncated .gdb_index");
+        assert_eq!(read_gdb_index(&gdb_index(7)[..40]).err().unwrap(), "malformed .gdb_index");
+    }
+
+    /// A DWARF 5 name table for `units` with the names at the offsets of `names` in
+    /// `.debug_str` and their entries, abbreviation codes and values of DW_IDX_compile_unit.
+    /// Code 1 is a function, 2 a variable, 3 a type and 4 a function without the unit.
+    fn name_table(version: u16, units: &[u32], names: &[(u32, &[(u8, u8)])]) -> Vec<u8> {
+        let abbreviations = [1, 0x2e, 1, 0x0b, 0, 0, 2, 0x34, 1, 0x0b, 0, 0, 3, 0x13, 1, 0x0b, 0, 0, 4, 0x2e, 0, 0, 0];
+        let mut pool = Vec::new();
+        let mut entry_offsets = Vec::new();
+        for (_, entries) in names {
+            entry_offsets.push(pool.len() as u32);
+            for (code, unit) in *entries {
+                pool.push(*code);
+                if *code != 4 {
+                    pool.push(*unit);
+                }
+            }
+            pool.push(0);
+        }
+        let mut table = version.to_le_bytes().to_vec();
+        table.extend([0, 0]);
+        table.extend(u32s(&[units.len() as u32, 0, 0, 0, names.len() as u32, abbreviations.len() as u32, 0]));
+        table.extend(u32s(units));
+        table.extend(u32s(&names.iter().map(|(offset, _)| *offset).collect::<Vec<_>>()));
+        table.extend(u32s(&entry_offsets));
+        table.extend(abbreviations);
+        table.extend(pool);
+        let mut data = u32s(&[table.len() as u32]);
+        data.extend(table);
+        data
+    }
+
+    #[test]
+    fn debug_names_tables() {
+        let debug_str = b"main\0counter\0helper\0";
+        let mut data = name_table(5, &[0, 0x40], &[(0, &[(1, 0)]), (5, &[(3, 0), (2, 1)])]);
+        // A second table of one unit, its entries don't need the unit.
+        data.extend(name_table(5, &[0x80], &[(13, &[(4, 0)])]));
+        let index = read_debug_names(&data, debug_str, RunTimeEndian::Little).unwrap();
+        assert_eq!(index.units, [0, 0x40, 0x80]);
+        assert_eq!(
+            names(&index),
+            [("main", NameKind::Function, 0), ("counter", NameKind::Variable, 0x40), ("helper", NameKind::Function, 0x80)]
+        );
+
+        let old = name_table(4, &[0], &[(0, &[(1, 0)])]);
+        assert_eq!(
+            read_debug_names(&old, debug_str, RunTimeEndian::Little).err().unwrap(),
+            "unsupported .debug_names version 4"
+        );
+        let truncated = &data[..data.len() - 1];
+        assert_eq!(
+            read_debug_names(truncated, debug_str, RunTimeEndian::Little).err().unwrap(),
+            "malformed .debug_names"
+        );
+    }
+}
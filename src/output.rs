@@ -0,0 +1,665 @@
+use std::fmt::{self, Display};
+
+use serde_json::{json, Value};
+
+use crate::debuginfo::{Location, SourceLine};
+use crate::symbolcache::CacheEntry;
+
+pub struct BreakpointInfo {
+    pub idx: u32,
+    pub address: usize,
+    /// Number of addresses of the breakpoint, more than one for an inlined function.
+    pub locations: usize,
+    pub location: Option<Location>,
+    pub commands: Vec<String>,
+    /// Format and arguments if this is a dprintf.
+    pub dprintf: Option<(String, Vec<String>)>,
+    /// The function of a breakpoint that waits for a shared library to be loaded.
+    pub pending: Option<String>,
+    /// The watched expression if this is a watchpoint.
+    pub watch: Option<String>,
+}
+
+pub struct RegisterInfo {
+    pub name: &'static str,
+    pub value: u64,
+    /// Memory rbp and rsp point to, `None` if it can't be read.
+    pub pointee: Option<u64>,
+}
+
+pub struct VariableInfo {
+    pub name: String,
+    pub type_name: String,
+    /// The first word of the value, `None` if the variable is optimized out.
+    pub value: Option<u64>,
+    /// The value formatted according to its type.
+    pub display: String,
+}
+
+pub struct FrameInfo {
+    pub level: usize,
+    pub address: usize,
+    /// True for the virtual frame of an inlined call.
+    pub inlined: bool,
+    pub location: Location,
+    pub arguments: Vec<VariableInfo>,
+    pub locals: Vec<VariableInfo>,
+}
+
+/// What `info frame` shows about a frame. Addresses are absolute.
+pub struct FrameDetails {
+    pub level: usize,
+    /// True for the virtual frame of an inlined call, which is part of the next frame.
+    pub inlined: bool,
+    pub rip: usize,
+    pub location: Location,
+    pub cfa: usize,
+    /// Return address stored in this frame, `None` if it can't be read.
+    pub saved_rip: Option<u64>,
+    /// CFA of the frame this one called, `None` for the innermost frame.
+    pub callee_cfa: Option<usize>,
+    /// CFA of the frame that called this one, `None` for the outermost frame.
+    pub caller_cfa: Option<usize>,
+    pub language: Option<String>,
+    /// Registers saved by the frame and their addresses.
+    pub saved_registers: Vec<(&'static str, usize)>,
+}
+
+/// The ELF symbol an address belongs to, see `info symbol`.
+pub struct SymbolInfo {
+    pub name: String,
+    pub offset: usize,
+    pub section: String,
+}
+
+/// A shared object loaded by the dynamic linker, see `info sharedlibrary`.
+pub struct SharedLibraryInfo {
+    /// Address range of its segments.
+    pub from: usize,
+    pub to: usize,
+    /// True if there is DWARF for it, not just symbols.
+    pub has_debug_info: bool,
+    pub path: String,
+}
+
+/// A function found by `info functions`.
+pub struct FunctionInfo {
+    pub name: String,
+    pub address: usize,
+    /// Source file and line of functions with debug information.
+    pub source: Option<(String, u32)>,
+}
+
+pub struct InstructionInfo {
+    pub address: usize,
+    pub bytes: Vec<u8>,
+    pub text: String,
+    /// The address relative to its function, e.g. `main+0x4`.
+    pub symbol: Option<String>,
+    /// Symbol of the destination of a call or jump.
+    pub branch_target: Option<String>,
+    /// True for the instruction rip points to.
+    pub current: bool,
+    pub breakpoint: Option<u32>,
+    /// Source lines to show before this instruction.
+    pub source: Vec<SourceLine>,
+}
+
+pub enum StopReason {
+    Breakpoint(u32),
+    /// A watchpoint with the formatted values before and after the change.
+    Watchpoint {
+        idx: u32,
+        expression: String,
+        old: String,
+        new: String,
+    },
+    Signal(String),
+    Step,
+}
+
+pub enum StopEvent {
+    Exited {
+        exit_code: i32,
+    },
+    Terminated {
+        signal: String,
+    },
+    Stopped {
+        reason: StopReason,
+        address: usize,
+        location: Option<Location>,
+        source: Vec<SourceLine>,
+    },
+}
+
+/// Structured result of a REPL command. The console prints it for humans,
+/// `--interpreter=json` serializes it.
+pub enum Output {
+    BreakpointSet(BreakpointInfo),
+    BreakpointExists(BreakpointInfo),
+    BreakpointDeleted(BreakpointInfo),
+    Breakpoints(Vec<BreakpointInfo>),
+    Registers(Vec<RegisterInfo>),
+    Backtrace(Vec<FrameInfo>),
+    /// The selected frame and its current source line.
+    Frame {
+        frame: FrameInfo,
+        source: Vec<SourceLine>,
+    },
+    FrameDetails(FrameDetails),
+    Variables(Vec<VariableInfo>),
+    Symbol(SymbolInfo),
+    Functions(Vec<FunctionInfo>),
+    SharedLibraries(Vec<SharedLibraryInfo>),
+    /// The files of the symbol cache in `directory`.
+    SymbolCache {
+        directory: String,
+        entries: Vec<CacheEntry>,
+    },
+    Value(VariableInfo),
+    Disassembly(Vec<InstructionInfo>),
+    Stopped(StopEvent),
+    Help(String),
+    Message(String),
+    /// Blank input without a command to repeat.
+    Nothing,
+}
+
+impl Display for BreakpointInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.dprintf {
+            Some(_) => "Dprintf",
+            None => "Breakpoint",
+        };
+        if let Some(expression) = &self.watch {
+            write!(f, "Watchpoint {}: {}", self.idx, expression)
+        } else if let Some(function) = &self.pending {
+            write!(f, "{} {} ({}) pending", kind, self.idx, function)
+        } else if let Some(location) = &self.location {
+            write!(f, "{} {} at {}", kind, self.idx, location)
+        } else {
+            write!(f, "{} {} at {:#x}", kind, self.idx, self.address)
+        }
+    }
+}
+
+impl BreakpointInfo {
+    fn to_json(&self) -> Value {
+        json!({
+            "number": self.idx,
+            "address": format!("{:#x}", self.address),
+            "locations": self.locations,
+            "location": self.location.as_ref().map(location_json),
+            "commands": self.commands,
+            "pending": self.pending,
+            "watch": self.watch,
+            "dprintf": self.dprintf.as_ref().map(|(format, arguments)| json!({
+                "format": format,
+                "arguments": arguments,
+            })),
+        })
+    }
+}
+
+impl VariableInfo {
+    fn to_json(&self) -> Value {
+        json!({
+            "name": self.name,
+            "type": self.type_name,
+            "value": self.display,
+            "raw": self.value.map(|value| format!("{:#x}", value)),
+        })
+    }
+}
+
+impl FrameInfo {
+    fn to_json(&self) -> Value {
+        json!({
+            "level": self.level,
+            "address": format!("{:#x}", self.address),
+            "inlined": self.inlined,
+            "location": location_json(&self.location),
+            "arguments": self.arguments.iter().map(VariableInfo::to_json).collect::<Vec<_>>(),
+            "locals": self.locals.iter().map(VariableInfo::to_json).collect::<Vec<_>>(),
+        })
+    }
+}
+
+impl Display for FrameDetails {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Stack level {}, frame at {:#x}:", self.level, self.cfa)?;
+        if self.inlined {
+            writeln!(f, " inlined into frame {}", self.level + 1)?;
+        }
+        let file_name = self.location.file.rsplit('/').next().unwrap_or("");
+        write!(
+            f,
+            " rip = {:#x} in {} ({}:{});",
+            self.rip, self.location.function_name, file_name, self.location.line
+        )?;
+        match self.saved_rip {
+            Some(saved_rip) => writeln!(f, " saved rip = {:#x}", saved_rip)?,
+            None => writeln!(f, " saved rip = <not saved>")?,
+        }
+        if let Some(cfa) = self.caller_cfa {
+            writeln!(f, " called by frame at {:#x}", cfa)?;
+        }
+        if let Some(cfa) = self.callee_cfa {
+            writeln!(f, " caller of frame at {:#x}", cfa)?;
+        }
+        if let Some(language) = &self.language {
+            writeln!(f, " source language {}.", language)?;
+        }
+        writeln!(f, " Saved registers:")?;
+        let registers: Vec<String> = self
+            .saved_registers
+            .iter()
+            .map(|(name, address)| format!("{} at {:#x}", name, address))
+            .collect();
+        writeln!(f, "  {}", registers.join(", "))
+    }
+}
+
+impl FrameDetails {
+    fn to_json(&self) -> Value {
+        let saved_registers: serde_json::Map<String, Value> = self
+            .saved_registers
+            .iter()
+            .map(|(name, address)| (String::from(*name), json!(format!("{:#x}", address))))
+            .collect();
+        json!({
+            "level": self.level,
+            "inlined": self.inlined,
+            "rip": format!("{:#x}", self.rip),
+            "location": location_json(&self.location),
+            "cfa": format!("{:#x}", self.cfa),
+            "saved_rip": self.saved_rip.map(|rip| format!("{:#x}", rip)),
+            "callee_cfa": self.callee_cfa.map(|cfa| format!("{:#x}", cfa)),
+            "caller_cfa": self.caller_cfa.map(|cfa| format!("{:#x}", cfa)),
+            "language": self.language,
+            "saved_registers": saved_registers,
+        })
+    }
+}
+
+impl Display for InstructionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.source {
+            writeln!(f, "{}\t{}", line.line, line.text)?;
+        }
+        let breakpoint = if self.breakpoint.is_some() { "●" } else { " " };
+        let current = if self.current { "⇒" } else { " " };
+        write!(f, "{}{} {:#x}", breakpoint, current, self.address)?;
+        if let Some(symbol) = &self.symbol {
+            write!(f, " <{}>", symbol)?;
+        }
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        write!(f, ":\t{:<24} {}", bytes.join(" "), self.text)?;
+        if let Some(target) = &self.branch_target {
+            write!(f, " <{}>", target)?;
+        }
+        Ok(())
+    }
+}
+
+impl InstructionInfo {
+    fn to_json(&self) -> Value {
+        json!({
+            "address": format!("{:#x}", self.address),
+            "bytes": self.bytes,
+            "instruction": self.text,
+            "symbol": self.symbol,
+            "branch_target": self.branch_target,
+            "current": self.current,
+            "breakpoint": self.breakpoint,
+            "source": self.source.iter().map(source_line_json).collect::<Vec<_>>(),
+        })
+    }
+}
+
+impl Display for StopEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StopEvent::Exited { exit_code } => {
+                writeln!(f, "Program exited with code {}", exit_code)
+            }
+            StopEvent::Terminated { signal } => {
+                writeln!(f, "Program terminated with signal {}", signal)
+            }
+            StopEvent::Stopped {
+                reason,
+                address,
+                location,
+                source,
+            } => {
+                if let StopReason::Watchpoint { idx, expression, old, new } = reason {
+                    writeln!(f, "Watchpoint {}: {}", idx, expression)?;
+                    writeln!(f)?;
+                    writeln!(f, "Old value = {}", old)?;
+                    writeln!(f, "New value = {}", new)?;
+                }
+                match location {
+                    Some(location) => write!(f, "{}", location)?,
+                    None => write!(f, "{:#x} in ??", address)?,
+                }
+                match reason {
+                    StopReason::Signal(signal) if signal == "SIGSEGV" => {
+                        writeln!(f, " 🔥 Segmentation Fault 🔥:")?;
+                    }
+                    StopReason::Signal(signal) => writeln!(f, " Program received {}:", signal)?,
+                    _ => writeln!(f)?,
+                }
+                for line in source {
+                    writeln!(f, "{}\t{}", if line.current { "⇒" } else { "  " }, line.text)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl StopEvent {
+    pub fn to_json(&self) -> Value {
+        match self {
+            StopEvent::Exited { exit_code } => {
+                json!({ "type": "event", "event": "exited", "exit_code": exit_code })
+            }
+            StopEvent::Terminated { signal } => {
+                json!({ "type": "event", "event": "terminated", "signal": signal })
+            }
+            StopEvent::Stopped {
+                reason,
+                address,
+                location,
+                source,
+            } => {
+                let source: Vec<Value> = source.iter().map(source_line_json).collect();
+                let mut event = json!({
+                    "type": "event",
+                    "event": "stopped",
+                    "address": format!("{:#x}", address),
+                    "location": location.as_ref().map(location_json),
+                    "source": source,
+                });
+                match reason {
+                    StopReason::Breakpoint(idx) => {
+                        event["reason"] = json!("breakpoint");
+                        event["breakpoint"] = json!(idx);
+                    }
+                    StopReason::Watchpoint { idx, expression, old, new } => {
+                        event["reason"] = json!("watchpoint");
+                        event["watchpoint"] = json!(idx);
+                        event["expression"] = json!(expression);
+                        event["old_value"] = json!(old);
+                        event["new_value"] = json!(new);
+                    }
+                    StopReason::Signal(signal) => {
+                        event["reason"] = json!("signal");
+                        event["signal"] = json!(signal);
+                    }
+                    StopReason::Step => event["reason"] = json!("step"),
+                }
+                event
+            }
+        }
+    }
+}
+
+impl Display for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Output::BreakpointSet(bp) if bp.locations > 1 => {
+                writeln!(f, "{} ({} locations)", bp, bp.locations)
+            }
+            Output::BreakpointSet(bp) => {
+                if let Some(function) = &bp.pending {
+                    writeln!(
+                        f,
+                        "Function \"{}\" not found, the shared libraries are not loaded yet.",
+                        function
+                    )?;
+                }
+                writeln!(f, "{}", bp)
+            }
+            Output::BreakpointExists(bp) => {
+                writeln!(f, "Breakpoint {} at {:#x} already exists.", bp.idx, bp.address)
+            }
+            Output::BreakpointDeleted(bp) => writeln!(f, "{} deleted.", bp),
+            Output::Breakpoints(bps) => {
+                for bp in bps {
+                    writeln!(f, "{}", bp)?;
+                    if let Some((format, arguments)) = &bp.dprintf {
+                        write!(f, "        printf {:?}", format)?;
+                        for argument in arguments {
+                            write!(f, ",{}", argument)?;
+                        }
+                        writeln!(f)?;
+                    }
+                    for command in &bp.commands {
+                        writeln!(f, "        {}", command)?;
+                    }
+                }
+                Ok(())
+            }
+            Output::Registers(registers) => {
+                for register in registers {
+                    write!(f, "{}\t{:#18x}", register.name, register.value)?;
+                    if register.name == "rbp" || register.name == "rsp" {
+                        match register.pointee {
+                            Some(pointee) => write!(f, "\t-> {:#18x}", pointee)?,
+                            None => write!(f, "\t-> <invalid>")?,
+                        }
+                    }
+                    writeln!(f)?;
+                }
+                Ok(())
+            }
+            Output::Backtrace(frames) => {
+                writeln!(f, "Backtrace:")?;
+                for frame in frames {
+                    let inlined = if frame.inlined { " [inlined]" } else { "" };
+                    writeln!(f, "{} {}{}", frame.level, frame.location, inlined)?;
+                    for var in frame.arguments.iter().chain(frame.locals.iter()) {
+                        match var.value {
+                            Some(value) => writeln!(f, "{} = {:#18x}", var.name, value)?,
+                            None => writeln!(f, "{} = {}", var.name, var.display)?,
+                        }
+                    }
+                }
+                Ok(())
+            }
+            Output::Frame { frame, source } => {
+                let inlined = if frame.inlined { " [inlined]" } else { "" };
+                writeln!(f, "#{} {}{}", frame.level, frame.location, inlined)?;
+                for line in source {
+                    writeln!(f, "{}\t{}", if line.current { "⇒" } else { "  " }, line.text)?;
+                }
+                Ok(())
+            }
+            Output::FrameDetails(details) => write!(f, "{}", details),
+            Output::Variables(variables) => {
+                for var in variables {
+                    writeln!(f, "{} = {}", var.name, var.display)?;
+                }
+                Ok(())
+            }
+            Output::Symbol(symbol) => {
+                if symbol.offset == 0 {
+                    write!(f, "{}", symbol.name)?;
+                } else {
+                    write!(f, "{}+{:#x}", symbol.name, symbol.offset)?;
+                }
+                writeln!(f, " in section {}", symbol.section)
+            }
+            Output::Functions(functions) => {
+                let mut file = None;
+                for function in functions {
+                    if let Some((path, line)) = &function.source {
+                        if file != Some(path) {
+                            let file_name = path.rsplit('/').next().unwrap_or(path);
+                            writeln!(f, "File {}:", file_name)?;
+                            file = Some(path);
+                        }
+                        writeln!(f, "{}:\t{}", line, function.name)?;
+                    }
+                }
+                let mut non_debugging = functions.iter().filter(|f| f.source.is_none()).peekable();
+                if non_debugging.peek().is_some() {
+                    if file.is_some() {
+                        writeln!(f)?;
+                    }
+                    writeln!(f, "Non-debugging symbols:")?;
+                    for function in non_debugging {
+                        writeln!(f, "{:#018x}  {}", function.address, function.name)?;
+                    }
+                }
+                Ok(())
+            }
+            Output::SharedLibraries(libraries) => {
+                if libraries.is_empty() {
+                    return writeln!(f, "No shared libraries loaded at this time.");
+                }
+                writeln!(f, "{:<20}{:<20}{:<12}Shared Object Library", "From", "To", "Syms Read")?;
+                for library in libraries {
+                    let read = if library.has_debug_info { "Yes" } else { "Yes (*)" };
+                    writeln!(
+                        f,
+                        "{:<#20x}{:<#20x}{:<12}{}",
+                        library.from, library.to, read, library.path
+                    )?;
+                }
+                if libraries.iter().any(|library| !library.has_debug_info) {
+                    writeln!(f, "(*): Shared library is missing debugging information.")?;
+                }
+                Ok(())
+            }
+            Output::SymbolCache { directory, entries } => {
+                if entries.is_empty() {
+                    return writeln!(f, "The symbol cache in {} is empty.", directory);
+                }
+                writeln!(f, "Symbol cache in {}:", directory)?;
+                writeln!(f, "{:<12}{:<52}Binary", "Size", "Key")?;
+                for entry in entries {
+                    writeln!(f, "{:<12}{:<52}{}", entry.size, entry.key, entry.binary)?;
+                }
+                Ok(())
+            }
+            Output::Value(var) => writeln!(f, "{} = {}", var.name, var.display),
+            Output::Disassembly(instructions) => {
+                for instruction in instructions {
+                    writeln!(f, "{}", instruction)?;
+                }
+                Ok(())
+            }
+            Output::Stopped(event) => write!(f, "{}", event),
+            Output::Help(text) => write!(f, "{}", text),
+            Output::Message(text) => writeln!(f, "{}", text),
+            Output::Nothing => Ok(()),
+        }
+    }
+}
+
+impl Output {
+    /// The `result` field of a JSON command response.
+    pub fn to_json(&self) -> Value {
+        match self {
+            Output::BreakpointSet(bp) | Output::BreakpointExists(bp) | Output::BreakpointDeleted(bp) => {
+                json!({ "breakpoint": bp.to_json() })
+            }
+            Output::Breakpoints(bps) => {
+                json!({ "breakpoints": bps.iter().map(BreakpointInfo::to_json).collect::<Vec<_>>() })
+            }
+            Output::Registers(registers) => {
+                let registers: serde_json::Map<String, Value> = registers
+                    .iter()
+                    .map(|r| (String::from(r.name), json!(format!("{:#x}", r.value))))
+                    .collect();
+                json!({ "registers": registers })
+            }
+            Output::Backtrace(frames) => {
+                json!({ "frames": frames.iter().map(FrameInfo::to_json).collect::<Vec<_>>() })
+            }
+            Output::Frame { frame, source } => {
+                let mut frame_json = frame.to_json();
+                frame_json["source"] = json!(source.iter().map(source_line_json).collect::<Vec<_>>());
+                json!({ "frame": frame_json })
+            }
+            Output::FrameDetails(details) => json!({ "frame": details.to_json() }),
+            Output::Variables(variables) => {
+                json!({ "variables": variables.iter().map(VariableInfo::to_json).collect::<Vec<_>>() })
+            }
+            Output::Symbol(symbol) => json!({
+                "symbol": {
+                    "name": symbol.name,
+                    "offset": symbol.offset,
+                    "section": symbol.section,
+                }
+            }),
+            Output::Functions(functions) => {
+                let functions: Vec<Value> = functions
+                    .iter()
+                    .map(|function| {
+                        json!({
+                            "name": function.name,
+                            "address": format!("{:#x}", function.address),
+                            "file": function.source.as_ref().map(|(file, _)| file),
+                            "line": function.source.as_ref().map(|(_, line)| line),
+                        })
+                    })
+                    .collect();
+                json!({ "functions": functions })
+            }
+            Output::SharedLibraries(libraries) => {
+                let libraries: Vec<Value> = libraries
+                    .iter()
+                    .map(|library| {
+                        json!({
+                            "from": format!("{:#x}", library.from),
+                            "to": format!("{:#x}", library.to),
+                            "debug_info": library.has_debug_info,
+                            "path": library.path,
+                        })
+                    })
+                    .collect();
+                json!({ "libraries": libraries })
+            }
+            Output::SymbolCache { directory, entries } => {
+                let entries: Vec<Value> = entries
+                    .iter()
+                    .map(|entry| {
+                        json!({
+                            "path": entry.path,
+                            "binary": entry.binary,
+                            "key": entry.key,
+                            "size": entry.size,
+                        })
+                    })
+                    .collect();
+                json!({ "directory": directory, "entries": entries })
+            }
+            Output::Value(var) => json!({ "value": var.to_json() }),
+            Output::Disassembly(instructions) => json!({
+                "instructions": instructions.iter().map(InstructionInfo::to_json).collect::<Vec<_>>(),
+            }),
+            Output::Stopped(_) => json!({ "running": true }),
+            Output::Help(text) => json!({ "help": text }),
+            Output::Message(text) => json!({ "message": text }),
+            Output::Nothing => Value::Null,
+        }
+    }
+}
+
+fn location_json(location: &Location) -> Value {
+    json!({
+        "address": format!("{:#x}", location.address),
+        "function": location.function_name,
+        "file": location.file,
+        "line": location.line,
+        "offset": location.symbol_offset,
+    })
+}
+
+fn source_line_json(line: &SourceLine) -> Value {
+    json!({ "line": line.line, "text": line.text, "current": line.current })
+}
@@ -0,0 +1,767 @@
+use crate::gimliwrapper::{Member, Type, TypeLookup, Variant, VariantPart};
+
+/// Elements shown of an array, a `Vec` or a `HashMap`.
+const MAX_ELEMENTS: usize = 200;
+/// Bytes shown of a string.
+const MAX_STRING_LENGTH: usize = 200;
+/// Bytes read of a single value, larger structs are cut off.
+const MAX_VALUE_SIZE: usize = 64 * 1024;
+/// How many structs deep values are shown, which also ends cycles of `Box`es and `Rc`s.
+const MAX_DEPTH: usize = 8;
+
+/// Formats a value of type `t` from the bytes of it, `None` if they don't have the
+/// expected layout, which shows the raw layout instead.
+type Printer = fn(&ValueFormatter, usize, &[u8], usize) -> Option<String>;
+
+/// Printers for the types of the Rust standard library, by the qualified DWARF name of the
+/// types they print. A name ending with `<` or `[` matches all instances of a generic type.
+const PRINTERS: &[(&str, Printer)] = &[
+    ("alloc::vec::Vec<", format_vec),
+    ("alloc::string::String", format_string),
+    ("&str", format_str),
+    ("&mut str", format_str),
+    ("&[", format_slice),
+    ("&mut [", format_slice),
+    ("alloc::boxed::Box<", format_box),
+    ("alloc::rc::Rc<", format_rc),
+    ("alloc::sync::Arc<", format_rc),
+    ("std::collections::hash::map::HashMap<", format_hash_map),
+];
+
+/// Formats values read from the memory of the program: scalars like `format_value` does,
+/// structs and arrays gdb style as `{x = 1, y = 2}` and `{1, 2}`, and with the printers
+/// Rust enums as `Some(1)` and the Rust types of `PRINTERS` the way `{:?}` shows them.
+pub struct ValueFormatter<'a> {
+    types: &'a dyn TypeLookup,
+    /// Reads `length` bytes at an address of the program.
+    read_memory: &'a dyn Fn(usize, usize) -> Option<Vec<u8>>,
+    /// Use the printers, otherwise show the raw layout of every value.
+    pretty: bool,
+}
+
+impl<'a> ValueFormatter<'a> {
+    pub fn new(
+        types: &'a dyn TypeLookup,
+        read_memory: &'a dyn Fn(usize, usize) -> Option<Vec<u8>>,
+        pretty: bool,
+    ) -> ValueFormatter<'a> {
+        ValueFormatter {
+            types,
+            read_memory,
+            pretty,
+        }
+    }
+
+    /// Format the value of type `t` at `address`, or `value` if it isn't in memory or
+    /// is a scalar.
+    pub fn format(&self, t: usize, address: Option<usize>, value: u64) -> String {
+        let in_memory = matches!(
+            self.types.get_type(self.types.resolve_type(t)),
+            Some(Type::Struct { .. }) | Some(Type::Array { .. })
+        ) || self.printer(t).is_some();
+        let size = self.shown_size(t).min(MAX_VALUE_SIZE);
+        match address.filter(|_| in_memory).and_then(|address| (self.read_memory)(address, size)) {
+            Some(bytes) => self.format_bytes(t, &bytes, 0),
+            None => self.format_scalar(t, value),
+        }
+    }
+
+    /// Format the value of type `t` made of `bytes`, a part of a value nested `depth` deep.
+    fn format_bytes(&self, t: usize, bytes: &[u8], depth: usize) -> String {
+        if let Some(printer) = self.printer(t) {
+            if let Some(text) = printer(self, t, bytes, depth) {
+                return text;
+            }
+        }
+        match self.types.get_type(t) {
+            Some(Type::Struct { .. }) | Some(Type::Array { .. }) if depth >= MAX_DEPTH => String::from("{...}"),
+            Some(Type::Struct {
+                name,
+                members,
+                variant_part,
+                ..
+            }) => self.format_struct(name, members, variant_part.as_ref(), bytes, depth),
+            Some(Type::Array { to, count, .. }) => {
+                let size = self.size_of(*to);
+                let elements: Vec<&[u8]> = match size {
+                    0 => Vec::new(),
+                    _ => bytes.chunks_exact(size).take(MAX_ELEMENTS).collect(),
+                };
+                let mut text: Vec<String> = elements
+                    .iter()
+                    .map(|element| self.format_bytes(*to, element, depth + 1))
+                    .collect();
+                // `bytes` can end after the elements that are shown.
+                if count.is_some_and(|count| count as usize > elements.len()) {
+                    text.push(String::from("..."));
+                }
+                format!("{{{}}}", text.join(", "))
+            }
+            Some(Type::Typedef { to, .. }) | Some(Type::Const { to, .. }) => self.format_bytes(*to, bytes, depth),
+            _ => self.format_scalar(t, little_endian(bytes)),
+        }
+    }
+
+    fn format_scalar(&self, t: usize, value: u64) -> String {
+        if self.pretty {
+            if let Some(Type::Base { name, byte_size, .. }) = self.types.get_type(self.types.resolve_type(t)) {
+                match (name.as_str(), byte_size) {
+                    ("bool", _) => return format!("{}", value & 0xff != 0),
+                    // Rust's char is a 32 bit code point, C's an 8 bit integer.
+                    ("char", 4) => {
+                        if let Some(c) = char::from_u32(value as u32) {
+                            return format!("{:?}", c);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        self.types.format_value(t, value)
+    }
+
+    fn format_struct(
+        &self,
+        name: &str,
+        members: &[Member],
+        variant_part: Option<&VariantPart>,
+        bytes: &[u8],
+        depth: usize,
+    ) -> String {
+        let variant = variant_part.and_then(|variant_part| self.active_variant(variant_part, bytes));
+        if self.pretty {
+            if let Some(variant) = variant {
+                return self.format_variant(&variant.member, bytes, depth);
+            }
+            if name.starts_with('(') {
+                let fields: Vec<String> = members
+                    .iter()
+                    .map(|member| self.format_bytes(member.t, self.member_bytes(member, bytes), depth + 1))
+                    .collect();
+                return format!("({})", fields.join(", "));
+            }
+        }
+        let fields: Vec<String> = members
+            .iter()
+            .chain(variant.map(|variant| &variant.member))
+            .map(|member| {
+                let value = self.format_bytes(member.t, self.member_bytes(member, bytes), depth + 1);
+                match member.name.as_str() {
+                    // A base class, or an anonymous union or struct
+                    "" => match self.types.type_name(member.t) {
+                        base if base != "{...}" => format!("<{}> = {}", base, value),
+                        _ => value,
+                    },
+                    name => format!("{} = {}", name, value),
+                }
+            })
+            .collect();
+        format!("{{{}}}", fields.join(", "))
+    }
+
+    /// The variant of a Rust enum the discriminant in `bytes` selects.
+    fn active_variant<'v>(&self, variant_part: &'v VariantPart, bytes: &[u8]) -> Option<&'v Variant> {
+        let variants = &variant_part.variants;
+        let discriminant = match &variant_part.discriminant {
+            Some(discriminant) => discriminant,
+            None => return variants.first(),
+        };
+        let value = little_endian(self.member_bytes(discriminant, bytes));
+        let mask = 1_u64
+            .checked_shl(8 * self.size_of(discriminant.t) as u32)
+            .map(|v| v - 1)
+            .unwrap_or(!0);
+        variants
+            .iter()
+            .find(|variant| variant.discr_value.is_some_and(|discr_value| discr_value & mask == value))
+            .or_else(|| variants.iter().find(|variant| variant.discr_value.is_none()))
+    }
+
+    /// A variant of a Rust enum as it is written: `None`, `Some(1)` or `Circle { r: 1.5 }`.
+    fn format_variant(&self, variant: &Member, bytes: &[u8], depth: usize) -> String {
+        let bytes = self.member_bytes(variant, bytes);
+        let fields = match self.types.get_type(variant.t) {
+            Some(Type::Struct { members, .. }) => members,
+            _ => return variant.name.clone(),
+        };
+        if fields.is_empty() {
+            return variant.name.clone();
+        }
+        let values = fields
+            .iter()
+            .map(|field| (field, self.format_bytes(field.t, self.member_bytes(field, bytes), depth + 1)));
+        if fields.iter().all(|field| field.name.starts_with("__")) {
+            let values: Vec<String> = values.map(|(_, value)| value).collect();
+            format!("{}({})", variant.name, values.join(", "))
+        } else {
+            let values: Vec<String> = values.map(|(field, value)| format!("{}: {}", field.name, value)).collect();
+            format!("{} {{ {} }}", variant.name, values.join(", "))
+        }
+    }
+
+    /// The printer for values of type `t`, none if the printers are off.
+    fn printer(&self, t: usize) -> Option<Printer> {
+        if !self.pretty {
+            return None;
+        }
+        let name = match self.types.get_type(self.types.resolve_type(t))? {
+            Type::Struct { name, .. } | Type::Pointer { name: Some(name), .. } => name,
+            _ => return None,
+        };
+        PRINTERS
+            .iter()
+            .find(|(key, _)| match key.ends_with(['<', '[']) {
+                true => name.starts_with(key),
+                false => name == key,
+            })
+            .map(|(_, printer)| *printer)
+    }
+
+    fn size_of(&self, t: usize) -> usize {
+        self.types.get_type_byte_size(t).unwrap_or(0) as usize
+    }
+
+    /// The bytes at the start of a value of type `t` that are shown, which leaves out the
+    /// elements of arrays after the first `MAX_ELEMENTS`, also in structs.
+    fn shown_size(&self, t: usize) -> usize {
+        let member_end = |member: &Member| member.offset as usize + self.shown_size(member.t);
+        match self.types.get_type(t) {
+            Some(Type::Array { to, count, .. }) => {
+                let shown = count.map_or(0, |count| (count as usize).min(MAX_ELEMENTS));
+                shown.saturating_mul(self.size_of(*to))
+            }
+            Some(Type::Struct {
+                members,
+                variant_part,
+                ..
+            }) => members
+                .iter()
+                .chain(variant_part.iter().flat_map(|part| {
+                    part.discriminant
+                        .iter()
+                        .chain(part.variants.iter().map(|variant| &variant.member))
+                }))
+                .map(member_end)
+                .max()
+                .unwrap_or(0)
+                .min(self.size_of(t)),
+            Some(Type::Typedef { to, .. }) | Some(Type::Const { to, .. }) => self.shown_size(*to),
+            _ => self.size_of(t),
+        }
+    }
+
+    /// The part of `bytes` with the value of `member`, shorter if `bytes` ends before it.
+    fn member_bytes<'b>(&self, member: &Member, bytes: &'b [u8]) -> &'b [u8] {
+        let start = (member.offset as usize).min(bytes.len());
+        let end = start.saturating_add(self.size_of(member.t)).min(bytes.len());
+        &bytes[start..end]
+    }
+
+    /// The type and bytes of the field at `path` of the struct `t` with the value `bytes`.
+    fn field<'b>(&self, t: usize, bytes: &'b [u8], path: &[&str]) -> Option<(usize, &'b [u8])> {
+        let (mut t, mut bytes) = (t, bytes);
+        for name in path {
+            let members = match self.types.get_type(self.types.resolve_type(t))? {
+                Type::Struct { members, .. } => members,
+                _ => return None,
+            };
+            let member = members.iter().find(|member| member.name == *name)?;
+            (t, bytes) = (member.t, self.member_bytes(member, bytes));
+        }
+        Some((t, bytes))
+    }
+
+    /// The type and value of the integer or pointer in the field at `path`, also when
+    /// it is wrapped in structs like `NonNull`, `Cell` or `AtomicUsize`.
+    fn scalar_field(&self, t: usize, bytes: &[u8], path: &[&str]) -> Option<(usize, u64)> {
+        let (mut t, mut bytes) = self.field(t, bytes, path)?;
+        while let Type::Struct { members, .. } = self.types.get_type(self.types.resolve_type(t))? {
+            let member = members.first()?;
+            (t, bytes) = (member.t, self.member_bytes(member, bytes));
+        }
+        Some((t, little_endian(bytes)))
+    }
+
+    fn field_value(&self, t: usize, bytes: &[u8], path: &[&str]) -> Option<u64> {
+        self.scalar_field(t, bytes, path).map(|(_, value)| value)
+    }
+
+    /// The type a pointer field points to and its address.
+    fn pointer_field(&self, t: usize, bytes: &[u8], path: &[&str]) -> Option<(usize, usize)> {
+        let (t, address) = self.scalar_field(t, bytes, path)?;
+        match self.types.get_type(self.types.resolve_type(t))? {
+            Type::Pointer { to, .. } => Some((*to, address as usize)),
+            _ => None,
+        }
+    }
+
+    fn template_parameter(&self, t: usize, name: &str) -> Option<usize> {
+        match self.types.get_type(self.types.resolve_type(t))? {
+            Type::Struct { template_parameters, .. } => template_parameters
+                .iter()
+                .find(|(parameter, _)| parameter == name)
+                .map(|(_, t)| *t),
+            _ => None,
+        }
+    }
+
+    /// `count` elements of type `t` at `address`, `[1, 2, 3]`.
+    fn format_elements(&self, t: usize, address: usize, count: u64, depth: usize) -> Option<String> {
+        let size = self.size_of(t);
+        let shown = (count as usize).min(MAX_ELEMENTS);
+        let bytes = match size.checked_mul(shown)? {
+            0 => Vec::new(),
+            length => (self.read_memory)(address, length)?,
+        };
+        let mut elements: Vec<String> = (0..shown)
+            .map(|idx| self.format_bytes(t, bytes.get(idx * size..(idx + 1) * size).unwrap_or(&[]), depth + 1))
+            .collect();
+        if count as usize > shown {
+            elements.push(String::from("..."));
+        }
+        Some(format!("[{}]", elements.join(", ")))
+    }
+
+    /// `length` bytes of UTF-8 at `address`, quoted and escaped like `{:?}` does.
+    fn format_utf8(&self, address: usize, length: u64) -> Option<String> {
+        let shown = (length as usize).min(MAX_STRING_LENGTH);
+        let bytes = match shown {
+            0 => Vec::new(),
+            _ => (self.read_memory)(address, shown)?,
+        };
+        let text = format!("{:?}", String::from_utf8_lossy(&bytes));
+        match length as usize > shown {
+            true => Some(format!("{}...", text)),
+            false => Some(text),
+        }
+    }
+}
+
+/// `Vec<T>`, `[1, 2, 3]`.
+fn format_vec(f: &ValueFormatter, t: usize, bytes: &[u8], depth: usize) -> Option<String> {
+    let element = f.template_parameter(t, "T")?;
+    let (address, length) = vec_data(f, t, bytes)?;
+    f.format_elements(element, address, length, depth)
+}
+
+/// Address and length of the elements of a `Vec`. Its buffer is untyped since Rust 1.81.
+fn vec_data(f: &ValueFormatter, t: usize, bytes: &[u8]) -> Option<(usize, u64)> {
+    let address = f
+        .field_value(t, bytes, &["buf", "inner", "ptr"])
+        .or_else(|| f.field_value(t, bytes, &["buf", "ptr"]))?;
+    Some((address as usize, f.field_value(t, bytes, &["len"])?))
+}
+
+/// `String`, `"text"`.
+fn format_string(f: &ValueFormatter, t: usize, bytes: &[u8], _depth: usize) -> Option<String> {
+    let (vec, bytes) = f.field(t, bytes, &["vec"])?;
+    let (address, length) = vec_data(f, vec, bytes)?;
+    f.format_utf8(address, length)
+}
+
+/// `&str`, `"text"`, a pointer with the length of the string.
+fn format_str(f: &ValueFormatter, t: usize, bytes: &[u8], _depth: usize) -> Option<String> {
+    let address = f.field_value(t, bytes, &["data_ptr"])?;
+    f.format_utf8(address as usize, f.field_value(t, bytes, &["length"])?)
+}
+
+/// `&[T]`, `[1, 2, 3]`, a pointer with the number of elements.
+fn format_slice(f: &ValueFormatter, t: usize, bytes: &[u8], depth: usize) -> Option<String> {
+    let (element, address) = f.pointer_field(t, bytes, &["data_ptr"])?;
+    f.format_elements(element, address, f.field_value(t, bytes, &["length"])?, depth)
+}
+
+/// `Box<T>` shows the value it points to, `Box<str>` and `Box<[T]>` like `&str` and `&[T]`.
+fn format_box(f: &ValueFormatter, t: usize, bytes: &[u8], depth: usize) -> Option<String> {
+    match f.types.get_type(f.types.resolve_type(t))? {
+        Type::Pointer { to, .. } => {
+            if depth >= MAX_DEPTH {
+                return Some(String::from("{...}"));
+            }
+            let value = (f.read_memory)(little_endian(bytes) as usize, f.shown_size(*to).min(MAX_VALUE_SIZE))?;
+            Some(f.format_bytes(*to, &value, depth + 1))
+        }
+        Type::Struct { name, .. } if name.starts_with("alloc::boxed::Box<str,") => format_str(f, t, bytes, depth),
+        Type::Struct { name, .. } if name.starts_with("alloc::boxed::Box<[") => format_slice(f, t, bytes, depth),
+        _ => None,
+    }
+}
+
+/// `Rc<T>` and `Arc<T>`, `Rc(strong=2, weak=0) = 42`, with the counts `Rc::strong_count` and
+/// `Rc::weak_count` return.
+fn format_rc(f: &ValueFormatter, t: usize, bytes: &[u8], depth: usize) -> Option<String> {
+    let (inner, address) = f.pointer_field(t, bytes, &["ptr"])?;
+    let kind = match f.types.type_name(t).starts_with("alloc::rc::") {
+        true => "Rc",
+        false => "Arc",
+    };
+    if depth >= MAX_DEPTH {
+        return Some(format!("{}({{...}})", kind));
+    }
+    let bytes = (f.read_memory)(address, f.shown_size(inner).min(MAX_VALUE_SIZE))?;
+    let strong = f.field_value(inner, &bytes, &["strong"])?;
+    // All strong references together hold one weak reference.
+    let weak = f.field_value(inner, &bytes, &["weak"])?.saturating_sub((strong > 0) as u64);
+    let (value_t, value) = f
+        .field(inner, &bytes, &["value"])
+        .or_else(|| f.field(inner, &bytes, &["data"]))?;
+    Some(format!(
+        "{}(strong={}, weak={}) = {}",
+        kind,
+        strong,
+        weak,
+        f.format_bytes(value_t, value, depth + 1)
+    ))
+}
+
+/// `HashMap<K, V>`, `{"a": 1, "b": 2}`. The hashbrown table it is built on keeps the
+/// `(K, V)` entries before its control bytes, the entry of bucket `i` ends `i` entries
+/// before them. The control byte of a bucket with an entry has its high bit clear.
+fn format_hash_map(f: &ValueFormatter, t: usize, bytes: &[u8], depth: usize) -> Option<String> {
+    let (table_t, table) = f.field(t, bytes, &["base", "table"])?;
+    let entry = f.template_parameter(table_t, "T")?;
+    let ctrl = f.field_value(table_t, table, &["table", "ctrl"])? as usize;
+    let buckets = f.field_value(table_t, table, &["table", "bucket_mask"])? as usize + 1;
+    let items = f.field_value(table_t, table, &["table", "items"])? as usize;
+    let size = f.size_of(entry);
+    if items == 0 {
+        return Some(String::from("{}"));
+    }
+    if items > buckets || buckets > MAX_VALUE_SIZE * 16 {
+        return None;
+    }
+    let ctrl_bytes = (f.read_memory)(ctrl, buckets)?;
+    let mut entries = Vec::new();
+    for (idx, _) in ctrl_bytes.iter().enumerate().filter(|(_, byte)| *byte & 0x80 == 0) {
+        if entries.len() == MAX_ELEMENTS {
+            entries.push(String::from("..."));
+            break;
+        }
+        let address = ctrl.checked_sub((idx + 1) * size)?;
+        let bytes = (f.read_memory)(address, size)?;
+        let key = f.field(entry, &bytes, &["__0"])?;
+        let value = f.field(entry, &bytes, &["__1"])?;
+        entries.push(format!(
+            "{}: {}",
+            f.format_bytes(key.0, key.1, depth + 1),
+            f.format_bytes(value.0, value.1, depth + 1)
+        ));
+    }
+    Some(format!("{{{}}}", entries.join(", ")))
+}
+
+/// The little endian value of the first 8 bytes of `bytes`.
+fn little_endian(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .take(8)
+        .rev()
+        .fold(0, |value, byte| (value << 8) | *byte as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    struct Types(HashMap<usize, Type>);
+
+    impl TypeLookup for Types {
+        fn get_type(&self, t: usize) -> Option<&Type> {
+            self.0.get(&t)
+        }
+    }
+
+    const U8: usize = 1;
+    const I32: usize = 2;
+    const USIZE: usize = 3;
+    const F64: usize = 4;
+    const I8: usize = 5;
+    const U8_POINTER: usize = 10;
+    const I32_POINTER: usize = 11;
+    const I32_REF: usize = 12;
+    const NON_NULL: usize = 13;
+    const STR: usize = 20;
+    const RAW_VEC_INNER: usize = 21;
+    const RAW_VEC: usize = 22;
+    const VEC: usize = 23;
+    const ENTRY: usize = 30;
+    const RAW_TABLE_INNER: usize = 31;
+    const RAW_TABLE: usize = 32;
+    const HASHBROWN_MAP: usize = 33;
+    const HASH_MAP: usize = 34;
+    const OPTION: usize = 40;
+    const OPTION_NONE: usize = 41;
+    const OPTION_SOME: usize = 42;
+    const OPTION_REF: usize = 43;
+    const OPTION_REF_SOME: usize = 44;
+    const SHAPE_CIRCLE: usize = 50;
+    const SHAPE_RECT: usize = 51;
+    const ARRAY: usize = 60;
+    const BUFFER: usize = 61;
+
+    fn base(name: &str, byte_size: u64, is_signed: bool) -> Type {
+        Type::Base {
+            name: String::from(name),
+            is_float: name.starts_with('f'),
+            is_signed,
+            byte_size,
+            ref_addr: 0,
+        }
+    }
+
+    fn pointer(name: Option<&str>, to: usize) -> Type {
+        Type::Pointer {
+            name: name.map(String::from),
+            byte_size: 8,
+            to,
+            ref_addr: 0,
+        }
+    }
+
+    fn member(name: &str, t: usize, offset: u64) -> Member {
+        Member {
+            name: String::from(name),
+            t,
+            offset,
+        }
+    }
+
+    fn structure(name: &str, byte_size: u64, members: Vec<Member>, template_parameters: &[(&str, usize)]) -> Type {
+        Type::Struct {
+            name: String::from(name),
+            byte_size,
+            members,
+            template_parameters: template_parameters.iter().map(|(name, t)| (String::from(*name), *t)).collect(),
+            variant_part: None,
+            ref_addr: 0,
+        }
+    }
+
+    fn enumeration(name: &str, byte_size: u64, discriminant: Member, variants: &[(Option<u64>, &str, usize)]) -> Type {
+        Type::Struct {
+            name: String::from(name),
+            byte_size,
+            members: Vec::new(),
+            template_parameters: Vec::new(),
+            variant_part: Some(VariantPart {
+                discriminant: Some(discriminant),
+                variants: variants
+                    .iter()
+                    .map(|(discr_value, name, t)| Variant {
+                        discr_value: *discr_value,
+                        member: member(name, *t, 0),
+                    })
+                    .collect(),
+            }),
+            ref_addr: 0,
+        }
+    }
+
+    /// The layouts of Rust 1.8x: `Vec<i32>`, `&str`, `HashMap<&str, i32>`, `Option<i32>`,
+    /// `Option<&i32>` and two variants of an enum `Shape`.
+    fn types() -> Types {
+        let non_null_u8 = "core::ptr::non_null::NonNull<u8>";
+        let raw_table = "hashbrown::raw::RawTable<(&str, i32), alloc::alloc::Global>";
+        Types(HashMap::from([
+            (U8, base("u8", 1, false)),
+            (I32, base("i32", 4, true)),
+            (USIZE, base("usize", 8, false)),
+            (F64, base("f64", 8, true)),
+            (I8, base("i8", 1, true)),
+            (U8_POINTER, pointer(None, U8)),
+            (I32_POINTER, pointer(None, I32)),
+            (I32_REF, pointer(Some("&i32"), I32)),
+            (NON_NULL, structure(non_null_u8, 8, vec![member("pointer", U8_POINTER, 0)], &[])),
+            (STR, structure("&str", 16, vec![member("data_ptr", U8_POINTER, 0), member("length", USIZE, 8)], &[])),
+            (
+                RAW_VEC_INNER,
+                structure("alloc::raw_vec::RawVecInner", 16, vec![member("ptr", NON_NULL, 0), member("cap", USIZE, 8)], &[]),
+            ),
+            (RAW_VEC, structure("alloc::raw_vec::RawVec<i32>", 16, vec![member("inner", RAW_VEC_INNER, 0)], &[])),
+            (
+                VEC,
+                structure(
+                    "alloc::vec::Vec<i32, alloc::alloc::Global>",
+                    24,
+                    vec![member("buf", RAW_VEC, 0), member("len", USIZE, 16)],
+                    &[("T", I32)],
+                ),
+            ),
+            (ENTRY, structure("(&str, i32)", 24, vec![member("__0", STR, 0), member("__1", I32, 16)], &[])),
+            (
+                RAW_TABLE_INNER,
+                structure(
+                    "hashbrown::raw::RawTableInner",
+                    32,
+                    vec![member("ctrl", NON_NULL, 0), member("bucket_mask", USIZE, 8), member("items", USIZE, 24)],
+                    &[],
+                ),
+            ),
+            (RAW_TABLE, structure(raw_table, 32, vec![member("table", RAW_TABLE_INNER, 0)], &[("T", ENTRY)])),
+            (HASHBROWN_MAP, structure("hashbrown::map::HashMap", 48, vec![member("table", RAW_TABLE, 16)], &[])),
+            (
+                HASH_MAP,
+                structure("std::collections::hash::map::HashMap<&str, i32>", 48, vec![member("base", HASHBROWN_MAP, 0)], &[]),
+            ),
+            (
+                OPTION,
+                enumeration(
+                    "core::option::Option<i32>",
+                    8,
+                    member("", I32, 0),
+                    &[(Some(0), "None", OPTION_NONE), (Some(1), "Some", OPTION_SOME)],
+                ),
+            ),
+            (OPTION_NONE, structure("None", 8, Vec::new(), &[])),
+            (OPTION_SOME, structure("Some", 8, vec![member("__0", I32, 4)], &[])),
+            (
+                OPTION_REF,
+                enumeration(
+                    "core::option::Option<&i32>",
+                    8,
+                    member("", I32_REF, 0),
+                    &[(Some(0), "None", OPTION_NONE), (None, "Some", OPTION_REF_SOME)],
+                ),
+            ),
+            (OPTION_REF_SOME, structure("Some", 8, vec![member("__0", I32_REF, 0)], &[])),
+            (SHAPE_CIRCLE, structure("Circle", 16, vec![member("r", F64, 8)], &[])),
+            (SHAPE_RECT, structure("Rect", 12, vec![member("__0", I32, 4), member("__1", I32, 8)], &[])),
+            (ARRAY, Type::Array { to: I32, count: Some(1000), ref_addr: 0 }),
+            (BUFFER, structure("Buffer", 4008, vec![member("length", USIZE, 0), member("data", ARRAY, 8)], &[])),
+        ]))
+    }
+
+    /// Memory of the program, blocks of bytes by their address.
+    struct Memory(Vec<(usize, Vec<u8>)>);
+
+    impl Memory {
+        fn read(&self, address: usize, length: usize) -> Option<Vec<u8>> {
+            self.0.iter().find_map(|(start, bytes)| {
+                let offset = address.checked_sub(*start)?;
+                bytes.get(offset..offset.checked_add(length)?).map(<[u8]>::to_vec)
+            })
+        }
+    }
+
+    fn words(values: &[u64]) -> Vec<u8> {
+        values.iter().flat_map(|value| value.to_le_bytes()).collect()
+    }
+
+    fn format(t: usize, address: usize, memory: &Memory, pretty: bool) -> String {
+        let types = types();
+        let read_memory = |address, length| memory.read(address, length);
+        ValueFormatter::new(&types, &read_memory, pretty).format(t, Some(address), 0)
+    }
+
+    #[test]
+    fn little_endian_values() {
+        assert_eq!(little_endian(&[]), 0);
+        assert_eq!(little_endian(&[0x34, 0x12]), 0x1234);
+        assert_eq!(little_endian(&[1, 0, 0, 0, 0, 0, 0, 0x80, 0xff]), 0x8000_0000_0000_0001);
+    }
+
+    #[test]
+    fn active_variants() {
+        let types = types();
+        let read_memory = |_, _| None;
+        let f = ValueFormatter::new(&types, &read_memory, true);
+        let variant_part = |t| match types.get_type(t) {
+            Some(Type::Struct {
+                variant_part: Some(variant_part),
+                ..
+            }) => variant_part,
+            _ => panic!("not an enum"),
+        };
+        let active = |t, bytes: &[u8]| f.active_variant(variant_part(t), bytes).map(|v| v.member.name.clone());
+        assert_eq!(active(OPTION, &[0, 0, 0, 0, 5, 0, 0, 0]).unwrap(), "None");
+        assert_eq!(active(OPTION, &[1, 0, 0, 0, 5, 0, 0, 0]).unwrap(), "Some");
+        assert_eq!(active(OPTION, &[2, 0, 0, 0, 5, 0, 0, 0]), None);
+        // A null pointer is None, any other value the pointer of Some.
+        assert_eq!(active(OPTION_REF, &[0; 8]).unwrap(), "None");
+        assert_eq!(active(OPTION_REF, &words(&[0x1000])).unwrap(), "Some");
+
+        // Negative discriminants are compared in the size of the discriminant.
+        let signed = enumeration("Sign", 1, member("", I8, 0), &[(Some(u64::MAX), "Minus", OPTION_NONE)]);
+        let signed = match &signed {
+            Type::Struct {
+                variant_part: Some(variant_part),
+                ..
+            } => variant_part,
+            _ => unreachable!(),
+        };
+        assert_eq!(f.active_variant(signed, &[0xff]).unwrap().member.name, "Minus");
+    }
+
+    #[test]
+    fn variants() {
+        let types = types();
+        let read_memory = |_, _| None;
+        let f = ValueFormatter::new(&types, &read_memory, true);
+        let circle = [words(&[0]), 1.5f64.to_le_bytes().to_vec()].concat();
+        assert_eq!(f.format_variant(&member("Circle", SHAPE_CIRCLE, 0), &circle, 0), "Circle { r: 1.5 }");
+        let rect = [0, 0, 0, 0, 3, 0, 0, 0, 4, 0, 0, 0];
+        assert_eq!(f.format_variant(&member("Rect", SHAPE_RECT, 0), &rect, 0), "Rect(3, 4)");
+        assert_eq!(f.format_variant(&member("None", OPTION_NONE, 0), &[0; 8], 0), "None");
+
+        let memory = Memory(vec![(0x1000, vec![1, 0, 0, 0, 42, 0, 0, 0]), (0x2000, vec![0; 8])]);
+        assert_eq!(format(OPTION, 0x1000, &memory, true), "Some(42)");
+        assert_eq!(format(OPTION_REF, 0x2000, &memory, true), "None");
+        assert_eq!(format(OPTION, 0x1000, &memory, false), "{Some = {__0 = 42}}");
+    }
+
+    #[test]
+    fn vec_and_str() {
+        let memory = Memory(vec![
+            // A Vec with 3 elements at 0x2000 and a capacity of 4.
+            (0x1000, words(&[0x2000, 4, 3])),
+            (0x2000, [1u32, 2, 3, 0].iter().flat_map(|v| v.to_le_bytes()).collect()),
+            // A &str and its bytes.
+            (0x3000, words(&[0x4000, 5])),
+            (0x4000, b"hello".to_vec()),
+            // An empty Vec with a dangling pointer.
+            (0x5000, words(&[4, 0, 0])),
+        ]);
+        assert_eq!(format(VEC, 0x1000, &memory, true), "[1, 2, 3]");
+        assert_eq!(format(VEC, 0x5000, &memory, true), "[]");
+        assert_eq!(format(STR, 0x3000, &memory, true), "\"hello\"");
+        assert_eq!(format(STR, 0x3000, &memory, false), "{data_ptr = 0x4000, length = 5}");
+    }
+
+    #[test]
+    fn arrays_read_only_the_elements_shown() {
+        let types = types();
+        let lengths = std::cell::RefCell::new(Vec::new());
+        let read_memory = |_, length| {
+            lengths.borrow_mut().push(length);
+            Some(vec![0; length])
+        };
+        let f = ValueFormatter::new(&types, &read_memory, true);
+        let array = f.format(ARRAY, Some(0x1000), 0);
+        assert_eq!(array, format!("{{{}, ...}}", vec!["0"; MAX_ELEMENTS].join(", ")));
+        let buffer = f.format(BUFFER, Some(0x1000), 0);
+        assert_eq!(buffer, format!("{{length = 0, data = {}}}", array));
+        assert_eq!(*lengths.borrow(), [MAX_ELEMENTS * 4, 8 + MAX_ELEMENTS * 4]);
+    }
+
+    #[test]
+    fn hash_map() {
+        // Four buckets, the second and the fourth hold an entry, which end that many
+        // entries before the control bytes.
+        let entry = |key: usize, length: u64, value: u64| [words(&[key as u64, length]), words(&[value])].concat();
+        let mut table = entry(0x4000, 1, 1);
+        table.extend(vec![0; 24]);
+        table.extend(entry(0x4001, 2, 2));
+        table.extend(vec![0; 24]);
+        table.extend([0xff, 0x12, 0xff, 0x05]);
+        let ctrl = 0x2000 + 4 * 24;
+        let memory = Memory(vec![
+            (0x1000, words(&[0, 0, ctrl as u64, 3, 0, 2])),
+            (0x1100, words(&[0, 0, 0, 3, 0, 0])),
+            (0x2000, table),
+            (0x4000, b"abc".to_vec()),
+        ]);
+        assert_eq!(format(HASH_MAP, 0x1000, &memory, true), "{\"bc\": 2, \"a\": 1}");
+        assert_eq!(format(HASH_MAP, 0x1100, &memory, true), "{}");
+    }
+}
@@ -1,80 +1,1053 @@
-use std::io::{stdin, stdout, Write};
+/// Where to set a breakpoint or what to disassemble.
+#[derive(Debug, Clone)]
+pub enum LocationSpec {
+    /// `*ADDRESS` or `0xADDRESS`
+    Address(usize),
+    Function(String),
+    /// A line in the file of the current location.
+    Line(u64),
+    FileLine(String, u64),
+}
+
+/// A breakpoint given by its number or address.
+#[derive(Debug)]
+pub enum BreakpointRef {
+    Number(u32),
+    Address(usize),
+}
+
+/// What `disassemble` shows.
+#[derive(Debug)]
+pub enum DisassembleRange {
+    /// The function containing the location.
+    Around(LocationSpec),
+    /// Start and end (exclusive) address.
+    Range(usize, usize),
+}
 
 #[derive(Debug)]
 pub enum ReplCommand {
-    Start,
     Continue,
     Exit,
-    Unknown,
-    SetBp(usize),
-    SetBpName(String),
-    DeleteBp(usize),
+    Help(Option<String>),
+    SetBp(LocationSpec),
+    DeleteBp(BreakpointRef),
     ListBps,
     GetRegs,
-    SingleStep,
+    SingleStep(usize),
+    Step(usize),
+    Next(usize),
+    Finish,
     Backtrace,
-    Frame,
-    GetVar
-}
-
-/// Very sophisticated command parser.
-pub fn get_command() -> ReplCommand {
-    let mut input = String::new();
-
-    print!("> ");
-    stdout().flush().unwrap();
-    stdin().read_line(&mut input).expect("Could not read line");
-
-    match input.trim() {
-        "cont" => ReplCommand::Continue,
-        "r" => ReplCommand::Continue,
-        "exit" => ReplCommand::Exit,
-        "e" => ReplCommand::Exit,
-        "regs" => ReplCommand::GetRegs,
-        "s" => ReplCommand::SingleStep,
-        "lsb" => ReplCommand::ListBps,
-        "back" => ReplCommand::Backtrace,
-        "frame" => ReplCommand::Frame,
-        "f" => ReplCommand::Frame,
-        "get" => ReplCommand::GetVar,
+    /// Select the frame at a level, or show the selected one if `None`.
+    Frame(Option<usize>),
+    /// Select a frame further out by the count.
+    Up(usize),
+    /// Select a frame further in by the count.
+    Down(usize),
+    InfoFrame,
+    InfoLocals,
+    InfoArgs,
+    /// The symbol containing the value of an expression.
+    InfoSymbol(String),
+    /// Functions matching a regular expression, all if `None`.
+    InfoFunctions(Option<String>),
+    InfoSharedLibrary,
+    /// Print the value of an expression, with the raw layout of Rust types if `raw`.
+    Print {
+        expression: String,
+        raw: bool,
+    },
+    /// Stop when the program changes a global variable.
+    Watch(String),
+    Source(String),
+    /// Record the commands of a breakpoint, the last one set if `None`.
+    Commands(Option<u32>),
+    Disassemble {
+        range: Option<DisassembleRange>,
+        with_source: bool,
+    },
+    /// A breakpoint that prints `format` with the values of `arguments` and continues.
+    Dprintf {
+        location: LocationSpec,
+        format: String,
+        arguments: Vec<String>,
+    },
+    /// Write dprintf output to a file, or to the console if `None`.
+    SetDprintfLog(Option<String>),
+    /// Directories searched for separate debug files.
+    SetDebugFileDirectory(Vec<String>),
+    /// Show Rust types like Rust does, or their raw layout.
+    SetPrettyPrinters(bool),
+    /// List the files of the symbol cache.
+    MaintCacheInfo,
+    /// Remove all files of the symbol cache.
+    MaintCacheClear,
+}
+
+/// An entry of the command registry.
+pub struct CommandSpec {
+    pub name: &'static str,
+    pub aliases: &'static [&'static str],
+    /// Argument synopsis, e.g. `LOCATION`.
+    pub usage: &'static str,
+    pub help: &'static str,
+    /// Options like `/r`, which may also follow the name directly: `print/r`.
+    pub options: &'static [&'static str],
+    /// Commands like `info` only dispatch to their subcommands.
+    pub subcommands: &'static [CommandSpec],
+    parse: fn(&mut Args) -> Result<ReplCommand, String>,
+}
+
+/// All commands of the REPL. Commands can be abbreviated to any unique prefix.
+pub static COMMANDS: &[CommandSpec] = &[
+    CommandSpec {
+        name: "backtrace",
+        aliases: &["bt", "back", "where"],
+        usage: "",
+        help: "Print the call stack with the arguments and locals of every frame.",
+        options: &[],
+        subcommands: &[],
+        parse: |args| args.done(ReplCommand::Backtrace),
+    },
+    CommandSpec {
+        name: "break",
+        aliases: &["b", "br"],
+        usage: "LOCATION",
+        help: "Set a breakpoint. LOCATION is a function, FILE:LINE, LINE in the current file or *ADDRESS.",
+        options: &[],
+        subcommands: &[],
+        parse: |args| {
+            let location = args.location()?;
+            args.done(ReplCommand::SetBp(location))
+        },
+    },
+    CommandSpec {
+        name: "commands",
+        aliases: &[],
+        usage: "[BREAKPOINT-NUMBER]",
+        help: "Give commands to run when the breakpoint is hit, one per line, ending with \"end\".",
+        options: &[],
+        subcommands: &[],
+        parse: |args| {
+            let command = args.command.clone();
+            let number = match args.next() {
+                Some(number) => Some(
+                    number
+                        .parse()
+                        .map_err(|_| format!("{}: invalid BREAKPOINT-NUMBER \"{}\".", command, number))?,
+                ),
+                None => None,
+            };
+            args.done(ReplCommand::Commands(number))
+        },
+    },
+    CommandSpec {
+        name: "continue",
+        aliases: &["c", "cont", "r", "run"],
+        usage: "",
+        help: "Continue the program. Starts it again if it is not running.",
+        options: &[],
+        subcommands: &[],
+        parse: |args| args.done(ReplCommand::Continue),
+    },
+    CommandSpec {
+        name: "delete",
+        aliases: &["d", "rb"],
+        usage: "BREAKPOINT",
+        help: "Delete a breakpoint given by its number or *ADDRESS.",
+        options: &[],
+        subcommands: &[],
+        parse: |args| {
+            let breakpoint = args.breakpoint()?;
+            args.done(ReplCommand::DeleteBp(breakpoint))
+        },
+    },
+    CommandSpec {
+        name: "disassemble",
+        aliases: &["disas"],
+        usage: "[/s] [LOCATION|START,END|START,+LENGTH]",
+        help: "Disassemble the function containing rip or LOCATION, or an address range. /s shows the source lines.",
+        options: &["/s", "/m"],
+        subcommands: &[],
+        parse: parse_disassemble,
+    },
+    CommandSpec {
+        name: "down",
+        aliases: &[],
+        usage: "[COUNT]",
+        help: "Select the frame called by the selected frame.",
+        options: &[],
+        subcommands: &[],
+        parse: |args| {
+            let count = args.count()?;
+            args.done(ReplCommand::Down(count))
+        },
+    },
+    CommandSpec {
+        name: "dprintf",
+        aliases: &[],
+        usage: "LOCATION,\"FORMAT\",ARGUMENT...",
+        help: "Set a breakpoint that prints FORMAT like printf with the values of the ARGUMENTs and continues.",
+        options: &[],
+        subcommands: &[],
+        parse: parse_dprintf,
+    },
+    CommandSpec {
+        name: "exit",
+        aliases: &["e", "q", "quit"],
+        usage: "",
+        help: "Kill the program and exit the debugger.",
+        options: &[],
+        subcommands: &[],
+        parse: |args| args.done(ReplCommand::Exit),
+    },
+    CommandSpec {
+        name: "finish",
+        aliases: &["fin"],
+        usage: "",
+        help: "Run until the current function returns.",
+        options: &[],
+        subcommands: &[],
+        parse: |args| args.done(ReplCommand::Finish),
+    },
+    CommandSpec {
+        name: "frame",
+        aliases: &["f"],
+        usage: "[LEVEL]",
+        help: "Select the frame at LEVEL of the backtrace, or show the selected frame.",
+        options: &[],
+        subcommands: &[],
+        parse: |args| {
+            let command = args.command.clone();
+            let level = match args.next() {
+                Some(level) => Some(
+                    level
+                        .parse()
+                        .map_err(|_| format!("{}: invalid LEVEL \"{}\".", command, level))?,
+                ),
+                None => None,
+            };
+            args.done(ReplCommand::Frame(level))
+        },
+    },
+    CommandSpec {
+        name: "help",
+        aliases: &["h"],
+        usage: "[COMMAND]",
+        help: "List all commands or show the help of COMMAND.",
+        options: &[],
+        subcommands: &[],
+        parse: |args| {
+            let topic = args.rest();
+            Ok(ReplCommand::Help(topic.map(String::from)))
+        },
+    },
+    CommandSpec {
+        name: "info",
+        aliases: &["i"],
+        usage: "SUBCOMMAND",
+        help: "Show information about the program.",
+        options: &[],
+        subcommands: &[
+            CommandSpec {
+                name: "args",
+                aliases: &[],
+                usage: "",
+                help: "Show the arguments of the selected frame.",
+                options: &[],
+                subcommands: &[],
+                parse: |args| args.done(ReplCommand::InfoArgs),
+            },
+            CommandSpec {
+                name: "breakpoints",
+                aliases: &[],
+                usage: "",
+                help: "List all breakpoints.",
+                options: &[],
+                subcommands: &[],
+                parse: |args| args.done(ReplCommand::ListBps),
+            },
+            CommandSpec {
+                name: "frame",
+                aliases: &[],
+                usage: "",
+                help: "Describe the selected frame: its CFA, saved registers, caller and language.",
+                options: &[],
+                subcommands: &[],
+                parse: |args| args.done(ReplCommand::InfoFrame),
+            },
+            CommandSpec {
+                name: "functions",
+                aliases: &[],
+                usage: "[REGEX]",
+                help: "List the functions with debug information and the other function symbols matching REGEX.",
+                options: &[],
+                subcommands: &[],
+                parse: |args| Ok(ReplCommand::InfoFunctions(args.rest().map(String::from))),
+            },
+            CommandSpec {
+                name: "locals",
+                aliases: &[],
+                usage: "",
+                help: "Show the local variables of the selected frame.",
+                options: &[],
+                subcommands: &[],
+                parse: |args| args.done(ReplCommand::InfoLocals),
+            },
+            CommandSpec {
+                name: "registers",
+                aliases: &[],
+                usage: "",
+                help: "Show the general purpose registers.",
+                options: &[],
+                subcommands: &[],
+                parse: |args| args.done(ReplCommand::GetRegs),
+            },
+            CommandSpec {
+                name: "sharedlibrary",
+                aliases: &["dll"],
+                usage: "",
+                help: "List the shared libraries loaded by the dynamic linker.",
+                options: &[],
+                subcommands: &[],
+                parse: |args| args.done(ReplCommand::InfoSharedLibrary),
+            },
+            CommandSpec {
+                name: "symbol",
+                aliases: &[],
+                usage: "ADDRESS",
+                help: "Show the symbol ADDRESS belongs to, ADDRESS can be any expression like $rip.",
+                options: &[],
+                subcommands: &[],
+                parse: |args| match args.rest() {
+                    Some(address) => Ok(ReplCommand::InfoSymbol(String::from(address))),
+                    None => Err(format!("{}: missing ADDRESS.", args.command)),
+                },
+            },
+        ],
+        parse: |args| Err(format!("{}: missing subcommand.", args.command)),
+    },
+    CommandSpec {
+        name: "lsb",
+        aliases: &[],
+        usage: "",
+        help: "List all breakpoints, same as `info breakpoints`.",
+        options: &[],
+        subcommands: &[],
+        parse: |args| args.done(ReplCommand::ListBps),
+    },
+    CommandSpec {
+        name: "maintenance",
+        aliases: &["mt"],
+        usage: "SUBCOMMAND",
+        help: "Commands for the maintenance of the debugger itself.",
+        options: &[],
+        subcommands: &[CommandSpec {
+            name: "cache",
+            aliases: &[],
+            usage: "[clear]",
+            help: "List the symbol indices cached in ~/.cache/mini-dbg, `clear` removes them.",
+            options: &[],
+            subcommands: &[CommandSpec {
+                name: "clear",
+                aliases: &[],
+                usage: "",
+                help: "Remove all symbol indices from the cache.",
+                options: &[],
+                subcommands: &[],
+                parse: |args| args.done(ReplCommand::MaintCacheClear),
+            }],
+            parse: |args| args.done(ReplCommand::MaintCacheInfo),
+        }],
+        parse: |args| Err(format!("{}: missing subcommand.", args.command)),
+    },
+    CommandSpec {
+        name: "next",
+        aliases: &["n"],
+        usage: "[COUNT]",
+        help: "Step to the next source line, stepping over calls.",
+        options: &[],
+        subcommands: &[],
+        parse: |args| {
+            let count = args.count()?;
+            args.done(ReplCommand::Next(count))
+        },
+    },
+    CommandSpec {
+        name: "print",
+        aliases: &["p"],
+        usage: "[/r] EXPRESSION",
+        help: "Print a variable of the selected frame, a global like `file.c::name`, a register like $rip or a number. /r shows the raw layout of Rust types.",
+        options: &["/r"],
+        subcommands: &[],
+        parse: |args| {
+            let raw = args.peek() == Some("/r");
+            if raw {
+                args.next();
+            }
+            match args.rest() {
+                Some(expression) => Ok(ReplCommand::Print {
+                    expression: String::from(expression),
+                    raw,
+                }),
+                None => Err(format!("{}: missing EXPRESSION.", args.command)),
+            }
+        },
+    },
+    CommandSpec {
+        name: "regs",
+        aliases: &[],
+        usage: "",
+        help: "Show the general purpose registers, same as `info registers`.",
+        options: &[],
+        subcommands: &[],
+        parse: |args| args.done(ReplCommand::GetRegs),
+    },
+    CommandSpec {
+        name: "set",
+        aliases: &[],
+        usage: "SUBCOMMAND",
+        help: "Change a setting of the debugger.",
+        options: &[],
+        subcommands: &[
+            CommandSpec {
+                name: "debug-file-directory",
+                aliases: &[],
+                usage: "DIRS",
+                help: "Search separate debug files in DIRS, a colon separated list, instead of /usr/lib/debug.",
+                options: &[],
+                subcommands: &[],
+                parse: |args| {
+                    let directories = args.next().unwrap_or_default();
+                    let directories = directories
+                        .split(':')
+                        .map(|dir| dir.trim_end_matches('/'))
+                        .filter(|dir| !dir.is_empty())
+                        .map(String::from)
+                        .collect();
+                    args.done(ReplCommand::SetDebugFileDirectory(directories))
+                },
+            },
+            CommandSpec {
+                name: "dprintf-log",
+                aliases: &[],
+                usage: "[FILE]",
+                help: "Append dprintf output to FILE, or print it to the console without FILE.",
+                options: &[],
+                subcommands: &[],
+                parse: |args| {
+                    let file = args.next().map(String::from);
+                    args.done(ReplCommand::SetDprintfLog(file))
+                },
+            },
+            CommandSpec {
+                name: "pretty-printers",
+                aliases: &[],
+                usage: "on|off",
+                help: "Show Vec, String, Option, Box, Rc, HashMap and other Rust types like Rust does, or their raw layout.",
+                options: &[],
+                subcommands: &[],
+                parse: |args| {
+                    let command = args.command.clone();
+                    let on = match args.next() {
+                        Some("on") => true,
+                        Some("off") => false,
+                        _ => return Err(format!("{}: expected on or off.", command)),
+                    };
+                    args.done(ReplCommand::SetPrettyPrinters(on))
+                },
+            },
+        ],
+        parse: |args| Err(format!("{}: missing subcommand.", args.command)),
+    },
+    CommandSpec {
+        name: "source",
+        aliases: &[],
+        usage: "FILE",
+        help: "Execute the commands in FILE.",
+        options: &[],
+        subcommands: &[],
+        parse: |args| {
+            let command = args.command.clone();
+            let file = args.next().ok_or(format!("{}: missing FILE.", command))?;
+            let file = String::from(file);
+            args.done(ReplCommand::Source(file))
+        },
+    },
+    CommandSpec {
+        name: "step",
+        aliases: &[],
+        usage: "[COUNT]",
+        help: "Step to the next source line, entering called functions.",
+        options: &[],
+        subcommands: &[],
+        parse: |args| {
+            let count = args.count()?;
+            args.done(ReplCommand::Step(count))
+        },
+    },
+    CommandSpec {
+        name: "stepi",
+        aliases: &["s", "si"],
+        usage: "[COUNT]",
+        help: "Execute a single machine instruction.",
+        options: &[],
+        subcommands: &[],
+        parse: |args| {
+            let count = args.count()?;
+            args.done(ReplCommand::SingleStep(count))
+        },
+    },
+    CommandSpec {
+        name: "up",
+        aliases: &[],
+        usage: "[COUNT]",
+        help: "Select the frame that called the selected frame.",
+        options: &[],
+        subcommands: &[],
+        parse: |args| {
+            let count = args.count()?;
+            args.done(ReplCommand::Up(count))
+        },
+    },
+    CommandSpec {
+        name: "watch",
+        aliases: &[],
+        usage: "VARIABLE",
+        help: "Stop when the program changes a global or static VARIABLE, `file.c::name` picks one of a file.",
+        options: &[],
+        subcommands: &[],
+        parse: |args| match args.rest() {
+            Some(variable) => Ok(ReplCommand::Watch(String::from(variable))),
+            None => Err(format!("{}: missing VARIABLE.", args.command)),
+        },
+    },
+];
+
+/// A token of the command line and where it starts in the input.
+struct Token {
+    text: String,
+    start: usize,
+}
+
+/// The arguments following a command word.
+pub struct Args<'a> {
+    /// The command as typed so far, e.g. `info registers`, for error messages.
+    command: String,
+    input: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Args<'_> {
+    fn next(&mut self) -> Option<&str> {
+        let token = self.tokens.get(self.pos)?;
+        self.pos += 1;
+        Some(&token.text)
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.pos).map(|t| t.text.as_str())
+    }
+
+    /// Everything after the current position as typed, including quotes.
+    fn rest(&mut self) -> Option<&str> {
+        let start = self.tokens.get(self.pos)?.start;
+        self.pos = self.tokens.len();
+        Some(self.input[start..].trim_end())
+    }
+
+    /// Finish parsing, there must be no arguments left.
+    fn done(&self, command: ReplCommand) -> Result<ReplCommand, String> {
+        match self.peek() {
+            Some(arg) => Err(format!("{}: unexpected argument \"{}\".", self.command, arg)),
+            None => Ok(command),
+        }
+    }
+
+    /// An optional positive count, 1 if missing.
+    fn count(&mut self) -> Result<usize, String> {
+        let command = self.command.clone();
+        match self.next() {
+            None => Ok(1),
+            Some(count) => match count.parse() {
+                Ok(count) if count > 0 => Ok(count),
+                _ => Err(format!("{}: COUNT must be a positive number, got \"{}\".", command, count)),
+            },
+        }
+    }
+
+    fn location(&mut self) -> Result<LocationSpec, String> {
+        let command = self.command.clone();
+        let arg = self
+            .next()
+            .ok_or_else(|| format!("{}: missing LOCATION.", command))?;
+        parse_location(arg).ok_or_else(|| format!("{}: invalid LOCATION \"{}\".", command, arg))
+    }
+
+    fn breakpoint(&mut self) -> Result<BreakpointRef, String> {
+        let command = self.command.clone();
+        let arg = self
+            .next()
+            .ok_or_else(|| format!("{}: missing BREAKPOINT.", command))?;
+        if let Some(address) = parse_address(arg) {
+            Ok(BreakpointRef::Address(address))
+        } else if let Ok(number) = arg.parse() {
+            Ok(BreakpointRef::Number(number))
+        } else {
+            Err(format!("{}: invalid BREAKPOINT \"{}\".", command, arg))
+        }
+    }
+}
+
+/// Parse a command line. `None` for an empty line.
+pub fn parse_command(input: &str) -> Result<Option<ReplCommand>, String> {
+    let mut tokens = tokenize(input)?;
+    if tokens.is_empty() {
+        return Ok(None);
+    }
+    // Options may follow the command word directly, gdb style: `print/r x`, `disassemble/s`.
+    let with_options = |word: &str| find_command(COMMANDS, word, "").is_ok_and(|spec| !spec.options.is_empty());
+    if let Some(idx) = tokens[0].text.find('/').filter(|idx| *idx > 0 && with_options(&tokens[0].text[..*idx])) {
+        let option = Token {
+            text: tokens[0].text[idx..].to_string(),
+            start: tokens[0].start + idx,
+        };
+        tokens[0].text.truncate(idx);
+        tokens.insert(1, option);
+    }
+    let mut args = Args {
+        command: String::new(),
+        input,
+        tokens,
+        pos: 0,
+    };
+    let mut specs = COMMANDS;
+    while let Some(word) = args.next().map(String::from) {
+        let spec = find_command(specs, &word, &args.command)?;
+        args.command = format!("{} {}", args.command, spec.name).trim().to_string();
+        if spec.subcommands.is_empty() || args.peek().is_none() {
+            return (spec.parse)(&mut args).map(Some);
+        }
+        specs = spec.subcommands;
+    }
+    Ok(None)
+}
+
+/// Look up `word` by name, alias or unique prefix.
+pub fn find_command(
+    specs: &'static [CommandSpec],
+    word: &str,
+    parent: &str,
+) -> Result<&'static CommandSpec, String> {
+    if let Some(spec) = specs
+        .iter()
+        .find(|s| s.name == word || s.aliases.contains(&word))
+    {
+        return Ok(spec);
+    }
+
+    let matches: Vec<&CommandSpec> = specs.iter().filter(|s| s.name.starts_with(word)).collect();
+    let full_command = format!("{} {}", parent, word).trim().to_string();
+    match matches.as_slice() {
+        [spec] => Ok(spec),
+        [] => {
+            let suggestions = suggestions(specs, word);
+            if suggestions.is_empty() {
+                Err(format!("Undefined command: \"{}\". Try \"help\".", full_command))
+            } else {
+                Err(format!(
+                    "Undefined command: \"{}\". Did you mean {}?",
+                    full_command,
+                    suggestions.join(" or ")
+                ))
+            }
+        }
         _ => {
-            if input.starts_with("b") {
-                let parts: Vec<&str> = input.trim().split(' ').collect();
-                if parts.len() == 2 {
-                    if let Some(parsed_addr) = parse_address(parts[1]) {
-                        ReplCommand::SetBp(parsed_addr)
-                    } else {
-                        ReplCommand::SetBpName(String::from(parts[1]))
-                    }
-                } else {
-                    println!("unsupported breakpoint command format.");
-                    ReplCommand::Unknown
-                }
-            } else if input.starts_with("rb") {
-                let parts: Vec<&str> = input.trim().split(' ').collect();
-                if parts.len() == 2 {
-                    if let Some(parsed_addr) = parse_address(parts[1]) {
-                        ReplCommand::DeleteBp(parsed_addr)
-                    } else {
-                        println!("unsupported breakpoint command format.");
-                        ReplCommand::Unknown
+            let names: Vec<&str> = matches.iter().map(|s| s.name).collect();
+            Err(format!("Ambiguous command \"{}\": {}.", full_command, names.join(", ")))
+        }
+    }
+}
+
+/// Help for all commands or the command named by `topic`.
+pub fn help(topic: Option<&str>) -> Result<String, String> {
+    let mut specs = COMMANDS;
+    let mut spec: Option<&CommandSpec> = None;
+    let mut path = String::new();
+    for word in topic.unwrap_or("").split_whitespace() {
+        let found = find_command(specs, word, &path)?;
+        path = format!("{} {}", path, found.name).trim().to_string();
+        specs = found.subcommands;
+        spec = Some(found);
+    }
+
+    let mut text = String::new();
+    if let Some(spec) = spec {
+        text.push_str(format!("{} {}", path, spec.usage).trim_end());
+        text.push_str(&format!("\n  {}\n", spec.help));
+        if !spec.aliases.is_empty() {
+            text.push_str(&format!("  Aliases: {}\n", spec.aliases.join(", ")));
+        }
+    }
+    for spec in specs {
+        let mut names = String::from(spec.name);
+        if !spec.aliases.is_empty() {
+            names = format!("{} ({})", names, spec.aliases.join(", "));
+        }
+        text.push_str(&format!("  {:<30} {}\n", names, spec.help));
+    }
+    Ok(text)
+}
+
+/// Names and aliases of all top level commands.
+pub fn command_names() -> Vec<&'static str> {
+    let mut names: Vec<&str> = COMMANDS
+        .iter()
+        .flat_map(|s| std::iter::once(s.name).chain(s.aliases.iter().copied()))
+        .collect();
+    names.sort();
+    names
+}
+
+/// Split the input at whitespace. Single and double quotes group words,
+/// backslash escapes the next character.
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut current: Option<Token> = None;
+    let mut quote = None;
+    let mut chars = input.char_indices();
+    while let Some((idx, c)) = chars.next() {
+        if quote.is_none() && c.is_whitespace() {
+            if let Some(token) = current.take() {
+                tokens.push(token);
+            }
+            continue;
+        }
+        let token = current.get_or_insert(Token {
+            text: String::new(),
+            start: idx,
+        });
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), _) if q == c => quote = None,
+            (Some('\''), _) => token.text.push(c),
+            (_, '\\') => match chars.next() {
+                Some((_, 'n')) => token.text.push('\n'),
+                Some((_, 't')) => token.text.push('\t'),
+                Some((_, escaped)) => token.text.push(escaped),
+                None => return Err(String::from("Trailing backslash.")),
+            },
+            _ => token.text.push(c),
+        }
+    }
+    if quote.is_some() {
+        return Err(String::from("Unterminated quoted string."));
+    }
+    tokens.extend(current);
+    Ok(tokens)
+}
+
+/// `disassemble [/s] [LOCATION|START,END|START,+LENGTH]`
+fn parse_disassemble(args: &mut Args) -> Result<ReplCommand, String> {
+    let command = args.command.clone();
+    let with_source = matches!(args.peek(), Some("/s") | Some("/m"));
+    if with_source {
+        args.next();
+    }
+    let range = match args.next() {
+        None => None,
+        Some(arg) => match arg.split_once(',') {
+            Some((start, end)) => {
+                let start = parse_address(start);
+                let end = match end.strip_prefix('+') {
+                    Some(length) => parse_number(length).and_then(|l| start.and_then(|s| s.checked_add(l))),
+                    None => parse_address(end),
+                };
+                match (start, end) {
+                    (Some(start), Some(end)) if start < end => {
+                        Some(DisassembleRange::Range(start, end))
                     }
-                } else {
-                    println!("unsupported breakpoint command format.");
-                    ReplCommand::Unknown
+                    _ => return Err(format!("{}: invalid address range \"{}\".", command, arg)),
                 }
-            } else {
-                ReplCommand::Unknown
             }
+            None => match parse_location(arg) {
+                Some(location) => Some(DisassembleRange::Around(location)),
+                None => return Err(format!("{}: invalid LOCATION \"{}\".", command, arg)),
+            },
+        },
+    };
+    args.done(ReplCommand::Disassemble { range, with_source })
+}
+
+/// `dprintf LOCATION,"FORMAT",ARGUMENT...`
+fn parse_dprintf(args: &mut Args) -> Result<ReplCommand, String> {
+    let command = args.command.clone();
+    let usage = || format!("{}: expected LOCATION,\"FORMAT\",ARGUMENT...", command);
+    let input = args.rest().ok_or_else(usage)?;
+    let (location, rest) = input.split_once(',').ok_or_else(usage)?;
+    let location = parse_location(location.trim())
+        .ok_or_else(|| format!("{}: invalid LOCATION \"{}\".", command, location.trim()))?;
+
+    let mut chars = rest.trim_start().chars();
+    if chars.next() != Some('"') {
+        return Err(usage());
+    }
+    let mut format = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => break,
+            Some('\\') => match chars.next() {
+                Some('n') => format.push('\n'),
+                Some('t') => format.push('\t'),
+                Some('r') => format.push('\r'),
+                Some('0') => format.push('\0'),
+                Some(c) => format.push(c),
+                None => return Err(String::from("Unterminated quoted string.")),
+            },
+            Some(c) => format.push(c),
+            None => return Err(String::from("Unterminated quoted string.")),
         }
     }
+
+    let rest = chars.as_str().trim();
+    let arguments = if rest.is_empty() {
+        Vec::new()
+    } else {
+        let rest = rest.strip_prefix(',').ok_or_else(usage)?;
+        rest.split(',').map(|arg| String::from(arg.trim())).collect()
+    };
+    if arguments.iter().any(String::is_empty) {
+        return Err(format!("{}: empty ARGUMENT.", command));
+    }
+    Ok(ReplCommand::Dprintf {
+        location,
+        format,
+        arguments,
+    })
 }
 
+/// `*ADDRESS`, `0xADDRESS`, `FILE:LINE`, `LINE` or a function name.
+fn parse_location(arg: &str) -> Option<LocationSpec> {
+    if let Some(address) = parse_address(arg) {
+        return Some(LocationSpec::Address(address));
+    }
+    if let Ok(line) = arg.parse() {
+        return Some(LocationSpec::Line(line));
+    }
+    if let Some((file, line)) = arg.rsplit_once(':') {
+        if let Ok(line) = line.parse() {
+            return Some(LocationSpec::FileLine(String::from(file), line));
+        }
+    }
+    if arg.is_empty() || arg.starts_with('*') {
+        return None;
+    }
+    Some(LocationSpec::Function(String::from(arg)))
+}
+
+/// `0x` prefixed hex, or hex after a `*`.
 fn parse_address(addr: &str) -> Option<usize> {
-    let addr_without_0x = if addr.to_lowercase().starts_with("0x") {
-        &addr[2..]
-    } else {
-        &addr
+    let hex = match addr.strip_prefix('*') {
+        Some(addr) => addr.strip_prefix("0x").unwrap_or(addr),
+        None => addr.strip_prefix("0x")?,
     };
-    usize::from_str_radix(addr_without_0x, 16).ok()
+    usize::from_str_radix(hex, 16).ok()
+}
+
+/// Decimal, or hex with a 0x prefix.
+pub fn parse_number(number: &str) -> Option<usize> {
+    match number.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => number.parse().ok(),
+    }
+}
+
+/// Commands with a name or alias close to `word`.
+fn suggestions(specs: &[CommandSpec], word: &str) -> Vec<String> {
+    let mut candidates: Vec<(usize, &str)> = specs
+        .iter()
+        .flat_map(|s| std::iter::once(s.name).chain(s.aliases.iter().copied()))
+        .map(|name| (edit_distance(word, name), name))
+        .filter(|(distance, _)| *distance <= if word.len() > 3 { 2 } else { 1 })
+        .collect();
+    candidates.sort();
+    candidates
+        .iter()
+        .take(3)
+        .map(|(_, name)| format!("\"{}\"", name))
+        .collect()
+}
+
+/// Levenshtein distance.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut previous = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous + usize::from(ca != *cb);
+            previous = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(previous + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(input: &str) -> String {
+        match parse_command(input) {
+            Ok(Some(command)) => format!("{:?}", command),
+            Ok(None) => String::from("None"),
+            Err(message) => message,
+        }
+    }
+
+    fn texts(input: &str) -> Vec<String> {
+        tokenize(input)
+            .unwrap()
+            .into_iter()
+            .map(|t| t.text)
+            .collect()
+    }
+
+    #[test]
+    fn tokenize_splits_at_whitespace() {
+        assert_eq!(texts("  b   main\t"), vec!["b", "main"]);
+        assert!(texts("   ").is_empty());
+        let starts: Vec<usize> = tokenize("p  x").unwrap().iter().map(|t| t.start).collect();
+        assert_eq!(starts, vec![0, 3]);
+    }
+
+    #[test]
+    fn tokenize_quotes_and_escapes() {
+        assert_eq!(texts(r#"b "my file.c:3""#), vec!["b", "my file.c:3"]);
+        assert_eq!(
+            texts(r#"x 'a\n b' "c\n" d\ e"#),
+            vec!["x", "a\\n b", "c\n", "d e"]
+        );
+        assert_eq!(texts(r#"a"b c"d"#), vec!["ab cd"]);
+        assert_eq!(
+            tokenize("p 'x").err().unwrap(),
+            "Unterminated quoted string."
+        );
+        assert_eq!(tokenize("p x\\").err().unwrap(), "Trailing backslash.");
+    }
+
+    #[test]
+    fn aliases_and_prefixes() {
+        assert_eq!(parse("bt"), "Backtrace");
+        assert_eq!(parse("back"), "Backtrace");
+        assert_eq!(parse("c"), "Continue");
+        assert_eq!(parse("fin"), "Finish");
+        assert_eq!(
+            parse("disas"),
+            "Disassemble { range: None, with_source: false }"
+        );
+        assert_eq!(parse("wat g"), "Watch(\"g\")");
+        assert_eq!(parse(""), "None");
+    }
+
+    #[test]
+    fn subcommands() {
+        assert_eq!(parse("info locals"), "InfoLocals");
+        assert_eq!(parse("i b"), "ListBps");
+        assert_eq!(parse("info dll"), "InfoSharedLibrary");
+        assert_eq!(parse("mt cache"), "MaintCacheInfo");
+        assert_eq!(parse("maint cache clear"), "MaintCacheClear");
+        assert_eq!(parse("set pretty-printers off"), "SetPrettyPrinters(false)");
+        assert_eq!(parse("info"), "info: missing subcommand.");
+        assert_eq!(parse("info symbol"), "info symbol: missing ADDRESS.");
+        assert_eq!(
+            parse("info locals x"),
+            "info locals: unexpected argument \"x\"."
+        );
+    }
+
+    #[test]
+    fn arguments() {
+        assert_eq!(parse("b main"), "SetBp(Function(\"main\"))");
+        assert_eq!(parse("b 'a b.c':12"), "SetBp(FileLine(\"a b.c\", 12))");
+        assert_eq!(parse("b *0x1139"), "SetBp(Address(4409))");
+        assert_eq!(parse("d 2"), "DeleteBp(Number(2))");
+        assert_eq!(parse("n 3"), "Next(3)");
+        assert_eq!(
+            parse("n 0"),
+            "next: COUNT must be a positive number, got \"0\"."
+        );
+        assert_eq!(
+            parse("p a + b"),
+            "Print { expression: \"a + b\", raw: false }"
+        );
+    }
+
+    #[test]
+    fn options_after_the_command_word() {
+        let raw = "Print { expression: \"x\", raw: true }";
+        assert_eq!(parse("print /r x"), raw);
+        assert_eq!(parse("print/r x"), raw);
+        assert_eq!(parse("p/r x"), raw);
+        assert_eq!(
+            parse("disas/s"),
+            "Disassemble { range: None, with_source: true }"
+        );
+        // Only commands with options are split at a slash.
+        assert_eq!(
+            parse("bt/x"),
+            "Undefined command: \"bt/x\". Did you mean \"bt\"?"
+        );
+    }
+
+    #[test]
+    fn disassemble_ranges() {
+        assert_eq!(
+            parse("disas 0x10,0x20"),
+            "Disassemble { range: Some(Range(16, 32)), with_source: false }"
+        );
+        assert_eq!(
+            parse("disas 0x10,+8"),
+            "Disassemble { range: Some(Range(16, 24)), with_source: false }"
+        );
+        assert_eq!(
+            parse("disas 0x20,0x10"),
+            "disassemble: invalid address range \"0x20,0x10\"."
+        );
+        assert_eq!(
+            parse("disas 0x10,+0xffffffffffffffff"),
+            "disassemble: invalid address range \"0x10,+0xffffffffffffffff\"."
+        );
+    }
+
+    #[test]
+    fn missing_arguments() {
+        assert_eq!(parse("print"), "print: missing EXPRESSION.");
+        assert_eq!(parse("p /r"), "print: missing EXPRESSION.");
+        assert_eq!(parse("b"), "break: missing LOCATION.");
+        assert_eq!(parse("delete"), "delete: missing BREAKPOINT.");
+        assert_eq!(parse("source"), "source: missing FILE.");
+        assert_eq!(parse("watch"), "watch: missing VARIABLE.");
+    }
+
+    #[test]
+    fn suggestions_for_unknown_commands() {
+        assert_eq!(
+            parse("bakc"),
+            "Undefined command: \"bakc\". Did you mean \"back\"?"
+        );
+        assert_eq!(
+            parse("info lcals"),
+            "Undefined command: \"info lcals\". Did you mean \"locals\"?"
+        );
+        assert_eq!(
+            parse("xyzzy"),
+            "Undefined command: \"xyzzy\". Try \"help\"."
+        );
+        assert_eq!(parse("d"), "delete: missing BREAKPOINT.");
+        assert_eq!(parse("st"), "Ambiguous command \"st\": step, stepi.");
+    }
 }
@@ -0,0 +1,75 @@
+use object::{Object, ObjectSection, ObjectSegment, ObjectSymbol};
+
+use crate::debugfile::map_file;
+use crate::debuginfo::DebugInfo;
+
+/// The dynamic linker calls this function whenever the list of loaded objects changes.
+pub const DEBUG_STATE_FUNCTION: &str = "_dl_debug_state";
+/// The dynamic linker's `struct r_debug`.
+pub const R_DEBUG_SYMBOL: &str = "_r_debug";
+
+/// Offsets into `struct r_debug` on x86-64.
+pub const R_DEBUG_MAP: usize = 8;
+pub const R_DEBUG_STATE: usize = 24;
+/// r_state while no objects are being added or removed.
+pub const RT_CONSISTENT: u64 = 0;
+
+/// Offsets into `struct link_map` on x86-64.
+pub const LINK_MAP_ADDR: usize = 0;
+pub const LINK_MAP_NAME: usize = 8;
+pub const LINK_MAP_NEXT: usize = 24;
+
+/// A shared object loaded by the dynamic linker.
+pub struct SharedLibrary {
+    pub path: String,
+    /// Load bias, the difference between addresses in memory and in the file.
+    pub base: usize,
+    /// First and last (exclusive) address of its segments in memory.
+    pub start: usize,
+    pub end: usize,
+    pub debug_info: DebugInfo,
+}
+
+impl SharedLibrary {
+    /// Load the symbols and DWARF of the object at `path`. `None` if it is not
+    /// an ELF file, like the `linux-vdso.so.1` entry of the link map.
+    pub fn load(path: &str, base: usize, debug_file_directories: &[String]) -> Option<SharedLibrary> {
+        let map = map_file(path)?;
+        let object = object::File::parse(&*map).ok()?;
+        let (start, end) = segment_range(&object)?;
+        Some(SharedLibrary {
+            path: String::from(path),
+            base,
+            start: start + base,
+            end: end + base,
+            debug_info: DebugInfo::create(path, debug_file_directories).ok()?,
+        })
+    }
+
+    pub fn contains(&self, addr: usize) -> bool {
+        addr >= self.start && addr < self.end
+    }
+}
+
+/// Lowest and highest (exclusive) address of the loadable segments of an ELF file.
+pub fn segment_range(object: &object::File) -> Option<(usize, usize)> {
+    let start = object.segments().map(|s| s.address()).min()?;
+    let end = object.segments().map(|s| s.address() + s.size()).max()?;
+    Some((start as usize, end as usize))
+}
+
+/// Address of the symbol `name` of any kind, e.g. the data symbol `_r_debug`.
+pub fn symbol_address(object: &object::File, name: &str) -> Option<usize> {
+    object
+        .symbols()
+        .chain(object.dynamic_symbols())
+        .find(|symbol| symbol.is_definition() && symbol.name() == Ok(name))
+        .map(|symbol| symbol.address() as usize)
+}
+
+/// Path of the dynamic linker from `.interp`, `None` for static executables.
+pub fn interpreter(object: &object::File) -> Option<String> {
+    let data = object.section_by_name(".interp")?.data().ok()?;
+    let path = data.split(|b| *b == 0).next()?;
+    Some(String::from_utf8_lossy(path).into_owned())
+}
@@ -0,0 +1,200 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use gimli::{Dwarf, DwarfPackage, DwoId, EndianReader, Reader as _, RunTimeEndian};
+use memmap2::Mmap;
+use object::{Object, ObjectSection};
+
+use crate::debugfile::map_file;
+
+/// The reader all DWARF is loaded with, owning its sections so split units can be
+/// loaded on demand next to those of the executable.
+pub type Reader = EndianReader<RunTimeEndian, SectionData>;
+
+/// What the sections of a `Reader` are read from.
+#[derive(Debug, Clone)]
+pub enum SectionData {
+    /// The memory mapped file, shared by all of its sections.
+    Mapped(Rc<Mmap>),
+    /// A compressed section, decompressed.
+    Decompressed(Rc<[u8]>),
+}
+
+impl Deref for SectionData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            SectionData::Mapped(map) => map,
+            SectionData::Decompressed(data) => data,
+        }
+    }
+}
+
+// Both are behind an `Rc`, the bytes don't move when a `SectionData` is moved or cloned.
+unsafe impl gimli::StableDeref for SectionData {}
+unsafe impl gimli::CloneStableDeref for SectionData {}
+
+/// Load the DWARF sections of `object`, which was parsed from `map`. With `dwo` the
+/// `.dwo` suffixed sections of a split DWARF file are loaded instead.
+pub fn load_dwarf(map: &Rc<Mmap>, object: &object::File, dwo: bool) -> Result<Dwarf<Reader>, gimli::Error> {
+    Dwarf::load(|id| Ok(load_section(map, object, id, dwo)))
+}
+
+/// A second `Dwarf` over the sections of `dwarf`, sharing their data, for users like
+/// addr2line that want to own theirs.
+pub fn share_dwarf(dwarf: &Dwarf<Reader>) -> Dwarf<Reader> {
+    Dwarf {
+        debug_abbrev: dwarf.debug_abbrev.clone(),
+        debug_addr: dwarf.debug_addr.clone(),
+        debug_aranges: dwarf.debug_aranges.clone(),
+        debug_info: dwarf.debug_info.clone(),
+        debug_line: dwarf.debug_line.clone(),
+        debug_line_str: dwarf.debug_line_str.clone(),
+        debug_str: dwarf.debug_str.clone(),
+        debug_str_offsets: dwarf.debug_str_offsets.clone(),
+        debug_types: dwarf.debug_types.clone(),
+        locations: dwarf.locations.clone(),
+        ranges: dwarf.ranges.clone(),
+        file_type: dwarf.file_type,
+        sup: dwarf.sup.clone(),
+        abbreviations_cache: Default::default(),
+    }
+}
+
+/// A section of `object` as a range of `map`, without copying it. Only compressed
+/// sections have their own buffer.
+fn load_section(map: &Rc<Mmap>, object: &object::File, id: gimli::SectionId, dwo: bool) -> Reader {
+    let name = if dwo { id.dwo_name() } else { Some(id.name()) };
+    let data = name
+        .and_then(|name| object.section_by_name(name))
+        .and_then(|section| section.uncompressed_data().ok())
+        .unwrap_or_default();
+    let endian = endian(object);
+    if let Cow::Borrowed(section) = data {
+        let start = (section.as_ptr() as usize).wrapping_sub(map.as_ptr() as usize);
+        if start.checked_add(section.len()).is_some_and(|end| end <= map.len()) {
+            return EndianReader::new(SectionData::Mapped(map.clone()), endian)
+                .range(start..start + section.len());
+        }
+    }
+    EndianReader::new(SectionData::Decompressed(Rc::from(&*data)), endian)
+}
+
+pub fn endian(object: &object::File) -> RunTimeEndian {
+    if object.is_little_endian() {
+        RunTimeEndian::Little
+    } else {
+        RunTimeEndian::Big
+    }
+}
+
+/// Finds the split units of the skeleton units in an executable built with
+/// `-gsplit-dwarf`, in its `.dwp` package or in the `.dwo` file each skeleton names.
+pub struct SplitDwarf {
+    package: Option<DwarfPackage<Reader>>,
+    /// The split units loaded so far, shared by addr2line and `GimliWrapper`. `None` for
+    /// those that could not be found.
+    loaded: RefCell<HashMap<DwoId, Option<Arc<Dwarf<Reader>>>>>,
+}
+
+impl SplitDwarf {
+    /// Look for the package `TARGET.dwp` that `dwp` writes next to the executable.
+    pub fn open(target: &str) -> SplitDwarf {
+        let path = format!("{}.dwp", target);
+        let package = map_file(&path).map(Rc::new).and_then(|map| {
+            let object = object::File::parse(&**map).ok()?;
+            let empty = EndianReader::new(SectionData::Decompressed(Rc::from(&[][..])), endian(&object));
+            DwarfPackage::load(|id| Ok::<_, gimli::Error>(load_section(&map, &object, id, true)), empty)
+                .ok()
+        });
+        SplitDwarf {
+            package,
+            loaded: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// The DWARF of the split unit `dwo_id` of `parent`, from the package or from the
+    /// `.dwo` file `name`, which is relative to `comp_dir`.
+    pub fn load(
+        &self,
+        dwo_id: DwoId,
+        comp_dir: Option<&str>,
+        name: Option<&str>,
+        parent: &Dwarf<Reader>,
+    ) -> Option<Arc<Dwarf<Reader>>> {
+        if let Some(dwarf) = self.loaded.borrow().get(&dwo_id) {
+            return dwarf.clone();
+        }
+        // addr2line shares split units in an `Arc`.
+        let dwarf = self
+            .load_split(dwo_id, comp_dir, name, parent)
+            .map(|mut dwarf| {
+                // The line table stays in the skeleton unit, whose DWARF 5 file names are in
+                // the executable's .debug_line_str, which split files don't have.
+                dwarf.debug_line_str = parent.debug_line_str.clone();
+                dwarf
+            })
+            .map(Arc::new);
+        self.loaded.borrow_mut().insert(dwo_id, dwarf.clone());
+        dwarf
+    }
+
+    fn load_split(
+        &self,
+        dwo_id: DwoId,
+        comp_dir: Option<&str>,
+        name: Option<&str>,
+        parent: &Dwarf<Reader>,
+    ) -> Option<Dwarf<Reader>> {
+        if let Some(package) = &self.package {
+            if let Ok(Some(dwarf)) = package.find_cu(dwo_id, parent) {
+                return Some(dwarf);
+            }
+        }
+
+        let mut path = PathBuf::new();
+        if let Some(comp_dir) = comp_dir {
+            path.push(comp_dir);
+        }
+        // An absolute name replaces the directory.
+        path.push(name?);
+        let map = Rc::new(map_file(&path.to_string_lossy())?);
+        let object = object::File::parse(&**map).ok()?;
+        let mut dwarf = load_dwarf(&map, &object, true).ok()?;
+        dwarf.make_dwo(parent);
+        Some(dwarf)
+    }
+
+    /// The split unit of `skeleton`, `None` if it isn't a skeleton unit or its
+    /// split unit can't be found.
+    pub fn unit(
+        &self,
+        skeleton: &gimli::Unit<Reader>,
+        parent: &Dwarf<Reader>,
+    ) -> Option<(Arc<Dwarf<Reader>>, gimli::Unit<Reader>)> {
+        let dwo_id = skeleton.dwo_id?;
+        let comp_dir = skeleton.comp_dir.as_ref().and_then(|dir| dir.to_string_lossy().ok());
+        let name = skeleton.dwo_name().ok()?.and_then(|name| parent.attr_string(skeleton, name).ok());
+        let name = name.as_ref().and_then(|name| name.to_string_lossy().ok());
+        let dwarf = self.load(dwo_id, comp_dir.as_deref(), name.as_deref(), parent)?;
+
+        let mut headers = dwarf.units();
+        while let Ok(Some(header)) = headers.next() {
+            let mut unit = match dwarf.unit(header) {
+                Ok(unit) => unit,
+                Err(_) => continue,
+            };
+            if unit.dwo_id == Some(dwo_id) {
+                unit.copy_relocated_attributes(skeleton);
+                return Some((dwarf, unit));
+            }
+        }
+        None
+    }
+}
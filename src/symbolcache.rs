@@ -0,0 +1,248 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use object::Object;
+use serde_json::{json, Value};
+
+/// Changes whenever the format of the cached index does.
+const VERSION: u64 = 2;
+
+/// What `GimliWrapper` knows about the units of a file before it reads their DIEs.
+#[derive(Default)]
+pub struct SymbolIndex {
+    /// Paths of the files in the line table header of each unit.
+    pub files: Vec<Vec<String>>,
+    /// Indices of the units that define a function, by its name.
+    pub function_units: HashMap<String, Vec<usize>>,
+    /// The same for the variables outside of functions.
+    pub variable_units: HashMap<String, Vec<usize>>,
+    /// Low pc, high pc and unit index of every address range of the compilation units,
+    /// sorted by low pc.
+    pub unit_ranges: Vec<(usize, usize, usize)>,
+}
+
+/// A file of the cache.
+pub struct CacheEntry {
+    pub path: String,
+    /// The file the index was built from.
+    pub binary: String,
+    /// The build-id or modification time and size of the binary.
+    pub key: String,
+    /// Size of the cache file in bytes.
+    pub size: u64,
+}
+
+/// `$XDG_CACHE_HOME/mini-dbg`, by default `~/.cache/mini-dbg`.
+pub fn directory() -> Option<PathBuf> {
+    let cache = env::var_os("XDG_CACHE_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+    Some(cache.join("mini-dbg"))
+}
+
+/// The cached index of the file at `path`, `None` if there is none or the file changed
+/// since it was built.
+pub fn load(object: &object::File, path: &str, units: usize) -> Option<SymbolIndex> {
+    load_in(&directory()?, object, path, units)
+}
+
+fn load_in(directory: &Path, object: &object::File, path: &str, units: usize) -> Option<SymbolIndex> {
+    let (file, key) = cache_file(directory, object, path)?;
+    let mut reader = BufReader::new(fs::File::open(file).ok()?);
+    let header = read_header(&mut reader)?;
+    if header["key"] != key || header["units"] != units {
+        return None;
+    }
+    let index: Value = serde_json::from_reader(reader).ok()?;
+    let numbers = |value: &Value| -> Option<Vec<usize>> {
+        value.as_array()?.iter().map(|n| Some(n.as_u64()? as usize)).collect()
+    };
+    let names = |value: &Value| -> Option<HashMap<String, Vec<usize>>> {
+        value.as_object()?.iter().map(|(name, units)| Some((name.clone(), numbers(units)?))).collect()
+    };
+    Some(SymbolIndex {
+        files: index["files"]
+            .as_array()?
+            .iter()
+            .map(|files| files.as_array()?.iter().map(|file| file.as_str().map(String::from)).collect())
+            .collect::<Option<_>>()?,
+        function_units: names(&index["functions"])?,
+        variable_units: names(&index["variables"])?,
+        unit_ranges: index["ranges"]
+            .as_array()?
+            .iter()
+            .map(|range| match numbers(range)?.as_slice() {
+                [low_pc, high_pc, idx] => Some((*low_pc, *high_pc, *idx)),
+                _ => None,
+            })
+            .collect::<Option<_>>()?,
+    })
+}
+
+/// Save the index of the file at `path`, replacing one of an older version of the file.
+pub fn store(object: &object::File, path: &str, index: &SymbolIndex) -> io::Result<()> {
+    let directory = directory().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "HOME is not set"))?;
+    store_in(&directory, object, path, index)
+}
+
+fn store_in(directory: &Path, object: &object::File, path: &str, index: &SymbolIndex) -> io::Result<()> {
+    let (file, key) = cache_file(directory, object, path).ok_or(io::ErrorKind::NotFound)?;
+    fs::create_dir_all(directory)?;
+    let header = json!({
+        "version": VERSION,
+        "key": key,
+        "binary": path,
+        "units": index.files.len(),
+    });
+    let ranges: Vec<[usize; 3]> = index
+        .unit_ranges
+        .iter()
+        .map(|(low_pc, high_pc, idx)| [*low_pc, *high_pc, *idx])
+        .collect();
+    let index = json!({
+        "files": index.files,
+        "functions": index.function_units,
+        "variables": index.variable_units,
+        "ranges": ranges,
+    });
+    // Another debugger reading the cache never sees half a file.
+    let temporary = file.with_extension(format!("{}.tmp", std::process::id()));
+    let mut writer = io::BufWriter::new(fs::File::create(&temporary)?);
+    writeln!(writer, "{}", header)?;
+    serde_json::to_writer(&mut writer, &index)?;
+    writer.flush()?;
+    fs::rename(temporary, file)
+}
+
+/// The files in the cache, sorted by the binary they belong to.
+pub fn entries() -> Vec<CacheEntry> {
+    let files = match directory().and_then(|dir| fs::read_dir(dir).ok()) {
+        Some(files) => files,
+        None => return Vec::new(),
+    };
+    let mut entries: Vec<CacheEntry> = files
+        .map_while(Result::ok)
+        .filter_map(|file| {
+            let path = file.path();
+            let header = read_header(&mut BufReader::new(fs::File::open(&path).ok()?))?;
+            Some(CacheEntry {
+                path: path.to_string_lossy().to_string(),
+                binary: String::from(header["binary"].as_str()?),
+                key: String::from(header["key"].as_str()?),
+                size: file.metadata().ok()?.len(),
+            })
+        })
+        .collect();
+    entries.sort_by(|a, b| a.binary.cmp(&b.binary));
+    entries
+}
+
+/// Remove all files of the cache, also those of other versions and left over temporary
+/// files, returns how many there were.
+pub fn clear() -> io::Result<usize> {
+    match directory() {
+        Some(directory) => clear_in(&directory),
+        None => Ok(0),
+    }
+}
+
+fn clear_in(directory: &Path) -> io::Result<usize> {
+    let files = match fs::read_dir(directory) {
+        Ok(files) => files,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(e),
+    };
+    let mut removed = 0;
+    for file in files {
+        let file = file?;
+        if file.file_type()?.is_file() {
+            fs::remove_file(file.path())?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// The first line of a cache file, `None` if it is not one of this version.
+fn read_header(reader: &mut impl BufRead) -> Option<Value> {
+    let mut line = String::new();
+    reader.read_line(&mut line).ok()?;
+    let header: Value = serde_json::from_str(&line).ok()?;
+    (header["version"] == VERSION).then_some(header)
+}
+
+/// The cache file of the file at `path` and the key telling if it is still up to date:
+/// the build-id, or without one the modification time and size of the file.
+fn cache_file(directory: &Path, object: &object::File, path: &str) -> Option<(PathBuf, String)> {
+    if let Ok(Some(build_id)) = object.build_id() {
+        let hex: String = build_id.iter().map(|b| format!("{:02x}", b)).collect();
+        return Some((directory.join(format!("{}.json", hex)), format!("build-id {}", hex)));
+    }
+    let path = fs::canonicalize(path).ok()?;
+    let metadata = fs::metadata(&path).ok()?;
+    let mtime = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    let name = path.file_name()?.to_string_lossy();
+    let hash = crc32fast::hash(path.to_string_lossy().as_bytes());
+    Some((
+        directory.join(format!("{}-{:08x}.json", name, hash)),
+        format!("mtime {}.{:09} size {}", mtime.as_secs(), mtime.subsec_nanos(), metadata.len()),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index() -> SymbolIndex {
+        SymbolIndex {
+            files: vec![vec![String::from("/src/a.c"), String::from("/usr/include/stdio.h")], Vec::new()],
+            function_units: HashMap::from([(String::from("main"), vec![0]), (String::from("helper"), vec![0, 1])]),
+            variable_units: HashMap::from([(String::from("counter"), vec![1])]),
+            unit_ranges: vec![(0x1000, 0x1100, 0), (0x1100, 0x1180, 1)],
+        }
+    }
+
+    #[test]
+    fn store_load_and_clear() {
+        let directory = env::temp_dir().join(format!("mini-dbg-cache-test-{}", std::process::id()));
+        let binary = env::current_exe().unwrap();
+        let path = binary.to_str().unwrap();
+        let data = fs::read(&binary).unwrap();
+        let object = object::File::parse(&*data).unwrap();
+
+        store_in(&directory, &object, path, &index()).unwrap();
+        let loaded = load_in(&directory, &object, path, 2).unwrap();
+        let expected = index();
+        assert_eq!(loaded.files, expected.files);
+        assert_eq!(loaded.function_units, expected.function_units);
+        assert_eq!(loaded.variable_units, expected.variable_units);
+        assert_eq!(loaded.unit_ranges, expected.unit_ranges);
+
+        // The file has a different number of units than when the index was built.
+        assert!(load_in(&directory, &object, path, 3).is_none());
+
+        // The index of another build of the file.
+        let (file, _) = cache_file(&directory, &object, path).unwrap();
+        let contents = fs::read_to_string(&file).unwrap();
+        let (header, index) = contents.split_once('\n').unwrap();
+        let mut header: Value = serde_json::from_str(header).unwrap();
+        header["key"] = json!("build-id 00");
+        fs::write(&file, format!("{}\n{}", header, index)).unwrap();
+        assert!(load_in(&directory, &object, path, 2).is_none());
+
+        // Clearing removes the files of other versions and left over temporary files,
+        // but not directories.
+        fs::write(directory.join("old.json"), "{\"version\":0}\n{}").unwrap();
+        fs::write(directory.join("old.json.1234.tmp"), "{").unwrap();
+        fs::create_dir(directory.join("dir")).unwrap();
+        assert_eq!(clear_in(&directory).unwrap(), 3);
+        let left: Vec<_> = fs::read_dir(&directory).unwrap().map(|f| f.unwrap().file_name()).collect();
+        assert_eq!(left, ["dir"]);
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(clear_in(&directory).unwrap(), 0);
+    }
+}
@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+
+use object::{Object, ObjectSection, ObjectSymbol, ObjectSymbolTable, RelocationTarget, SymbolKind};
+
+use crate::demangle::{demangle_name, name_matches};
+
+/// Sections with the stubs that call imported functions through the GOT.
+const PLT_SECTIONS: [&str; 3] = [".plt", ".plt.sec", ".plt.got"];
+/// Size of a `.plt` and `.plt.sec` entry, and of a `.plt.got` entry with `endbr64`.
+const PLT_ENTRY_SIZE: usize = 16;
+/// Size of a `.plt.got` entry without `endbr64`, stubs start at multiples of this.
+const PLT_GOT_ENTRY_SIZE: usize = 8;
+const ENDBR64: [u8; 4] = [0xf3, 0x0f, 0x1e, 0xfa];
+
+/// A function symbol of the ELF symbol tables.
+#[derive(Debug, Clone)]
+pub struct Symbol {
+    pub name: String,
+    /// Address relative to the base address.
+    pub address: usize,
+    pub size: usize,
+    pub section: String,
+    pub global: bool,
+}
+
+/// Function symbols of `.symtab` and `.dynsym` and the PLT stubs, for code without DWARF.
+pub struct SymbolTable {
+    /// Sorted by address, global symbols before local ones at the same address.
+    symbols: Vec<Symbol>,
+}
+
+impl SymbolTable {
+    /// Collect the symbols of `objects`, an ELF file and possibly its separate debug file.
+    pub fn create(objects: &[&object::File]) -> SymbolTable {
+        let mut symbols: Vec<Symbol> = objects
+            .iter()
+            .flat_map(|object| object_symbols(object).into_iter().chain(plt_symbols(object)))
+            .collect();
+        // Aliases like `_IO_printf` and `printf` share an address, prefer the plain name.
+        let underscores = |symbol: &Symbol| symbol.name.len() - symbol.name.trim_start_matches('_').len();
+        symbols.sort_by(|a, b| {
+            (a.address, !a.global, underscores(a), &a.name)
+                .cmp(&(b.address, !b.global, underscores(b), &b.name))
+        });
+        // .dynsym repeats the exported symbols of .symtab.
+        symbols.dedup_by(|a, b| a.address == b.address && a.name == b.name);
+        for idx in 0..symbols.len() {
+            let next = symbols[idx..].iter().find(|s| s.address > symbols[idx].address);
+            if let Some(next) = next.map(|s| s.address) {
+                let symbol = &mut symbols[idx];
+                symbol.size = symbol.size.min(next - symbol.address);
+            }
+        }
+        SymbolTable { symbols }
+    }
+
+    /// The symbol containing `addr` and the offset of `addr` in it.
+    pub fn lookup(&self, addr: usize) -> Option<(&Symbol, usize)> {
+        let end = self.symbols.partition_point(|symbol| symbol.address <= addr);
+        let last = self.symbols[..end].last()?;
+        // Prefer the first, global, symbol at that address.
+        let first = self.symbols[..end].partition_point(|symbol| symbol.address < last.address);
+        let symbol = &self.symbols[first];
+        if addr >= symbol.address + symbol.size {
+            return None;
+        }
+        Some((symbol, addr - symbol.address))
+    }
+
+    /// The symbol `name`, which may be partially qualified, preferring global symbols
+    /// and those with exactly that name.
+    pub fn by_name(&self, name: &str) -> Option<&Symbol> {
+        let exact = |symbol: &&Symbol| symbol.name == name;
+        let matching = |symbol: &&Symbol| name_matches(&symbol.name, name);
+        let global = |symbol: &&Symbol| symbol.global;
+        self.symbols
+            .iter()
+            .find(|symbol| exact(symbol) && global(symbol))
+            .or_else(|| self.symbols.iter().find(exact))
+            .or_else(|| self.symbols.iter().find(|symbol| matching(symbol) && global(symbol)))
+            .or_else(|| self.symbols.iter().find(matching))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Symbol> {
+        self.symbols.iter()
+    }
+}
+
+/// The function definitions of `.symtab` and `.dynsym`.
+fn object_symbols(object: &object::File) -> Vec<Symbol> {
+    object
+        .symbols()
+        .chain(object.dynamic_symbols())
+        .filter(|symbol| symbol.kind() == SymbolKind::Text && symbol.is_definition())
+        .filter_map(|symbol| {
+            let section = object.section_by_index(symbol.section_index()?).ok()?;
+            let address = symbol.address() as usize;
+            // Symbols without a size, like those of hand written assembly, reach
+            // up to the end of their section or the next symbol.
+            let size = match symbol.size() {
+                0 => ((section.address() + section.size()) as usize).saturating_sub(address),
+                size => size as usize,
+            };
+            Some(Symbol {
+                name: demangle_name(symbol.name().ok()?),
+                address,
+                size,
+                section: String::from(section.name().ok()?),
+                global: symbol.is_global(),
+            })
+        })
+        .filter(|symbol| !symbol.name.is_empty())
+        .collect()
+}
+
+/// Synthesize `name@plt` symbols for the PLT stubs. Each stub jumps through the GOT
+/// slot that the dynamic linker fills in for a JUMP_SLOT or GLOB_DAT relocation.
+fn plt_symbols(object: &object::File) -> Vec<Symbol> {
+    let dynamic_symbols = match object.dynamic_symbol_table() {
+        Some(dynamic_symbols) => dynamic_symbols,
+        None => return Vec::new(),
+    };
+    let mut imports = HashMap::new();
+    for (slot, relocation) in object.dynamic_relocations().into_iter().flatten() {
+        let index = match relocation.target() {
+            RelocationTarget::Symbol(index) => index,
+            _ => continue,
+        };
+        match dynamic_symbols.symbol_by_index(index).and_then(|s| s.name()) {
+            Ok(name) if !name.is_empty() => {
+                imports.insert(slot as usize, demangle_name(name));
+            }
+            _ => {}
+        }
+    }
+
+    let mut symbols = Vec::new();
+    for section in PLT_SECTIONS.iter().filter_map(|name| object.section_by_name(name)) {
+        if let (Ok(name), Ok(data)) = (section.name(), section.data()) {
+            symbols.extend(plt_stubs(name, section.address() as usize, data, &imports));
+        }
+    }
+    symbols
+}
+
+/// The stubs in the PLT section `name` at `start` that jump through a GOT slot of
+/// `imports`, which maps the address of the slot to the imported function.
+fn plt_stubs(name: &str, start: usize, data: &[u8], imports: &HashMap<usize, String>) -> Vec<Symbol> {
+    let mut symbols = Vec::new();
+    for offset in (0..data.len()).step_by(PLT_GOT_ENTRY_SIZE) {
+        let address = start + offset;
+        let code = &data[offset..];
+        let import = rip_relative_jump(code)
+            .and_then(|(end, disp)| imports.get(&(address + end).wrapping_add_signed(disp)));
+        if let Some(import) = import {
+            let size = match name {
+                ".plt.got" if !code.starts_with(&ENDBR64) => PLT_GOT_ENTRY_SIZE,
+                _ => PLT_ENTRY_SIZE,
+            };
+            symbols.push(Symbol {
+                name: format!("{}@plt", import),
+                address,
+                size: size.min(code.len()),
+                section: String::from(name),
+                global: false,
+            });
+        }
+    }
+    symbols
+}
+
+/// If `code` starts with `jmp *disp32(%rip)`, possibly after `endbr64` and a `bnd`
+/// prefix, return the offset of the end of the jump and its displacement.
+fn rip_relative_jump(code: &[u8]) -> Option<(usize, isize)> {
+    let mut offset = 0;
+    if code.starts_with(&ENDBR64) {
+        offset += 4;
+    }
+    if code.get(offset) == Some(&0xf2) {
+        offset += 1;
+    }
+    if code.get(offset..offset + 2)? != [0xff, 0x25] {
+        return None;
+    }
+    let disp = code.get(offset + 2..offset + 6)?;
+    Some((offset + 6, i32::from_le_bytes(disp.try_into().ok()?) as isize))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `jmp *disp32(%rip)` at `address` after `prefix` that jumps through `slot`.
+    fn jump(prefix: &[u8], address: usize, slot: usize) -> Vec<u8> {
+        let end = address + prefix.len() + 6;
+        let disp = (slot as i64 - end as i64) as i32;
+        let mut code = prefix.to_vec();
+        code.extend([0xff, 0x25]);
+        code.extend(disp.to_le_bytes());
+        code
+    }
+
+    fn imports() -> HashMap<usize, String> {
+        HashMap::from([
+            (0x4018, String::from("puts")),
+            (0x4020, String::from("malloc")),
+            (0x3ff0, String::from("__cxa_finalize")),
+        ])
+    }
+
+    fn stubs(name: &str, start: usize, data: &[u8]) -> Vec<(String, usize, usize)> {
+        plt_stubs(name, start, data, &imports())
+            .into_iter()
+            .map(|s| (s.name, s.address, s.size))
+            .collect()
+    }
+
+    #[test]
+    fn rip_relative_jumps() {
+        assert_eq!(rip_relative_jump(&[0xff, 0x25, 0x10, 0, 0, 0]), Some((6, 0x10)));
+        assert_eq!(rip_relative_jump(&[0xff, 0x25, 0xfe, 0xff, 0xff, 0xff, 0x90]), Some((6, -2)));
+        // bnd jmp
+        assert_eq!(rip_relative_jump(&[0xf2, 0xff, 0x25, 1, 0, 0, 0]), Some((7, 1)));
+        // endbr64; bnd jmp
+        let code = [0xf3, 0x0f, 0x1e, 0xfa, 0xf2, 0xff, 0x25, 0, 1, 0, 0];
+        assert_eq!(rip_relative_jump(&code), Some((11, 0x100)));
+        // push *disp32(%rip), the start of PLT0
+        assert_eq!(rip_relative_jump(&[0xff, 0x35, 0, 0, 0, 0]), None);
+        // truncated displacement
+        assert_eq!(rip_relative_jump(&[0xff, 0x25, 0, 0]), None);
+        assert_eq!(rip_relative_jump(&[]), None);
+    }
+
+    #[test]
+    fn lazy_plt() {
+        // PLT0: push GOT+8; jmp *GOT+16; nopl 0x0(%rax)
+        let mut data = vec![0xff, 0x35, 0xe2, 0x2f, 0, 0, 0xff, 0x25, 0xe4, 0x2f, 0, 0, 0x0f, 0x1f, 0x40, 0];
+        for (idx, slot) in [0x4018, 0x4020].into_iter().enumerate() {
+            let address = 0x1010 + 16 * idx;
+            data.extend(jump(&[], address, slot));
+            // push $idx; jmp PLT0
+            data.extend([0x68, idx as u8, 0, 0, 0, 0xe9, 0, 0, 0, 0]);
+        }
+        assert_eq!(
+            stubs(".plt", 0x1000, &data),
+            vec![(String::from("puts@plt"), 0x1010, 16), (String::from("malloc@plt"), 0x1020, 16)]
+        );
+    }
+
+    #[test]
+    fn plt_sec_with_endbr64() {
+        let mut data = Vec::new();
+        for (idx, slot) in [0x4020, 0x4018].into_iter().enumerate() {
+            let address = 0x1100 + 16 * idx;
+            data.extend(jump(&[0xf3, 0x0f, 0x1e, 0xfa, 0xf2], address, slot));
+            data.extend([0x0f, 0x1f, 0x44, 0, 0]);
+        }
+        assert_eq!(
+            stubs(".plt.sec", 0x1100, &data),
+            vec![(String::from("malloc@plt"), 0x1100, 16), (String::from("puts@plt"), 0x1110, 16)]
+        );
+    }
+
+    #[test]
+    fn plt_got_entries() {
+        // jmp *slot; xchg %ax,%ax
+        let mut data = jump(&[], 0x1200, 0x3ff0);
+        data.extend([0x66, 0x90]);
+        // A slot without a relocation is no stub of an import.
+        data.extend(jump(&[], 0x1208, 0x3ff8));
+        data.extend([0x66, 0x90]);
+        data.extend(jump(&[], 0x1210, 0x4018));
+        data.extend([0x66, 0x90]);
+        assert_eq!(
+            stubs(".plt.got", 0x1200, &data),
+            vec![(String::from("__cxa_finalize@plt"), 0x1200, 8), (String::from("puts@plt"), 0x1210, 8)]
+        );
+
+        // With IBT the entries are 16 bytes.
+        let mut data = jump(&[0xf3, 0x0f, 0x1e, 0xfa, 0xf2], 0x1200, 0x3ff0);
+        data.extend([0x0f, 0x1f, 0x44, 0, 0]);
+        assert_eq!(stubs(".plt.got", 0x1200, &data), vec![(String::from("__cxa_finalize@plt"), 0x1200, 16)]);
+    }
+}
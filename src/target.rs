@@ -1,16 +1,49 @@
 use std::collections::HashMap;
 use std::ffi::{self, c_void};
+use std::fs;
 use std::mem::size_of;
 
 use nix::libc;
 use nix::sys::personality::Persona;
 use nix::sys::signal::Signal;
-use nix::sys::wait::{wait, WaitStatus};
+use nix::sys::wait::{waitpid, WaitStatus};
 use nix::sys::{personality, ptrace};
 use nix::unistd::{fork, ForkResult, Pid};
 
-use crate::debuginfo::{DebugInfo, Location};
-use crate::util::{add_offset, get_base_address};
+use crate::debugfile::map_file;
+use crate::debuginfo::{DebugInfo, Location, SourceLine};
+use crate::disassembler::{self, Instruction, MAX_INSTRUCTION_LENGTH};
+use crate::dprintf::{self, PrintfArg};
+use crate::gimliwrapper::{FrameBase, Function, GlobalVariable, TypeLookup, VariableLocation};
+use crate::prettyprint::ValueFormatter;
+use crate::solib::{self, SharedLibrary};
+use crate::util::{add_offset, get_base_address, memory_maps};
+
+/// General purpose registers in the order `info registers` shows them.
+pub const REGISTER_NAMES: [&str; 18] = [
+    "rax", "rbx", "rcx", "rdx", "rsi", "rdi", "rbp", "rsp", "r8", "r9", "r10", "r11", "r12",
+    "r13", "r14", "r15", "rip", "eflags",
+];
+
+/// Strings printed by dprintf's `%s` are cut off after this many bytes.
+const MAX_STRING_LENGTH: usize = 200;
+
+/// si_code of a SIGTRAP caused by an int3 instruction.
+const SI_KERNEL: i32 = 0x80;
+/// si_code of a SIGTRAP caused by a breakpoint trap.
+const TRAP_BRKPT: i32 = 1;
+
+/// The `endbr64` instruction that starts functions built with `-fcf-protection`.
+const ENDBR64: [u8; 4] = [0xf3, 0x0f, 0x1e, 0xfa];
+/// The `push rbp` instruction that starts the prologue of a function.
+const PUSH_RBP: u8 = 0x55;
+
+/// x86-64 has four debug registers for addresses, DR0 to DR3.
+const DEBUG_ADDRESS_REGISTERS: usize = 4;
+/// The debug status register, which tells which address register triggered.
+const DR6: usize = 6;
+/// The debug control register, which enables the address registers.
+const DR7: usize = 7;
 
 pub struct Breakpoint {
     pub address: usize,
@@ -18,62 +51,412 @@ pub struct Breakpoint {
     original_byte: u8,
     /// Set to true if this bp was hit on SIGTRAP.
     set_on_continue: bool,
+    /// REPL commands run when the breakpoint is hit, see `commands`.
+    pub commands: Vec<String>,
+    /// Set for `dprintf` breakpoints, which print and continue instead of stopping.
+    pub dprintf: Option<Dprintf>,
 }
 
-impl Breakpoint {
-    pub fn pprint(&self, debug_info: &DebugInfo, base_address: usize) {
-        let location = debug_info.get_location_at_addr(self.address - base_address);
-        if let Some(location) = location {
-            print!("Breakpoint {} at {}", self.idx, location);
-        } else {
-            print!("Breakpoint {} at {:#x}", self.idx, self.address);
-        }
+/// The format and argument expressions of a `dprintf` breakpoint.
+pub struct Dprintf {
+    pub format: String,
+    pub arguments: Vec<String>,
+}
+
+/// A hardware watchpoint, a debug register that traps when the program writes a variable.
+pub struct Watchpoint {
+    pub idx: u32,
+    pub expression: String,
+    /// Absolute address of the variable.
+    pub address: usize,
+    pub t: usize,
+    /// The value when the watchpoint was set or last triggered.
+    pub value: u64,
+    /// Size of the variable, 1, 2, 4 or 8 bytes.
+    length: usize,
+    /// Number of the debug register with the address, 0 to 3.
+    slot: usize,
+}
+
+/// A breakpoint on a function that is not loaded yet. It is inserted once a
+/// shared library defining the function is loaded.
+pub struct PendingBreakpoint {
+    pub idx: u32,
+    pub function: String,
+}
+
+/// A stack frame found by walking the rbp chain.
+pub struct Frame {
+    pub level: usize,
+    /// rip of this frame.
+    pub address: usize,
+    pub rbp: usize,
+    /// rsp of this frame, for outer frames the CFA of the frame it called.
+    pub rsp: usize,
+    pub location: Location,
+    /// True for the virtual frame of an inlined call, which shares the registers of
+    /// the frame further out it was inlined into.
+    pub inlined: bool,
+    /// True if `address` is a return address, in the frames further out than the
+    /// innermost real frame.
+    pub caller: bool,
+}
+
+impl Frame {
+    /// The canonical frame address, rsp before the call instruction of the caller.
+    pub fn cfa(&self) -> usize {
+        self.rbp + 16
     }
 }
 
+/// The value of a formal parameter, local variable or evaluated expression.
+pub struct VariableValue {
+    pub name: String,
+    pub t: usize,
+    /// The first bytes of the value, all of it for scalars.
+    pub value: u64,
+    /// Where the value is, `None` for registers and numbers.
+    pub address: Option<usize>,
+    /// True for a variable that has no location at the pc of its frame.
+    pub optimized_out: bool,
+}
+
 pub struct Target {
-    _executable_path: String,
+    executable_path: String,
     pid: Pid,
+    /// True if we attached to a running process instead of starting it.
+    pub attached: bool,
     pub base_address: usize,
     next_bp_num: u32,
     pub breakpoints: HashMap<usize, Breakpoint>,
+    /// Address of the breakpoint that caused the last stop, if any.
+    pub stopped_at_breakpoint: Option<usize>,
+    /// Address of the int3 that caused the last SIGTRAP, ours or not.
+    last_trap_address: Option<usize>,
+    /// Messages of the dprintf breakpoints hit since the debugger last took them.
+    pub dprintf_output: Vec<String>,
     pub debug_info: DebugInfo,
+    /// Shared objects loaded by the dynamic linker, starting with the linker itself.
+    pub libraries: Vec<SharedLibrary>,
+    /// Address of the dynamic linker's `struct r_debug`.
+    r_debug: Option<usize>,
+    /// Address and original byte of the int3 on `_dl_debug_state`.
+    library_breakpoint: Option<(usize, u8)>,
+    /// True once the dynamic linker has mapped the libraries the program starts with.
+    libraries_loaded: bool,
+    pub pending_breakpoints: Vec<PendingBreakpoint>,
+    pub watchpoints: Vec<Watchpoint>,
+    /// Number and previous value of the watchpoint that caused the last stop, if any.
+    pub stopped_at_watchpoint: Option<(u32, u64)>,
+    /// True while the child runs with PTRACE_CONT rather than single steps.
+    continuing: bool,
+    /// Where `cont` stopped while stepping over a breakpoint, returned by the next `wait`.
+    stop_after_step: Option<WaitStatus>,
+    /// Where to look for separate debug files, see `debugfile::find_debug_file`.
+    debug_file_directories: Vec<String>,
 }
 
 impl Target {
-    pub fn create(target: &str) -> Result<Target, nix::Error> {
-        let pid = Target::fork_child(target)?;
-        let debug_info = DebugInfo::create(target);
-        Ok(Target {
-            _executable_path: String::from(target),
+    pub fn create(
+        target: &str,
+        args: &[String],
+        debug_file_directories: &[String],
+    ) -> Result<Target, String> {
+        // A file that can't be debugged is not started at all.
+        let debug_info = DebugInfo::create(target, debug_file_directories)?;
+        let pid = Target::fork_child(target, args).map_err(|e| e.to_string())?;
+        // The child stops with SIGTRAP once execve is done.
+        waitpid(pid, None).map_err(|e| e.to_string())?;
+        Ok(Target::with_pid(target, pid, false, debug_info, debug_file_directories))
+    }
+
+    /// Attach to an already running process.
+    pub fn attach(pid: Pid, debug_file_directories: &[String]) -> Result<Target, String> {
+        ptrace::attach(pid).map_err(|e| e.to_string())?;
+        waitpid(pid, None).map_err(|e| e.to_string())?;
+        let target = fs::read_link(format!("/proc/{}/exe", pid))
+            .map(|p| p.to_string_lossy().to_string())
+            .map_err(|e| e.to_string())
+            .and_then(|executable| {
+                let debug_info = DebugInfo::create(&executable, debug_file_directories)?;
+                Ok(Target::with_pid(&executable, pid, true, debug_info, debug_file_directories))
+            });
+        if target.is_err() {
+            // Let the process run on as it did before.
+            let _ = ptrace::detach(pid, None);
+        }
+        target
+    }
+
+    fn with_pid(
+        target: &str,
+        pid: Pid,
+        attached: bool,
+        debug_info: DebugInfo,
+        debug_file_directories: &[String],
+    ) -> Target {
+        match &debug_info.debug_file {
+            Some(debug_file) => println!(
+                "Successfully loaded debug information for file {} from {}.",
+                target, debug_file
+            ),
+            None => println!("Successfully loaded debug information for file {}.", target),
+        }
+        let map = map_file(target);
+        let object = map.as_ref().and_then(|map| object::File::parse(&**map).ok());
+        // Executables that are not position independent are linked at their load address.
+        let link_address = object
+            .as_ref()
+            .and_then(solib::segment_range)
+            .map_or(0, |(start, _)| start & !0xfff);
+        let mut target = Target {
+            executable_path: String::from(target),
             pid,
-            base_address: get_base_address(pid).unwrap_or(0),
+            attached,
+            base_address: get_base_address(pid).unwrap_or(0).saturating_sub(link_address),
             next_bp_num: 0,
             breakpoints: HashMap::new(),
+            stopped_at_breakpoint: None,
+            last_trap_address: None,
+            dprintf_output: Vec::new(),
             debug_info,
-        })
+            libraries: Vec::new(),
+            r_debug: None,
+            library_breakpoint: None,
+            libraries_loaded: false,
+            pending_breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            stopped_at_watchpoint: None,
+            continuing: false,
+            stop_after_step: None,
+            debug_file_directories: debug_file_directories.to_vec(),
+        };
+        if let Some(interpreter) = object.as_ref().and_then(solib::interpreter) {
+            if let Err(e) = target.watch_libraries(&interpreter) {
+                println!("Could not track shared libraries: {}", e);
+            }
+        }
+        target
     }
 
-    /// Get location to which rip points.
-    pub fn get_current_location(&self) -> Option<Location> {
-        self.debug_info
-            .get_location_at_addr(self.get_virtual_address())
+    /// Load the dynamic linker, break on `_dl_debug_state` to learn about
+    /// loaded and unloaded objects and read the ones loaded so far.
+    fn watch_libraries(&mut self, interpreter: &str) -> Result<(), String> {
+        let path = fs::canonicalize(interpreter).map_err(|e| format!("{}: {}", interpreter, e))?;
+        let path = path.to_string_lossy();
+        let mapped_at = memory_maps(self.pid)
+            .into_iter()
+            .filter(|(_, mapping)| *mapping == path)
+            .map(|(start, _)| start)
+            .min()
+            .ok_or(format!("{} is not mapped.", path))?;
+        let map = map_file(&path).ok_or(format!("Could not read {}.", path))?;
+        let object = object::File::parse(&*map).map_err(|e| format!("{}: {}", path, e))?;
+        let (start, _) = solib::segment_range(&object).ok_or(format!("{} has no segments.", path))?;
+        let base = mapped_at - (start & !0xfff);
+        let symbol = |name| {
+            solib::symbol_address(&object, name)
+                .map(|address| address + base)
+                .ok_or(format!("{} not found in {}.", name, path))
+        };
+        let r_debug = symbol(solib::R_DEBUG_SYMBOL)?;
+        let debug_state = symbol(solib::DEBUG_STATE_FUNCTION)?;
+        // The link map calls the linker by the name in .interp.
+        let linker = SharedLibrary::load(interpreter, base, &self.debug_file_directories)
+            .ok_or(format!("Could not load {}.", path))?;
+
+        let original_byte = self.write_byte(debug_state, 0xcc).map_err(|e| e.to_string())?;
+        self.library_breakpoint = Some((debug_state, original_byte));
+        self.r_debug = Some(r_debug);
+        self.libraries.push(linker);
+        // Libraries are already loaded if we attached.
+        self.update_libraries();
+        Ok(())
     }
 
-    /// Get rip relative to the base address.
-    pub fn get_virtual_address(&self) -> usize {
-        let regs = ptrace::getregs(self.pid).expect("Could not get registers.");
-        (regs.rip as usize) - self.base_address
+    /// Change where separate debug files are searched for and reload the debug
+    /// information of the executable and all libraries.
+    pub fn set_debug_file_directories(&mut self, directories: &[String]) -> Result<(), String> {
+        self.debug_file_directories = directories.to_vec();
+        self.debug_info = DebugInfo::create(&self.executable_path, directories)?;
+        for library in &mut self.libraries {
+            // Keep what was loaded of a library that was deleted since.
+            if let Ok(debug_info) = DebugInfo::create(&library.path, directories) {
+                library.debug_info = debug_info;
+            }
+        }
+        self.resolve_pending_breakpoints();
+        Ok(())
+    }
+
+    /// True for dynamically linked executables, where breakpoints can be pending.
+    pub fn is_dynamic(&self) -> bool {
+        self.r_debug.is_some()
+    }
+
+    /// True while the libraries the program starts with are not loaded yet, so that
+    /// a function that isn't found may still turn up in one of them.
+    pub fn libraries_pending(&self) -> bool {
+        self.is_dynamic() && !self.libraries_loaded
+    }
+
+    /// Synchronize `libraries` with the dynamic linker's link map and insert
+    /// pending breakpoints that can be resolved now.
+    fn update_libraries(&mut self) {
+        let r_debug = match self.r_debug {
+            Some(r_debug) => r_debug,
+            None => return,
+        };
+        let state = self.read_word(r_debug + solib::R_DEBUG_STATE);
+        let mut link_map = match self.read_word(r_debug + solib::R_DEBUG_MAP) {
+            Ok(link_map) if link_map != 0 && state == Ok(solib::RT_CONSISTENT) => link_map as usize,
+            _ => return,
+        };
+
+        let mut loaded = Vec::new();
+        while link_map != 0 {
+            let base = self.read_word(link_map + solib::LINK_MAP_ADDR);
+            let name = self
+                .read_word(link_map + solib::LINK_MAP_NAME)
+                .and_then(|name| self.read_string(name as usize));
+            match (base, name) {
+                // The executable itself has an empty name.
+                (Ok(base), Ok(name)) if !name.is_empty() => loaded.push((name, base as usize)),
+                (Ok(_), Ok(_)) => {}
+                _ => break,
+            }
+            link_map = match self.read_word(link_map + solib::LINK_MAP_NEXT) {
+                Ok(next) => next as usize,
+                Err(_) => break,
+            };
+        }
+
+        self.libraries_loaded = true;
+        self.libraries
+            .retain(|library| loaded.contains(&(library.path.clone(), library.base)));
+        for (path, base) in loaded {
+            let known = self
+                .libraries
+                .iter()
+                .any(|library| library.path == path && library.base == base);
+            if known {
+                continue;
+            }
+            // linux-vdso.so.1 has no file.
+            if let Some(library) = SharedLibrary::load(&path, base, &self.debug_file_directories) {
+                self.libraries.push(library);
+            }
+        }
+        self.resolve_pending_breakpoints();
+    }
+
+    fn resolve_pending_breakpoints(&mut self) {
+        let pending = std::mem::take(&mut self.pending_breakpoints);
+        for bp in pending {
+            match self.function_address(&bp.function) {
+                Some(addr) if !self.breakpoints.contains_key(&addr) => {
+                    if self.insert_breakpoint(addr, bp.idx).is_err() {
+                        self.pending_breakpoints.push(bp);
+                    }
+                }
+                // Another breakpoint is already there.
+                Some(_) => {}
+                None => self.pending_breakpoints.push(bp),
+            }
+        }
+    }
+
+    /// Absolute address of the function `name` in the executable or a loaded library.
+    pub fn function_address(&self, name: &str) -> Option<usize> {
+        let modules = std::iter::once((&self.debug_info, self.base_address))
+            .chain(self.libraries.iter().map(|library| (&library.debug_info, library.base)));
+        for (debug_info, base) in modules {
+            let low_pc = debug_info
+                .dwarf_info
+                .get_function_by_name(name)
+                .and_then(|function| function.address_range.first())
+                .map(|(low_pc, _)| *low_pc);
+            if let Some(address) = low_pc.or(debug_info.symbols.by_name(name).map(|s| s.address)) {
+                return Some(address + base);
+            }
+        }
+        None
+    }
+
+    /// Absolute addresses of the function `name`, of the other functions it names like
+    /// overloads or functions of other namespaces, and of the places they are inlined at,
+    /// in the executable and the loaded libraries.
+    pub fn function_addresses(&self, name: &str) -> Vec<usize> {
+        let mut addresses: Vec<usize> = self.function_address(name).into_iter().collect();
+        let modules = std::iter::once((&self.debug_info, self.base_address))
+            .chain(self.libraries.iter().map(|library| (&library.debug_info, library.base)));
+        for (debug_info, base) in modules {
+            let functions = debug_info.dwarf_info.functions_by_name(name);
+            let entries = functions.iter().filter_map(|function| function.address_range.first());
+            let sites = debug_info.dwarf_info.inlined_sites(name);
+            for address in entries.map(|(low_pc, _)| *low_pc).chain(sites) {
+                if !addresses.contains(&(address + base)) {
+                    addresses.push(address + base);
+                }
+            }
+        }
+        addresses
+    }
+
+    /// Debug information and load bias of the module containing the absolute address `addr`.
+    pub fn module_at(&self, addr: usize) -> (&DebugInfo, usize) {
+        match self.libraries.iter().find(|library| library.contains(addr)) {
+            Some(library) => (&library.debug_info, library.base),
+            None => (&self.debug_info, self.base_address),
+        }
+    }
+
+    /// True if the absolute address `addr` belongs to the executable rather than a library.
+    fn in_executable(&self, addr: usize) -> bool {
+        !self.libraries.iter().any(|library| library.contains(addr))
+    }
+
+    /// Source location of the absolute address `addr`, in whichever module contains it.
+    pub fn location_at(&self, addr: usize) -> Option<Location> {
+        let (debug_info, base) = self.module_at(addr);
+        debug_info.get_location_at_addr(addr.checked_sub(base)?)
+    }
+
+    /// Source locations of the absolute address `addr` with the inlined calls it is in,
+    /// see `DebugInfo::get_locations_at_addr`.
+    pub fn locations_at(&self, addr: usize) -> Vec<Location> {
+        let (debug_info, base) = self.module_at(addr);
+        match addr.checked_sub(base) {
+            Some(address) => debug_info.get_locations_at_addr(address),
+            None => Vec::new(),
+        }
+    }
+
+    /// Location of `addr` in the real function containing it, not in code inlined into it.
+    fn function_location(&self, addr: usize) -> Option<Location> {
+        self.locations_at(addr).pop()
     }
 
-    /// Retrieve the current canonical frame address (CFA).
-    /// See DWARF standarf v4, section 6.4 Call Frame Information.
-    pub fn get_cfa(&self) -> usize {
+    /// Source lines around the absolute address `addr`, see `DebugInfo::get_source_lines`.
+    pub fn source_lines_at(&self, addr: usize, range: usize) -> Vec<SourceLine> {
+        let (debug_info, base) = self.module_at(addr);
+        match addr.checked_sub(base) {
+            Some(address) => debug_info.get_source_lines(address, range),
+            None => Vec::new(),
+        }
+    }
+
+    /// Get location to which rip points.
+    pub fn get_current_location(&self) -> Option<Location> {
+        self.location_at(self.get_rip())
+    }
+
+    pub fn get_rip(&self) -> usize {
         let regs = ptrace::getregs(self.pid).expect("Could not get registers.");
-        (regs.rbp + 16) as usize
+        regs.rip as usize
     }
 
-    pub fn get_offset_from_cfa(&self, rbp: usize, offset: isize) -> usize {
+    pub fn get_offset_from_cfa(&self, rbp: usize, offset: isize) -> Option<usize> {
         // Breakpoint 1, complex_function (a=21845, b=1431654909) at segfault.c:1
         // 1       int complex_function(int a, int b) {
         // (gdb) s
@@ -106,229 +489,899 @@ impl Target {
         address
     }
 
-    pub fn read_bytes(&self, addr: usize, _amount: usize) -> Result<Vec<u8>, nix::Error> {
-        let aligned_addr = self.align_addr_to_word(addr);
-        let _byte_offset = addr - aligned_addr;
-        let word = ptrace::read(self.pid, aligned_addr as ptrace::AddressType)? as u64;
-        println!("{:#034x}", word);
-        let bytes = word.to_le_bytes();
-        for byte in bytes {
-            println!("{:#06x}", byte);
+    pub fn read_bytes(&self, addr: usize, amount: usize) -> Result<Vec<u8>, nix::Error> {
+        let mut bytes = Vec::with_capacity(amount);
+        let mut word_addr = self.align_addr_to_word(addr);
+        let mut skip = addr - word_addr;
+        while bytes.len() < amount {
+            let word = ptrace::read(self.pid, word_addr as ptrace::AddressType)? as u64;
+            let remaining = amount - bytes.len();
+            bytes.extend(word.to_le_bytes().iter().skip(skip).take(remaining));
+            word_addr += size_of::<usize>();
+            skip = 0;
+        }
+        // Hide our own int3 instructions.
+        let lifted = self.breakpoints.values().filter(|bp| !bp.set_on_continue);
+        let int3s = lifted
+            .map(|bp| (bp.address, bp.original_byte))
+            .chain(self.library_breakpoint);
+        for (address, original_byte) in int3s {
+            if address >= addr && address < addr + amount {
+                bytes[address - addr] = original_byte;
+            }
         }
+        Ok(bytes)
+    }
 
-        todo!();
+    /// Decode the instructions in `[addr, addr + length)`. Breakpoints show the original code.
+    pub fn disassemble(&self, addr: usize, length: usize) -> Result<Vec<Instruction>, nix::Error> {
+        // Read a bit more so the last instruction in the range is complete.
+        let bytes = match self.read_bytes(addr, length + MAX_INSTRUCTION_LENGTH - 1) {
+            Ok(bytes) => bytes,
+            Err(_) => self.read_bytes(addr, length)?,
+        };
+        let mut instructions = disassembler::disassemble(&bytes, addr);
+        instructions.retain(|i| i.address < addr + length);
+        Ok(instructions)
+    }
+
+    /// Start and end (exclusive) of the function containing the absolute address `addr`.
+    pub fn function_bounds(&self, addr: usize) -> Option<(usize, usize)> {
+        let (debug_info, base) = self.module_at(addr);
+        let address = addr.checked_sub(base)?;
+        if let Some(function) = debug_info.dwarf_info.get_function_by_address(address) {
+            // Functions with a .cold part have several ranges.
+            let (low_pc, high_pc) = *function
+                .address_range
+                .iter()
+                .find(|(low_pc, high_pc)| (*low_pc..=*high_pc).contains(&address))?;
+            return Some((low_pc + base, high_pc + base + 1));
+        }
+        let (symbol, _) = debug_info.symbols.lookup(address)?;
+        let start = symbol.address + base;
+        Some((start, start + symbol.size))
     }
 
-    pub fn print_current_source_line(&self, range: usize) {
-        let addr = self.get_virtual_address();
-        self.debug_info.print_code_at_addr(addr, range)
+    /// Name an absolute address relative to the function containing it, e.g. `main+0x1c`.
+    pub fn symbolize(&self, addr: usize) -> Option<String> {
+        let (start, _) = self.function_bounds(addr)?;
+        let location = self.function_location(addr)?;
+        if addr == start {
+            Some(location.function_name)
+        } else {
+            Some(format!("{}+{:#x}", location.function_name, addr - start))
+        }
     }
 
-    pub fn print_backtrace(&self) {
+    /// Walk the rbp chain and collect all frames that have debug information.
+    pub fn backtrace(&self) -> Vec<Frame> {
         let regs = ptrace::getregs(self.pid).expect("Could not get registers.");
 
-        let mut rbp = regs.rbp;
+        // At the entry of a function rbp still belongs to the caller. The frame
+        // gets the rbp it will have after the prologue so that its CFA is right.
+        let cfa = self.innermost_cfa(&regs);
+        let mut rbp = cfa - 16;
+        let mut caller_rbp = if cfa == regs.rbp + 16 { None } else { Some(regs.rbp) };
         let mut rip = regs.rip;
-        let mut i = 0;
+        let mut rsp = regs.rsp;
+        let mut frames = Vec::new();
+        let mut outermost = false;
 
-        println!("Backtrace:");
         while rbp != 0x0 {
-            if let Some(location) = self
-                .debug_info
-                .get_location_at_addr(rip as usize - self.base_address)
-            {
-                println!("{} {}", i, location);
+            let address = rip as usize;
+            let caller = !frames.is_empty();
+            let locations = self.frame_locations(address, caller);
+            if locations.is_empty() {
+                break;
+            }
+            // A virtual frame for each inlined call, then the real one.
+            let inlined = locations.len() - 1;
+            for (idx, location) in locations.into_iter().enumerate() {
+                frames.push(Frame {
+                    level: frames.len(),
+                    address,
+                    rbp: rbp as usize,
+                    rsp: rsp as usize,
+                    location,
+                    inlined: idx < inlined,
+                    caller,
+                });
+            }
+            if outermost {
+                break;
+            }
+            // The rbp chain ends in code we know nothing about, stop there.
+            let next_rbp = match caller_rbp.take() {
+                Some(next_rbp) => Ok(next_rbp as i64),
+                None => ptrace::read(self.pid, rbp as *mut c_void),
+            };
+            match (ptrace::read(self.pid, (rbp + 8) as *mut c_void), next_rbp) {
+                (Ok(next_rip), Ok(next_rbp)) => {
+                    // The stack grows down. If the caller's rbp is below ours, code
+                    // without frame pointers used it and the chain ends at the caller.
+                    outermost = next_rbp as u64 <= rbp;
+                    rsp = rbp + 16;
+                    rip = next_rip as u64;
+                    rbp = next_rbp as u64;
+                }
+                _ => break,
+            }
+        }
+        frames
+    }
 
-                // TODO: switch to get function by address
-                if let Some(function) = self
-                    .debug_info
-                    .dwarf_info
-                    .get_function_by_name(&location.function_name)
-                {
-                    for formal in &function.formal_parameters {
-                        self.print_local(
-                            rbp as usize,
-                            formal.fbreg_offset as isize,
-                            formal.t,
-                            &formal.name,
-                        );
-                    }
-                    for local in &function.local_variables {
-                        self.print_local(
-                            rbp as usize,
-                            local.fbreg_offset as isize,
-                            local.t,
-                            &local.name,
-                        );
-                    }
+    /// The source locations of the `address` of a frame. For a return address, the call
+    /// instruction before it is looked up, the call can be the last instruction of an
+    /// inlined call or a block.
+    fn frame_locations(&self, address: usize, caller: bool) -> Vec<Location> {
+        if caller {
+            let mut locations = self.locations_at(address - 1);
+            if locations.first().is_some_and(|location| location.has_source()) {
+                for location in &mut locations {
+                    location.address += 1;
                 }
-            } else {
-                break;
+                return locations;
             }
-            rip = ptrace::read(self.pid, (rbp + 8) as *mut c_void).expect("Could not read next rip")
-                as u64;
-            rbp =
-                ptrace::read(self.pid, rbp as *mut c_void).expect("Could not read next rbp") as u64;
-            i += 1;
+        }
+        self.locations_at(address)
+    }
+
+    /// The function of `frame`, or the inlined instance of one for a virtual frame.
+    pub fn frame_function(&self, frame: &Frame) -> Option<&Function> {
+        if !self.in_executable(frame.address) {
+            return None;
+        }
+        let dwarf_info = &self.debug_info.dwarf_info;
+        let pc = self.scope_pc(frame);
+        if frame.inlined {
+            return dwarf_info
+                .get_inlined_call(&frame.location.function_name, pc)
+                .map(|call| &call.function);
+        }
+        dwarf_info.get_function_by_address(pc)
+    }
+
+    /// Format the value of a variable, with the printers for Rust types if `pretty`.
+    pub fn format_value(&self, var: &VariableValue, pretty: bool) -> String {
+        if var.optimized_out {
+            return String::from("<optimized out>");
+        }
+        let read_memory = |address, length| self.read_bytes(address, length).ok();
+        ValueFormatter::new(&self.debug_info.dwarf_info, &read_memory, pretty).format(var.t, var.address, var.value)
+    }
+
+    /// Values of the formal parameters of the function of `frame`.
+    pub fn frame_arguments(&self, frame: &Frame) -> Vec<VariableValue> {
+        match self.frame_function(frame) {
+            Some(function) => function
+                .formal_parameters
+                .iter()
+                .map(|formal| self.read_local(frame, &formal.name, &formal.location, formal.t))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Values of the local variables of the function of `frame`.
+    pub fn frame_locals(&self, frame: &Frame) -> Vec<VariableValue> {
+        match self.frame_function(frame) {
+            Some(function) => function
+                .locals_at(self.scope_pc(frame))
+                .into_iter()
+                .map(|local| self.read_local(frame, &local.name, &local.location, local.t))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// The module relative pc the scopes of `frame` are looked up with. Outer frames
+    /// use their call instruction, the return address may be past the end of a block.
+    fn scope_pc(&self, frame: &Frame) -> usize {
+        let pc = frame.address - self.base_address;
+        if frame.caller {
+            pc - 1
+        } else {
+            pc
+        }
+    }
+
+    fn read_local(&self, frame: &Frame, name: &str, location: &Option<VariableLocation>, t: usize) -> VariableValue {
+        let offset = location
+            .as_ref()
+            .and_then(|location| location.frame_offset(self.scope_pc(frame)));
+        let address = offset.and_then(|offset| self.frame_base_offset(frame, offset as isize));
+        VariableValue {
+            name: String::from(name),
+            t,
+            value: address
+                .and_then(|address| self.read_value(address, self.value_length(t)).ok())
+                .unwrap_or(0),
+            address,
+            optimized_out: offset.is_none(),
+        }
+    }
+
+    /// The address `offset` bytes from the frame base of the function of `frame`, which
+    /// virtual frames share with the function they are inlined into.
+    fn frame_base_offset(&self, frame: &Frame, offset: isize) -> Option<usize> {
+        if !self.in_executable(frame.address) {
+            return None;
+        }
+        let function = self.debug_info.dwarf_info.get_function_by_address(self.scope_pc(frame))?;
+        match function.frame_base {
+            FrameBase::Cfa => self.get_offset_from_cfa(frame.rbp, offset),
+            FrameBase::Register(gimli::X86_64::RBP) => add_offset(frame.rbp, offset),
+            FrameBase::Register(gimli::X86_64::RSP) => add_offset(frame.rsp, offset),
+            FrameBase::Register(_) => None,
         }
     }
 
-    fn print_local(&self, rbp: usize, fbreg_offset: isize, t: usize, name: &str) {
-        let val_addr = self.get_offset_from_cfa(rbp as usize, fbreg_offset as isize);
-        let val_size = self
-            .debug_info
+    /// Evaluate a variable name, `$register` or integer literal in the context of `frame`.
+    pub fn evaluate(&self, expr: &str, frame: &Frame) -> Result<VariableValue, String> {
+        let expr = expr.trim();
+        let value = |t, value| VariableValue {
+            name: String::from(expr),
+            t,
+            value,
+            address: None,
+            optimized_out: false,
+        };
+
+        if let Some(register) = expr.strip_prefix('$') {
+            let regs = ptrace::getregs(self.pid).map_err(|e| e.to_string())?;
+            return match register {
+                "rip" | "pc" if frame.level > 0 => {
+                    Ok(value(0, frame.address as u64))
+                }
+                "rbp" | "fp" if frame.level > 0 => Ok(value(0, frame.rbp as u64)),
+                "rsp" | "sp" if frame.level > 0 => Ok(value(0, frame.rsp as u64)),
+                _ => register_by_name(&regs, register)
+                    .map(|v| value(0, v))
+                    .ok_or(format!("Unknown register ${}.", register)),
+            };
+        }
+
+        let literal = match expr.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+            None => expr.parse::<u64>().ok(),
+        };
+        if let Some(literal) = literal {
+            return Ok(value(0, literal));
+        }
+
+        let local = self
+            .frame_locals(frame)
+            .into_iter()
+            .chain(self.frame_arguments(frame))
+            .find(|v| v.name == expr);
+        if let Some(local) = local {
+            return Ok(local);
+        }
+
+        let global = self
+            .find_global(expr, frame)
+            .ok_or(format!("No symbol \"{}\" in current context.", expr))?;
+        let address = global.address + self.base_address;
+        let length = self.value_length(global.t);
+        self.read_value(address, length)
+            .map(|v| VariableValue { address: Some(address), ..value(global.t, v) })
+            .map_err(|e| format!("Cannot access memory at address {:#x}: {}", address, e))
+    }
+
+    /// The global variable `expr`, a name or `scope::name`, as the code of `frame` sees it.
+    fn find_global(&self, expr: &str, frame: &Frame) -> Option<&GlobalVariable> {
+        let in_executable = self.in_executable(frame.address);
+        let function = Some(frame.location.function_name.as_str()).filter(|_| in_executable);
+        let file = Some(frame.location.file.as_str()).filter(|_| in_executable);
+        self.debug_info.dwarf_info.get_global(expr, function, file)
+    }
+
+    /// Bytes read for the value of a variable of type `t`. The value of a type larger
+    /// than a word, like an array or struct, is its first word.
+    fn value_length(&self, t: usize) -> usize {
+        self.debug_info
             .dwarf_info
             .get_type_byte_size(t)
-            .expect("Could not get type byte size") as u32;
-        let val = ptrace::read(self.pid, val_addr as *mut c_void).unwrap_or(0) as u64;
-        let val_mask = (1 as u64)
-            .checked_shl(8 * val_size)
-            .map(|v| v - 1)
-            .unwrap_or(!0);
-        let val = val & val_mask;
-        println!("{} = {:#18x}", name, val);
+            .map_or(size_of::<u64>(), |size| (size as usize).clamp(1, size_of::<u64>()))
+    }
+
+    /// Read a little endian value of `length` bytes, at most 8.
+    fn read_value(&self, address: usize, length: usize) -> Result<u64, nix::Error> {
+        let bytes = self.read_bytes(address, length)?;
+        Ok(bytes
+            .iter()
+            .rev()
+            .fold(0, |value, byte| (value << 8) | *byte as u64))
+    }
+
+    /// Watch the global variable `expr` with a debug register and return the number
+    /// of the watchpoint.
+    pub fn set_watchpoint(&mut self, expr: &str, frame: &Frame) -> Result<u32, String> {
+        let expr = expr.trim();
+        let global = match self.find_global(expr, frame) {
+            Some(global) => global,
+            None => {
+                // Say why a local can't be watched, and that an unknown name is unknown.
+                self.evaluate(expr, frame)?;
+                return Err(format!(
+                    "Cannot watch \"{}\", only variables with a static address can be watched.",
+                    expr
+                ));
+            }
+        };
+        let address = global.address + self.base_address;
+        let t = global.t;
+        let length = self.value_length(t);
+        // The debug registers watch naturally aligned words of 1, 2, 4 or 8 bytes.
+        if !length.is_power_of_two() || !address.is_multiple_of(length) {
+            return Err(format!("Cannot watch {} bytes at {:#x}.", length, address));
+        }
+        let slot = (0..DEBUG_ADDRESS_REGISTERS)
+            .find(|slot| !self.watchpoints.iter().any(|w| w.slot == *slot))
+            .ok_or(String::from("All hardware watchpoints are in use."))?;
+        let value = self
+            .read_value(address, length)
+            .map_err(|e| format!("Cannot access memory at address {:#x}: {}", address, e))?;
+
+        self.set_debug_register(slot, address as u64)
+            .map_err(|e| format!("Could not set debug register: {}", e))?;
+        let idx = self.next_bp_num;
+        self.next_bp_num += 1;
+        self.watchpoints.push(Watchpoint {
+            idx,
+            expression: String::from(expr),
+            address,
+            t,
+            value,
+            length,
+            slot,
+        });
+        self.update_debug_control()
+            .map_err(|e| format!("Could not set debug register: {}", e))?;
+        Ok(idx)
+    }
+
+    /// Remove the watchpoint number `idx`. Returns the removed watchpoint, if there was one.
+    pub fn delete_watchpoint(&mut self, idx: u32) -> Result<Option<Watchpoint>, nix::Error> {
+        let position = match self.watchpoints.iter().position(|w| w.idx == idx) {
+            Some(position) => position,
+            None => return Ok(None),
+        };
+        let watchpoint = self.watchpoints.remove(position);
+        self.update_debug_control()?;
+        Ok(Some(watchpoint))
+    }
+
+    /// Enable the debug registers of the watchpoints in DR7, to trap on writes of their length.
+    fn update_debug_control(&self) -> Result<(), nix::Error> {
+        self.set_debug_register(DR7, debug_control(&self.watchpoints))
+    }
+
+    /// If a watchpoint triggered, remember its number and previous value. Returns true if
+    /// the value of the variable changed.
+    fn check_watchpoints(&mut self) -> Result<bool, nix::Error> {
+        if self.watchpoints.is_empty() {
+            return Ok(false);
+        }
+        let dr6 = self.debug_register(DR6)?;
+        // The processor never clears the status bits itself.
+        self.set_debug_register(DR6, 0)?;
+        let position = match self.watchpoints.iter().position(|w| dr6 & (1 << w.slot) != 0) {
+            Some(position) => position,
+            None => return Ok(false),
+        };
+        let watchpoint = &self.watchpoints[position];
+        let old = watchpoint.value;
+        let new = self.read_value(watchpoint.address, watchpoint.length)?;
+        let watchpoint = &mut self.watchpoints[position];
+        watchpoint.value = new;
+        self.stopped_at_watchpoint = Some((watchpoint.idx, old));
+        Ok(new != old)
+    }
+
+    /// Write debug register `number` of the child, see `u_debugreg` of `struct user`.
+    fn set_debug_register(&self, number: usize, value: u64) -> Result<(), nix::Error> {
+        let offset = std::mem::offset_of!(libc::user, u_debugreg) + number * size_of::<u64>();
+        let result = unsafe {
+            libc::ptrace(libc::PTRACE_POKEUSER, self.pid.as_raw(), offset, value)
+        };
+        nix::errno::Errno::result(result).map(drop)
+    }
+
+    fn debug_register(&self, number: usize) -> Result<u64, nix::Error> {
+        let offset = std::mem::offset_of!(libc::user, u_debugreg) + number * size_of::<u64>();
+        // PTRACE_PEEKUSER returns the value, so -1 is only an error if errno is set.
+        nix::errno::Errno::clear();
+        let result = unsafe {
+            libc::ptrace(libc::PTRACE_PEEKUSER, self.pid.as_raw(), offset, 0)
+        };
+        match nix::errno::Errno::last() {
+            nix::errno::Errno::UnknownErrno => Ok(result as u64),
+            errno => Err(errno),
+        }
+    }
+
+    /// If the child stopped at a dprintf, add its message to `dprintf_output` and return true.
+    pub fn record_dprintf(&mut self) -> bool {
+        let bp = match self
+            .stopped_at_breakpoint
+            .and_then(|addr| self.breakpoints.get(&addr))
+        {
+            Some(bp) => bp,
+            None => return false,
+        };
+        let dprintf = match &bp.dprintf {
+            Some(dprintf) => dprintf,
+            None => return false,
+        };
+        let message = match self.backtrace().first() {
+            Some(frame) => self.format_dprintf(dprintf, frame),
+            None => Err(String::from("No stack.")),
+        };
+        let message = message.unwrap_or_else(|e| format!("dprintf {}: {}\n", bp.idx, e));
+        self.dprintf_output.push(message);
+        true
+    }
+
+    /// Evaluate the arguments of a dprintf in the context of `frame` and format them.
+    fn format_dprintf(&self, dprintf: &Dprintf, frame: &Frame) -> Result<String, String> {
+        let conversions = dprintf::conversions(&dprintf.format)?;
+        let mut args = Vec::new();
+        for (conversion, expr) in conversions.iter().zip(&dprintf.arguments) {
+            let value = self.evaluate(expr, frame)?;
+            if value.optimized_out {
+                return Err(format!("{} is optimized out.", expr));
+            }
+            args.push(match conversion {
+                's' => PrintfArg::String(
+                    self.read_string(value.value as usize)
+                        .map_err(|e| format!("Cannot read string at {:#x}: {}", value.value, e))?,
+                ),
+                'f' | 'F' | 'e' | 'E' | 'g' | 'G' => PrintfArg::Float(
+                    self.debug_info
+                        .dwarf_info
+                        .float_value(value.t, value.value)
+                        .unwrap_or(value.value as f64),
+                ),
+                _ => PrintfArg::Integer(value.value),
+            });
+        }
+        dprintf::format(&dprintf.format, &args)
     }
 
-    pub fn print_registers(&self) -> Result<(), nix::Error> {
+    /// Read a NUL terminated string of at most `MAX_STRING_LENGTH` bytes.
+    pub fn read_string(&self, addr: usize) -> Result<String, nix::Error> {
+        dprintf::read_string(
+            |addr| self.read_bytes(addr, size_of::<usize>()),
+            addr,
+            MAX_STRING_LENGTH,
+        )
+    }
+
+    /// Values of all general purpose registers, see `REGISTER_NAMES`.
+    pub fn registers(&self) -> Result<Vec<(&'static str, u64)>, nix::Error> {
         let regs = ptrace::getregs(self.pid)?;
-        let rbp_pointee = ptrace::read(self.pid, regs.rbp as *mut c_void).ok();
-        let rsp_pointee = ptrace::read(self.pid, regs.rsp as *mut c_void).ok();
-
-        println!("rax\t{:#18x}", regs.rax);
-        println!("rbx\t{:#18x}", regs.rbx);
-        println!("rcx\t{:#18x}", regs.rcx);
-        println!("rdx\t{:#18x}", regs.rdx);
-
-        println!("rsi\t{:#18x}", regs.rsi);
-        println!("rdi\t{:#18x}", regs.rdi);
-        print!("rbp\t{:#18x}", regs.rbp);
-        if let Some(rbp_pointee) = rbp_pointee {
-            println!("\t-> {:#18x}", rbp_pointee);
-        } else {
-            println!("\t-> <invalid>");
+        Ok(REGISTER_NAMES
+            .iter()
+            .map(|name| (*name, register_by_name(&regs, name).unwrap()))
+            .collect())
+    }
+
+    pub fn read_word(&self, addr: usize) -> Result<u64, nix::Error> {
+        Ok(ptrace::read(self.pid, addr as ptrace::AddressType)? as u64)
+    }
+
+    /// Execute a single instruction and wait for the child to stop again.
+    pub fn step(&mut self) -> Result<WaitStatus, nix::Error> {
+        let rip = ptrace::getregs(self.pid)?.rip as usize;
+        self.lift_breakpoint(rip)?;
+        self.continuing = false;
+        ptrace::step(self.pid, None)?;
+        let status = self.wait()?;
+        if let WaitStatus::Stopped(..) = status {
+            let rip = ptrace::getregs(self.pid)?.rip as usize;
+            self.rearm_breakpoints(Some(rip))?;
         }
-        print!("rsp\t{:#18x}", regs.rsp);
-        if let Some(rsp_pointee) = rsp_pointee {
-            println!("\t-> {:#18x}", rsp_pointee);
-        } else {
-            println!("\t-> <invalid>");
+        Ok(status)
+    }
+
+    /// Step to the next source line, entering called functions that have debug information.
+    pub fn step_line(&mut self) -> Result<WaitStatus, nix::Error> {
+        self.step_line_impl(true)
+    }
+
+    /// Step to the next source line, stepping over called functions.
+    pub fn next_line(&mut self) -> Result<WaitStatus, nix::Error> {
+        self.step_line_impl(false)
+    }
+
+    fn step_line_impl(&mut self, enter_calls: bool) -> Result<WaitStatus, nix::Error> {
+        let start_locations = self.locations_at(self.get_rip());
+        let start = start_locations.first().cloned();
+        loop {
+            let prev_regs = ptrace::getregs(self.pid)?;
+            let status = self.step()?;
+            if !matches!(status, WaitStatus::Stopped(_, Signal::SIGTRAP))
+                || self.stopped_at_watchpoint.is_some()
+            {
+                return Ok(status);
+            }
+
+            let regs = ptrace::getregs(self.pid)?;
+            let return_address = self.called_from(prev_regs.rip, prev_regs.rsp, regs.rsp);
+            if let Some(return_address) = return_address {
+                let has_debug_info = self
+                    .get_current_location()
+                    .is_some_and(|location| location.has_source());
+                if !enter_calls || !has_debug_info {
+                    let status = self.run_to(return_address, prev_regs.rsp)?;
+                    if self.stopped_at_breakpoint.is_some()
+                        || self.stopped_at_watchpoint.is_some()
+                        || !matches!(status, WaitStatus::Stopped(_, Signal::SIGTRAP))
+                    {
+                        return Ok(status);
+                    }
+                }
+            }
+
+            let address = self.get_rip();
+            let (debug_info, base) = self.module_at(address);
+            // Code below the load bias, like the vdso, has no locations.
+            let relative = address.checked_sub(base);
+            let locations = relative
+                .map(|relative| debug_info.get_locations_at_addr(relative))
+                .unwrap_or_default();
+            // `next` steps over a call inlined into the function like over a real call.
+            let in_inlined_call = !enter_calls
+                && locations.len() > start_locations.len()
+                && locations.last().map(|l| &l.function_name)
+                    == start_locations.last().map(|l| &l.function_name);
+            match locations.first() {
+                Some(_) if in_inlined_call => {}
+                Some(location) if location.has_source() => {
+                    let new_line = start.as_ref().is_none_or(|start| {
+                        start.line != location.line
+                            || start.file != location.file
+                            || start.function_name != location.function_name
+                    });
+                    if new_line
+                        && location.line != 0
+                        && relative.is_some_and(|relative| debug_info.dwarf_info.is_statement_start(relative))
+                    {
+                        return Ok(status);
+                    }
+                }
+                _ => {
+                    // Returned into code without debug info, run until something happens.
+                    self.cont()?;
+                    return self.wait();
+                }
+            }
         }
+    }
 
-        println!("r8\t{:#18x}", regs.r8);
-        println!("r9\t{:#18x}", regs.r9);
-        println!("r10\t{:#18x}", regs.r10);
-        println!("r11\t{:#18x}", regs.r11);
-        println!("r12\t{:#18x}", regs.r12);
-        println!("r13\t{:#18x}", regs.r13);
-        println!("r14\t{:#18x}", regs.r14);
-        println!("r15\t{:#18x}", regs.r15);
+    /// Run until the current function returns to its caller. In an inlined call, run
+    /// until the code of the call is left.
+    pub fn step_out(&mut self) -> Result<WaitStatus, nix::Error> {
+        let rip = self.get_rip();
+        let locations = self.locations_at(rip);
+        if locations.len() > 1 && self.in_executable(rip) {
+            let inlined_call = self
+                .debug_info
+                .dwarf_info
+                .get_inlined_call(&locations[0].function_name, rip - self.base_address);
+            if let Some(inlined_call) = inlined_call {
+                let ranges = inlined_call.function.address_range.clone();
+                return self.step_out_of(&ranges);
+            }
+        }
 
-        Ok(())
+        let regs = ptrace::getregs(self.pid)?;
+        let cfa = self.innermost_cfa(&regs);
+        let return_address = ptrace::read(self.pid, (cfa - 8) as *mut c_void)?;
+        self.run_to(return_address as usize, cfa)
     }
 
-    pub fn step(&self) -> Result<(), nix::Error> {
-        ptrace::step(self.pid, None)
+    /// Single step until the pc leaves the module relative `ranges`, stepping over calls.
+    fn step_out_of(&mut self, ranges: &[(usize, usize)]) -> Result<WaitStatus, nix::Error> {
+        loop {
+            let prev_regs = ptrace::getregs(self.pid)?;
+            let mut status = self.step()?;
+            if !matches!(status, WaitStatus::Stopped(_, Signal::SIGTRAP))
+                || self.stopped_at_watchpoint.is_some()
+            {
+                return Ok(status);
+            }
+            let regs = ptrace::getregs(self.pid)?;
+            if let Some(return_address) = self.called_from(prev_regs.rip, prev_regs.rsp, regs.rsp) {
+                status = self.run_to(return_address, prev_regs.rsp)?;
+                if self.stopped_at_breakpoint.is_some()
+                    || self.stopped_at_watchpoint.is_some()
+                    || !matches!(status, WaitStatus::Stopped(_, Signal::SIGTRAP))
+                {
+                    return Ok(status);
+                }
+            }
+            let pc = self.get_rip() - self.base_address;
+            if !ranges.iter().any(|(low_pc, high_pc)| (*low_pc..=*high_pc).contains(&pc)) {
+                return Ok(status);
+            }
+        }
+    }
+
+    /// CFA of the innermost frame. Before the prologue has set up rbp it is found relative to rsp.
+    fn innermost_cfa(&self, regs: &libc::user_regs_struct) -> u64 {
+        if self.in_plt_stub(regs.rip as usize) {
+            return regs.rsp + 8;
+        }
+        let rsp_offset = self.function_bounds(regs.rip as usize).and_then(|(start, _)| {
+            let code = self.read_bytes(start, ENDBR64.len() + 1).ok()?;
+            prologue_cfa_offset(&code, regs.rip as usize - start)
+        });
+        match rsp_offset {
+            Some(offset) => regs.rsp + offset,
+            None => regs.rbp + 16,
+        }
+    }
+
+    /// True if `addr` is in a PLT stub, which jumps on without touching the stack.
+    fn in_plt_stub(&self, addr: usize) -> bool {
+        let (debug_info, base) = self.module_at(addr);
+        addr.checked_sub(base)
+            .and_then(|address| debug_info.symbols.lookup(address))
+            .is_some_and(|(symbol, _)| symbol.section.starts_with(".plt"))
+    }
+
+    /// If the last single step executed a call, return the address it will return to.
+    fn called_from(&self, prev_rip: u64, prev_rsp: u64, rsp: u64) -> Option<usize> {
+        if rsp != prev_rsp.wrapping_sub(8) {
+            return None;
+        }
+        let return_address = ptrace::read(self.pid, rsp as *mut c_void).ok()? as u64;
+        // The longest x86-64 instruction is 15 bytes long.
+        if return_address > prev_rip && return_address <= prev_rip + 15 {
+            Some(return_address as usize)
+        } else {
+            None
+        }
+    }
+
+    /// Continue until `addr` is reached with rsp at or above `sp`, using a temporary breakpoint
+    /// if needed. A return address is reached with a lower rsp in recursive calls, these go on.
+    fn run_to(&mut self, addr: usize, sp: u64) -> Result<WaitStatus, nix::Error> {
+        let temporary = if self.breakpoints.contains_key(&addr) {
+            None
+        } else {
+            Some(self.write_byte(addr, 0xcc)?)
+        };
+
+        let mut status;
+        loop {
+            self.cont()?;
+            status = self.wait()?;
+            // dprintf breakpoints on the way don't stop a next or finish.
+            if self.stopped_at_breakpoint != Some(addr) && self.record_dprintf() {
+                continue;
+            }
+            let deeper_frame = self.last_trap_address == Some(addr)
+                && ptrace::getregs(self.pid)?.rsp < sp;
+            if let (Some(original_byte), true) = (temporary, deeper_frame) {
+                // Step over the int3 and put it back.
+                self.write_byte(addr, original_byte)?;
+                let mut regs = ptrace::getregs(self.pid)?;
+                regs.rip = addr as u64;
+                ptrace::setregs(self.pid, regs)?;
+                status = self.step()?;
+                if matches!(status, WaitStatus::Stopped(_, Signal::SIGTRAP)) {
+                    // cont would step over a breakpoint on the next instruction.
+                    let rip = self.get_rip();
+                    if self.breakpoints.contains_key(&rip) {
+                        self.stopped_at_breakpoint = Some(rip);
+                    }
+                }
+                if !matches!(status, WaitStatus::Stopped(_, Signal::SIGTRAP))
+                    || self.stopped_at_breakpoint.is_some()
+                    || self.stopped_at_watchpoint.is_some()
+                {
+                    return Ok(status);
+                }
+                self.write_byte(addr, 0xcc)?;
+                continue;
+            }
+            break;
+        }
+
+        if let (Some(original_byte), WaitStatus::Stopped(..)) = (temporary, status) {
+            self.write_byte(addr, original_byte)?;
+            if self.last_trap_address == Some(addr) {
+                let mut regs = ptrace::getregs(self.pid)?;
+                regs.rip = addr as u64;
+                ptrace::setregs(self.pid, regs)?;
+            }
+        }
+        Ok(status)
     }
 
     pub fn cont(&mut self) -> Result<(), nix::Error> {
         let rip = ptrace::getregs(self.pid).expect("Could not get RIP.").rip as usize;
-        let mut need_single_step = false;
-        for (addr, _) in self
+        let need_single_step = self
             .breakpoints
-            .iter()
-            .filter(|&(_, bp)| bp.set_on_continue)
-        {
-            self.write_byte(*addr, 0xcc)?;
-            need_single_step = *addr == rip;
-        }
-        for (_, bp) in self.breakpoints.iter_mut() {
-            bp.set_on_continue = false;
-        }
+            .get(&rip)
+            .is_some_and(|bp| bp.set_on_continue);
 
         if need_single_step {
-            self.step()?;
-            wait()?;
+            ptrace::step(self.pid, None)?;
+            let status = waitpid(self.pid, None)?;
+            // The stepped instruction may have changed a watched variable or ended the program.
+            let stopped = match status {
+                WaitStatus::Stopped(_, Signal::SIGTRAP) => self.check_watchpoints()?,
+                WaitStatus::Stopped(..) => false,
+                _ => true,
+            };
+            if stopped {
+                if let WaitStatus::Stopped(..) = status {
+                    self.rearm_breakpoints(None)?;
+                }
+                self.stop_after_step = Some(status);
+                return Ok(());
+            }
+            self.stopped_at_watchpoint = None;
         }
+        self.rearm_breakpoints(None)?;
 
+        self.continuing = true;
         ptrace::cont(self.pid, None)
     }
 
     pub fn wait(&mut self) -> Result<WaitStatus, nix::Error> {
-        let wait_status = wait()?;
+        if let Some(status) = self.stop_after_step.take() {
+            self.stopped_at_breakpoint = None;
+            self.last_trap_address = None;
+            return Ok(status);
+        }
+        let mut wait_status = waitpid(self.pid, None)?;
+        self.stopped_at_breakpoint = None;
+        self.stopped_at_watchpoint = None;
+        self.last_trap_address = None;
+
         if let WaitStatus::Stopped(_, Signal::SIGTRAP) = wait_status {
-            let mut regs = ptrace::getregs(self.pid).expect("Could not get registers.");
-            regs.rip -= 1; // set rip to the breakpoint address
-
-            if let Some(breakpoint) = self.breakpoints.get_mut(&(regs.rip as usize)) {
-                // we hit our own breakpoint --> restore byte (after this if) and mark for re-setting (here).
-                breakpoint.set_on_continue = true;
-                ptrace::setregs(self.pid, regs).expect("Could not set registers.");
-            } else {
-                // not our breakpoint, this is executed after step() for example.
-            };
+            // Watchpoints trap after the write, when it left the value as it was, go on.
+            if !self.check_watchpoints()? && self.stopped_at_watchpoint.is_some() {
+                if self.continuing {
+                    ptrace::cont(self.pid, None)?;
+                    return self.wait();
+                }
+                self.stopped_at_watchpoint = None;
+            }
+            let si_code = ptrace::getsiginfo(self.pid)?.si_code;
+            if si_code == SI_KERNEL || si_code == TRAP_BRKPT {
+                let mut regs = ptrace::getregs(self.pid).expect("Could not get registers.");
+                regs.rip -= 1; // set rip to the breakpoint address
+
+                if let Some((addr, original_byte)) = self.library_breakpoint {
+                    if regs.rip as usize == addr {
+                        // The set of loaded objects changed. Step over the int3 and go on.
+                        ptrace::setregs(self.pid, regs)?;
+                        self.write_byte(addr, original_byte)?;
+                        self.update_libraries();
+                        ptrace::step(self.pid, None)?;
+                        wait_status = waitpid(self.pid, None)?;
+                        self.write_byte(addr, 0xcc)?;
+                        if self.continuing && matches!(wait_status, WaitStatus::Stopped(..)) {
+                            ptrace::cont(self.pid, None)?;
+                            return self.wait();
+                        }
+                        return Ok(wait_status);
+                    }
+                }
 
-            if let Some(breakpoint) = self.breakpoints.get(&(regs.rip as usize)) {
-                self.restore_breakpoint(breakpoint.address)?;
+                self.last_trap_address = Some(regs.rip as usize);
+
+                if self.breakpoints.contains_key(&(regs.rip as usize)) {
+                    // we hit our own breakpoint --> restore byte and mark for re-setting.
+                    ptrace::setregs(self.pid, regs).expect("Could not set registers.");
+                    self.lift_breakpoint(regs.rip as usize)?;
+                    self.stopped_at_breakpoint = Some(regs.rip as usize);
+                }
             }
         }
 
         Ok(wait_status)
     }
 
-    pub fn set_breakpoint(&mut self, addr: usize) -> Result<(), nix::Error> {
+    /// Temporarily remove the int3 of the breakpoint at `addr`, if there is one.
+    fn lift_breakpoint(&mut self, addr: usize) -> Result<(), nix::Error> {
         if let Some(bp) = self.breakpoints.get(&addr) {
-            println!("Breakpoint {} at {:#x} already exists.", bp.idx, addr);
-        } else {
-            let bp_idx = self.next_bp_num;
-            self.next_bp_num += 1;
-
-            let old_byte = self.write_byte(addr, 0xcc)?;
-
-            self.breakpoints.insert(
-                addr,
-                Breakpoint {
-                    address: addr,
-                    original_byte: old_byte,
-                    idx: bp_idx,
-                    set_on_continue: false,
-                },
-            );
-            let breakpoint = self.breakpoints.get(&addr).unwrap();
-            breakpoint.pprint(&self.debug_info, self.base_address);
-            println!("");
+            if !bp.set_on_continue {
+                self.write_byte(addr, bp.original_byte)?;
+                self.breakpoints.get_mut(&addr).unwrap().set_on_continue = true;
+            }
         }
         Ok(())
     }
 
-    pub fn delete_breakpoint(&mut self, addr: usize) -> Result<(), nix::Error> {
-        if self.restore_breakpoint(addr)? {
-            let bp = self
-                .breakpoints
-                .remove(&addr)
-                .expect("Breakpoint should exist in map?");
-            bp.pprint(&self.debug_info, self.base_address);
-            println!(" deleted.");
+    /// Re-insert all lifted breakpoints, except the one at `except`.
+    fn rearm_breakpoints(&mut self, except: Option<usize>) -> Result<(), nix::Error> {
+        let lifted: Vec<usize> = self
+            .breakpoints
+            .values()
+            .filter(|bp| bp.set_on_continue && Some(bp.address) != except)
+            .map(|bp| bp.address)
+            .collect();
+        for addr in lifted {
+            self.write_byte(addr, 0xcc)?;
+            self.breakpoints.get_mut(&addr).unwrap().set_on_continue = false;
         }
         Ok(())
     }
 
+    /// Set a breakpoint at the absolute address `addr` and return its number.
+    /// If there already is a breakpoint at `addr`, its number is returned.
+    pub fn set_breakpoint(&mut self, addr: usize) -> Result<u32, nix::Error> {
+        if let Some(bp) = self.breakpoints.get(&addr) {
+            return Ok(bp.idx);
+        }
+
+        let bp_idx = self.next_bp_num;
+        self.insert_breakpoint(addr, bp_idx)?;
+        self.next_bp_num += 1;
+        Ok(bp_idx)
+    }
+
+    /// Add the absolute address `addr` to breakpoint `idx`, e.g. another place the function
+    /// of the breakpoint is inlined at. Nothing changes if there is a breakpoint at `addr`.
+    pub fn add_breakpoint_location(&mut self, addr: usize, idx: u32) -> Result<(), nix::Error> {
+        if self.breakpoints.contains_key(&addr) {
+            return Ok(());
+        }
+        self.insert_breakpoint(addr, idx)
+    }
+
+    /// Remember a breakpoint on the function `name`, which is not loaded yet, and return its number.
+    pub fn set_pending_breakpoint(&mut self, function: &str) -> u32 {
+        let bp_idx = self.next_bp_num;
+        self.next_bp_num += 1;
+        self.pending_breakpoints.push(PendingBreakpoint {
+            idx: bp_idx,
+            function: String::from(function),
+        });
+        bp_idx
+    }
+
+    fn insert_breakpoint(&mut self, addr: usize, idx: u32) -> Result<(), nix::Error> {
+        let old_byte = self.write_byte(addr, 0xcc)?;
+        self.breakpoints.insert(
+            addr,
+            Breakpoint {
+                address: addr,
+                original_byte: old_byte,
+                idx,
+                set_on_continue: false,
+                commands: Vec::new(),
+                dprintf: None,
+            },
+        );
+        Ok(())
+    }
+
+    /// Remove the breakpoint at `addr`. Returns the removed breakpoint, if there was one.
+    pub fn delete_breakpoint(&mut self, addr: usize) -> Result<Option<Breakpoint>, nix::Error> {
+        if self.restore_breakpoint(addr)? {
+            Ok(self.breakpoints.remove(&addr))
+        } else {
+            Ok(None)
+        }
+    }
+
     fn restore_breakpoint(&mut self, addr: usize) -> Result<bool, nix::Error> {
         if let Some(bp) = self.breakpoints.get(&addr) {
             self.write_byte(addr, bp.original_byte).ok();
             Ok(true)
         } else {
-            println!("No restore info at address {:#x} found.", addr);
             Ok(false)
         }
     }
 
-    pub fn list_breakpoints(&self) {
-        for (_, breakpoint) in &self.breakpoints {
-            breakpoint.pprint(&self.debug_info, self.base_address);
-            println!("");
-        }
-    }
-
     fn align_addr_to_word(&self, addr: usize) -> usize {
         addr & (-(size_of::<usize>() as isize) as usize)
     }
@@ -337,9 +1390,9 @@ impl Target {
         let aligned_addr = self.align_addr_to_word(addr);
         let byte_offset = addr - aligned_addr;
         let word = ptrace::read(self.pid, aligned_addr as ptrace::AddressType)? as u64;
-        let orig_byte = (word >> 8 * byte_offset) & 0xff;
-        let masked_word = word & !(0xff << 8 * byte_offset);
-        let updated_word = masked_word | ((byte as u64) << 8 * byte_offset);
+        let orig_byte = (word >> (8 * byte_offset)) & 0xff;
+        let masked_word = word & !(0xff << (8 * byte_offset));
+        let updated_word = masked_word | ((byte as u64) << (8 * byte_offset));
         unsafe {
             ptrace::write(
                 self.pid,
@@ -354,10 +1407,25 @@ impl Target {
         ptrace::kill(self.pid)
     }
 
-    fn fork_child(target: &str) -> Result<Pid, nix::Error> {
+    /// Remove all breakpoints and let an attached process run on its own.
+    pub fn detach(&mut self) -> Result<(), nix::Error> {
+        let addresses: Vec<usize> = self.breakpoints.keys().copied().collect();
+        for addr in addresses {
+            self.restore_breakpoint(addr)?;
+        }
+        self.breakpoints.clear();
+        self.watchpoints.clear();
+        self.update_debug_control()?;
+        if let Some((addr, original_byte)) = self.library_breakpoint.take() {
+            self.write_byte(addr, original_byte)?;
+        }
+        ptrace::detach(self.pid, None)
+    }
+
+    fn fork_child(target: &str, args: &[String]) -> Result<Pid, nix::Error> {
         match unsafe { fork() }? {
             ForkResult::Child => {
-                bootstrap_target_process(target);
+                bootstrap_target_process(target, args);
                 Ok(Pid::from_raw(0)) // not used by anyone
             }
             ForkResult::Parent { child } => Ok(child),
@@ -365,8 +1433,65 @@ impl Target {
     }
 }
 
+/// How far above rsp the CFA is at `offset` bytes into a function that starts with `code`,
+/// while its prologue has not set up rbp yet. `None` once it has.
+fn prologue_cfa_offset(code: &[u8], offset: usize) -> Option<u64> {
+    // With -fcf-protection the function starts with endbr64 and push rbp comes after it.
+    let push_rbp = if code.starts_with(&ENDBR64) { ENDBR64.len() } else { 0 };
+    if offset <= push_rbp {
+        Some(8)
+    } else if offset == push_rbp + 1 && code.get(push_rbp) == Some(&PUSH_RBP) {
+        Some(16)
+    } else {
+        None
+    }
+}
+
+fn register_by_name(regs: &libc::user_regs_struct, name: &str) -> Option<u64> {
+    let value = match name {
+        "rax" => regs.rax,
+        "rbx" => regs.rbx,
+        "rcx" => regs.rcx,
+        "rdx" => regs.rdx,
+        "rsi" => regs.rsi,
+        "rdi" => regs.rdi,
+        "rbp" | "fp" => regs.rbp,
+        "rsp" | "sp" => regs.rsp,
+        "r8" => regs.r8,
+        "r9" => regs.r9,
+        "r10" => regs.r10,
+        "r11" => regs.r11,
+        "r12" => regs.r12,
+        "r13" => regs.r13,
+        "r14" => regs.r14,
+        "r15" => regs.r15,
+        "rip" | "pc" => regs.rip,
+        "eflags" => regs.eflags,
+        _ => return None,
+    };
+    Some(value)
+}
+
+/// The value of DR7 that enables the debug registers of `watchpoints`, each trapping on
+/// writes of the length of its variable. See Intel SDM Vol. 3B, 18.2.4.
+fn debug_control(watchpoints: &[Watchpoint]) -> u64 {
+    let mut dr7 = 0;
+    for watchpoint in watchpoints {
+        let length_bits = match watchpoint.length {
+            1 => 0b00,
+            2 => 0b01,
+            8 => 0b10,
+            _ => 0b11,
+        };
+        let write_only = 0b01;
+        dr7 |= 1 << (2 * watchpoint.slot);
+        dr7 |= ((length_bits << 2) | write_only) << (16 + 4 * watchpoint.slot);
+    }
+    dr7
+}
+
 /// Do ptrace(TRACEME) then execve
-fn bootstrap_target_process(target: &str) {
+fn bootstrap_target_process(target: &str, args: &[String]) {
     ptrace::traceme().expect("traceme failed");
 
     let pers = personality::get().unwrap();
@@ -379,13 +1504,18 @@ fn bootstrap_target_process(target: &str) {
         }
     }
 
-    let c_target_hold = ffi::CString::new(target.clone()).unwrap();
+    let c_target_hold = ffi::CString::new(target).unwrap();
     let c_target = c_target_hold.as_ptr();
+    let c_args_hold: Vec<ffi::CString> = args
+        .iter()
+        .map(|arg| ffi::CString::new(arg.as_str()).unwrap())
+        .collect();
 
     let mut argv: Vec<*const i8> = Vec::new();
     let mut env: Vec<*const i8> = Vec::new();
 
     argv.push(c_target);
+    argv.extend(c_args_hold.iter().map(|arg| arg.as_ptr()));
     argv.push(std::ptr::null());
 
     env.push(std::ptr::null());
@@ -393,5 +1523,81 @@ fn bootstrap_target_process(target: &str) {
     unsafe {
         let ret = libc::execve(c_target, argv.as_ptr(), env.as_ptr());
         println!("Programm returned {}", ret);
+        libc::_exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn watchpoint(slot: usize, length: usize) -> Watchpoint {
+        Watchpoint {
+            idx: slot as u32,
+            expression: String::from("x"),
+            address: 0x4000,
+            t: 0,
+            value: 0,
+            length,
+            slot,
+        }
+    }
+
+    #[test]
+    fn debug_control_without_watchpoints() {
+        assert_eq!(debug_control(&[]), 0);
+    }
+
+    #[test]
+    fn debug_control_enables_slot() {
+        // L0 and RW0 = 01 (write), LEN0 = 11 (4 bytes).
+        assert_eq!(debug_control(&[watchpoint(0, 4)]), 0b1101 << 16 | 0b1);
+        // L3 and RW3/LEN3 in bits 28 to 31.
+        assert_eq!(debug_control(&[watchpoint(3, 4)]), 0b1101 << 28 | 0b1 << 6);
+    }
+
+    #[test]
+    fn debug_control_lengths() {
+        let len_rw = |length| debug_control(&[watchpoint(1, length)]) >> 20 & 0xf;
+        assert_eq!(len_rw(1), 0b0001);
+        assert_eq!(len_rw(2), 0b0101);
+        assert_eq!(len_rw(4), 0b1101);
+        assert_eq!(len_rw(8), 0b1001);
+    }
+
+    #[test]
+    fn debug_control_all_slots() {
+        let watchpoints = [watchpoint(0, 1), watchpoint(1, 2), watchpoint(2, 8), watchpoint(3, 4)];
+        let dr7 = debug_control(&watchpoints);
+        // Only local enable bits, never the global ones.
+        assert_eq!(dr7 & 0xff, 0b0101_0101);
+        assert_eq!(dr7 >> 16, 0b1101_1001_0101_0001);
+    }
+
+    #[test]
+    fn prologue_cfa() {
+        // push rbp; mov rbp,rsp
+        let code = [0x55, 0x48, 0x89, 0xe5];
+        assert_eq!(prologue_cfa_offset(&code, 0), Some(8));
+        assert_eq!(prologue_cfa_offset(&code, 1), Some(16));
+        assert_eq!(prologue_cfa_offset(&code, 4), None);
+    }
+
+    #[test]
+    fn prologue_cfa_after_endbr64() {
+        // endbr64; push rbp; mov rbp,rsp
+        let code = [0xf3, 0x0f, 0x1e, 0xfa, 0x55, 0x48, 0x89, 0xe5];
+        assert_eq!(prologue_cfa_offset(&code, 0), Some(8));
+        assert_eq!(prologue_cfa_offset(&code, 4), Some(8));
+        assert_eq!(prologue_cfa_offset(&code, 5), Some(16));
+        assert_eq!(prologue_cfa_offset(&code, 8), None);
+    }
+
+    #[test]
+    fn prologue_cfa_without_push_rbp() {
+        // sub rsp,0x18
+        let code = [0x48, 0x83, 0xec, 0x18];
+        assert_eq!(prologue_cfa_offset(&code, 0), Some(8));
+        assert_eq!(prologue_cfa_offset(&code, 4), None);
     }
 }
@@ -0,0 +1,461 @@
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, stdout, BufWriter, ErrorKind, Read, Write};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::Duration;
+
+use crossterm::event::{self, Event, KeyCode, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{
+    disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use tui::backend::{Backend, CrosstermBackend};
+use tui::layout::{Constraint, Direction, Layout, Rect};
+use tui::style::{Color, Modifier, Style};
+use tui::text::{Span, Spans};
+use tui::widgets::{Block, Borders, Paragraph};
+use tui::{Frame, Terminal};
+
+use crate::debugger::Debugger;
+use crate::disassembler::MAX_INSTRUCTION_LENGTH;
+use crate::gimliwrapper::TypeLookup;
+use crate::target::Target;
+use crate::util::{capture_stdout, restore_stdout};
+
+/// Number of lines kept in the output pane.
+const LOG_LINES: usize = 500;
+
+const KEY_HELP: &str = " F5 continue | F9 breakpoint | F10 next | F11 step | ↑↓ move | Ctrl-C quit ";
+
+struct TuiState {
+    /// Source file shown in the source pane and its lines.
+    file: Option<String>,
+    lines: Vec<String>,
+    /// Line the cursor is on, 1-based.
+    cursor: usize,
+    /// First line shown in the source pane, 0-based.
+    scroll: usize,
+    input: String,
+    log: Vec<String>,
+    registers: Vec<(&'static str, u64)>,
+    /// Registers that changed with the last command.
+    changed_registers: HashSet<&'static str>,
+    /// Backtrace and variables of the selected frame, formatted once per command
+    /// instead of on every redraw.
+    stack: Vec<Spans<'static>>,
+}
+
+/// Run the full screen UI until the user quits.
+pub fn run(debugger: &mut Debugger) -> io::Result<()> {
+    // Everything printed by the debugger and the child ends up in the output pane.
+    // The child is started afterwards so it inherits the captured stdout as well.
+    let (output, captured) = capture_stdout()?;
+    let captured = spawn_reader(captured);
+    debugger.start();
+    if !debugger.run_startup_scripts() {
+        return restore_stdout(&output).map_err(io::Error::from);
+    }
+
+    enable_raw_mode()?;
+    let mut backend_output = BufWriter::new(output.try_clone()?);
+    execute!(backend_output, EnterAlternateScreen)?;
+    let mut terminal = Terminal::new(CrosstermBackend::new(backend_output))?;
+
+    let mut state = TuiState::new();
+    state.refresh(debugger);
+    let result = state.event_loop(debugger, &mut terminal, &captured);
+
+    disable_raw_mode()?;
+    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    terminal.show_cursor()?;
+    restore_stdout(&output)?;
+    result
+}
+
+/// Read the captured stdout on a separate thread, so that neither the debugger
+/// nor the child block on a full pipe while the UI is busy.
+fn spawn_reader(mut captured: File) -> Receiver<Vec<u8>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buffer = [0; 4096];
+        loop {
+            let n = match captured.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(_) => break,
+            };
+            if sender.send(buffer[..n].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+impl TuiState {
+    fn new() -> TuiState {
+        TuiState {
+            file: None,
+            lines: Vec::new(),
+            cursor: 1,
+            scroll: 0,
+            input: String::new(),
+            log: Vec::new(),
+            registers: Vec::new(),
+            changed_registers: HashSet::new(),
+            stack: Vec::new(),
+        }
+    }
+
+    fn event_loop<B: Backend>(
+        &mut self,
+        debugger: &mut Debugger,
+        terminal: &mut Terminal<B>,
+        captured: &Receiver<Vec<u8>>,
+    ) -> io::Result<()> {
+        loop {
+            stdout().flush()?;
+            self.drain(captured);
+            terminal.draw(|f| self.draw(f, debugger))?;
+
+            if !event::poll(Duration::from_millis(100))? {
+                continue;
+            }
+            if let Event::Key(key) = event::read()? {
+                match (key.code, key.modifiers) {
+                    (KeyCode::Char('c'), KeyModifiers::CONTROL)
+                    | (KeyCode::Char('d'), KeyModifiers::CONTROL) => return Ok(()),
+                    (KeyCode::F(5), _) => {
+                        self.run_command(debugger, "cont");
+                    }
+                    (KeyCode::F(9), _) => self.toggle_breakpoint(debugger),
+                    (KeyCode::F(10), _) => {
+                        self.run_command(debugger, "n");
+                    }
+                    (KeyCode::F(11), _) => {
+                        self.run_command(debugger, "step");
+                    }
+                    (KeyCode::Up, _) => self.move_cursor(-1),
+                    (KeyCode::Down, _) => self.move_cursor(1),
+                    (KeyCode::PageUp, _) => self.move_cursor(-20),
+                    (KeyCode::PageDown, _) => self.move_cursor(20),
+                    (KeyCode::Enter, _) => {
+                        let input = std::mem::take(&mut self.input);
+                        if !self.run_command(debugger, &input) {
+                            return Ok(());
+                        }
+                    }
+                    (KeyCode::Backspace, _) => {
+                        self.input.pop();
+                    }
+                    (KeyCode::Char(c), _) => self.input.push(c),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    /// Execute a command, its output shows up in the log. Returns false if the user wants to exit.
+    fn run_command(&mut self, debugger: &mut Debugger, input: &str) -> bool {
+        self.log_text(&format!("> {}", input));
+        match debugger.run_command(input) {
+            Ok(false) => return false,
+            Ok(true) => {}
+            Err(message) => self.log_text(&message),
+        }
+        self.refresh(debugger);
+        true
+    }
+
+    /// Set or delete the breakpoint on the cursor line.
+    fn toggle_breakpoint(&mut self, debugger: &mut Debugger) {
+        let address = match (debugger.target_process(), &self.file) {
+            (Some(target), Some(file)) => target
+                .debug_info
+                .dwarf_info
+                .get_address_for_line(file, self.cursor as u64)
+                .map(|(_, addr)| addr + target.base_address)
+                .map(|addr| (addr, target.breakpoints.contains_key(&addr))),
+            _ => None,
+        };
+        match address {
+            Some((addr, true)) => {
+                self.run_command(debugger, &format!("rb {:#x}", addr));
+            }
+            Some((addr, false)) => {
+                self.run_command(debugger, &format!("b {:#x}", addr));
+            }
+            None => self.log_text(&format!("No code at line {}.", self.cursor)),
+        }
+    }
+
+    fn move_cursor(&mut self, delta: isize) {
+        let last = self.lines.len().max(1);
+        self.cursor = self.cursor.saturating_add_signed(delta).clamp(1, last);
+    }
+
+    /// Follow the current location and remember which registers changed.
+    fn refresh(&mut self, debugger: &Debugger) {
+        self.stack = stack_lines(debugger.target_process(), debugger.selected_frame(), debugger.pretty_printers());
+        let target = match debugger.target_process() {
+            Some(target) => target,
+            None => return,
+        };
+
+        let registers = target.registers().unwrap_or_default();
+        self.changed_registers = registers
+            .iter()
+            .zip(self.registers.iter())
+            .filter(|(new, old)| new.1 != old.1)
+            .map(|(new, _)| new.0)
+            .collect();
+        self.registers = registers;
+
+        if let Some(location) = target.get_current_location() {
+            if self.file.as_ref() != Some(&location.file) {
+                self.lines = fs::read_to_string(&location.file)
+                    .map(|s| s.lines().map(|l| l.replace('\t', "    ")).collect())
+                    .unwrap_or_default();
+                self.file = Some(location.file);
+            }
+            self.cursor = location.line as usize;
+        }
+    }
+
+    /// Move everything written to the captured stdout into the log.
+    fn drain(&mut self, captured: &Receiver<Vec<u8>>) {
+        let text: Vec<u8> = captured.try_iter().flatten().collect();
+        if !text.is_empty() {
+            self.log_text(&String::from_utf8_lossy(&text));
+        }
+    }
+
+    fn log_text(&mut self, text: &str) {
+        self.log
+            .extend(text.lines().map(|l| l.replace('\t', "    ")));
+        if self.log.len() > LOG_LINES {
+            self.log.drain(..self.log.len() - LOG_LINES);
+        }
+    }
+
+    fn draw<B: Backend>(&mut self, f: &mut Frame<B>, debugger: &Debugger) {
+        let rows = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([
+                Constraint::Min(10),
+                Constraint::Length(8),
+                Constraint::Length(3),
+            ])
+            .split(f.size());
+        let columns = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([Constraint::Percentage(60), Constraint::Percentage(40)])
+            .split(rows[0]);
+        let left = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Min(5), Constraint::Length(10)])
+            .split(columns[0]);
+        let right = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints([Constraint::Length(20), Constraint::Min(5)])
+            .split(columns[1]);
+
+        let target = debugger.target_process();
+        self.draw_source(f, left[0], target);
+        draw_disassembly(f, left[1], target);
+        self.draw_registers(f, right[0], target.is_some());
+        self.draw_stack(f, right[1]);
+        self.draw_log(f, rows[1]);
+
+        let command = Paragraph::new(format!("> {}", self.input))
+            .block(Block::default().borders(Borders::ALL).title(KEY_HELP));
+        f.render_widget(command, rows[2]);
+        f.set_cursor(rows[2].x + 3 + self.input.len() as u16, rows[2].y + 1);
+    }
+
+    fn draw_source<B: Backend>(&mut self, f: &mut Frame<B>, area: Rect, target: Option<&Target>) {
+        let height = area.height.saturating_sub(2) as usize;
+        if self.cursor <= self.scroll {
+            self.scroll = self.cursor.saturating_sub(1);
+        } else if self.cursor > self.scroll + height {
+            self.scroll = self.cursor - height;
+        }
+
+        let mut current_line = None;
+        let mut breakpoint_lines = HashSet::new();
+        if let (Some(target), Some(file)) = (target, &self.file) {
+            current_line = target
+                .get_current_location()
+                .filter(|l| &l.file == file)
+                .map(|l| l.line as usize);
+            breakpoint_lines = target
+                .breakpoints
+                .values()
+                .filter_map(|bp| target.location_at(bp.address))
+                .filter(|l| &l.file == file)
+                .map(|l| l.line as usize)
+                .collect();
+        }
+
+        let lines: Vec<Spans> = self
+            .lines
+            .iter()
+            .enumerate()
+            .skip(self.scroll)
+            .take(height)
+            .map(|(idx, text)| {
+                let line = idx + 1;
+                let marker = if breakpoint_lines.contains(&line) {
+                    Span::styled("●", Style::default().fg(Color::Red))
+                } else {
+                    Span::raw(" ")
+                };
+                let arrow = if current_line == Some(line) {
+                    Span::styled("⇒", Style::default().fg(Color::Green))
+                } else {
+                    Span::raw(" ")
+                };
+                let mut style = Style::default();
+                if current_line == Some(line) {
+                    style = style.add_modifier(Modifier::BOLD);
+                }
+                if self.cursor == line {
+                    style = style.add_modifier(Modifier::REVERSED);
+                }
+                Spans::from(vec![
+                    marker,
+                    arrow,
+                    Span::styled(format!("{:4} {}", line, text), style),
+                ])
+            })
+            .collect();
+
+        let title = self
+            .file
+            .as_ref()
+            .map(|file| format!(" {} ", file))
+            .unwrap_or(String::from(" Source "));
+        let source = Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(title));
+        f.render_widget(source, area);
+    }
+
+    fn draw_registers<B: Backend>(&self, f: &mut Frame<B>, area: Rect, running: bool) {
+        let lines: Vec<Spans> = if running {
+            self.registers
+                .iter()
+                .map(|(name, value)| {
+                    let style = if self.changed_registers.contains(name) {
+                        Style::default().fg(Color::Yellow).add_modifier(Modifier::BOLD)
+                    } else {
+                        Style::default()
+                    };
+                    Spans::from(Span::styled(format!("{:7} {:#18x}", name, value), style))
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        let registers =
+            Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(" Registers "));
+        f.render_widget(registers, area);
+    }
+
+    fn draw_stack<B: Backend>(&self, f: &mut Frame<B>, area: Rect) {
+        let stack = Paragraph::new(self.stack.clone())
+            .block(Block::default().borders(Borders::ALL).title(" Backtrace / Locals "));
+        f.render_widget(stack, area);
+    }
+
+    fn draw_log<B: Backend>(&self, f: &mut Frame<B>, area: Rect) {
+        let height = area.height.saturating_sub(2) as usize;
+        let lines: Vec<Spans> = self
+            .log
+            .iter()
+            .skip(self.log.len().saturating_sub(height))
+            .map(|l| Spans::from(l.as_str()))
+            .collect();
+        let log = Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(" Output "));
+        f.render_widget(log, area);
+    }
+}
+
+/// Instructions around rip.
+fn draw_disassembly<B: Backend>(f: &mut Frame<B>, area: Rect, target: Option<&Target>) {
+    let rows = area.height.saturating_sub(2) as usize;
+    let mut lines = Vec::new();
+    if let Some(target) = target {
+        let rip = target.get_rip();
+        let (start, end) = target
+            .function_bounds(rip)
+            .unwrap_or((rip, rip + rows * MAX_INSTRUCTION_LENGTH));
+        let instructions = target.disassemble(start, end - start).unwrap_or_default();
+        let current = instructions.iter().position(|i| i.address == rip).unwrap_or(0);
+        // Keep a few instructions before rip visible.
+        let first = current.saturating_sub(rows / 3);
+        for instruction in instructions.iter().skip(first).take(rows) {
+            let marker = if target.breakpoints.contains_key(&instruction.address) {
+                Span::styled("●", Style::default().fg(Color::Red))
+            } else {
+                Span::raw(" ")
+            };
+            let (arrow, style) = if instruction.address == rip {
+                ("⇒", Style::default().add_modifier(Modifier::BOLD))
+            } else {
+                (" ", Style::default())
+            };
+            let mut text = format!("{} {:#x}  {}", arrow, instruction.address, instruction.text);
+            if let Some(symbol) = instruction.branch_target.and_then(|a| target.symbolize(a)) {
+                text = format!("{} <{}>", text, symbol);
+            }
+            lines.push(Spans::from(vec![marker, Span::styled(text, style)]));
+        }
+    }
+    let disassembly =
+        Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(" Disassembly "));
+    f.render_widget(disassembly, area);
+}
+
+/// Backtrace and the variables of the selected frame.
+fn stack_lines(target: Option<&Target>, selected: usize, pretty: bool) -> Vec<Spans<'static>> {
+    let mut lines = Vec::new();
+    match target {
+        Some(target) => {
+            let frames = target.backtrace();
+            for frame in &frames {
+                let file_name = frame.location.file.split('/').next_back().unwrap_or("");
+                let text = format!(
+                    "#{} {}() {}:{}",
+                    frame.level, frame.location.function_name, file_name, frame.location.line
+                );
+                let style = if frame.level == selected {
+                    Style::default().add_modifier(Modifier::BOLD)
+                } else {
+                    Style::default()
+                };
+                lines.push(Spans::from(Span::styled(text, style)));
+            }
+            if let Some(frame) = frames.get(selected) {
+                lines.push(Spans::from(""));
+                let dwarf_info = &target.debug_info.dwarf_info;
+                for var in target
+                    .frame_arguments(frame)
+                    .iter()
+                    .chain(target.frame_locals(frame).iter())
+                {
+                    lines.push(Spans::from(vec![
+                        Span::styled(var.name.clone(), Style::default().fg(Color::Cyan)),
+                        Span::raw(format!(
+                            " = {} ({})",
+                            target.format_value(var, pretty),
+                            dwarf_info.type_name(var.t)
+                        )),
+                    ]));
+                }
+            }
+        }
+        None => lines.push(Spans::from("The program is not being run.")),
+    }
+    lines
+}
@@ -1,11 +1,14 @@
+use std::io::{self, IsTerminal, Write};
+use std::time::{Duration, Instant};
 use std::{fs::File, io::BufRead};
 
-use nix::unistd::Pid;
+use nix::unistd::{close, dup, dup2, pipe, Pid};
 use std::io::BufReader;
+use std::os::unix::io::{AsRawFd, FromRawFd};
 
 pub fn get_base_address(pid: Pid) -> Result<usize, ()> {
     let maps = format!("/proc/{}/maps", pid);
-    let file = File::open(&maps).expect(format!("Could not open {}", &maps).as_str());
+    let file = File::open(&maps).unwrap_or_else(|_| panic!("Could not open {}", &maps));
     let reader = BufReader::new(file);
 
     let line = reader.lines().next().unwrap().unwrap();
@@ -14,10 +17,109 @@ pub fn get_base_address(pid: Pid) -> Result<usize, ()> {
     Ok(usize::from_str_radix(parts[0], 16).unwrap())
 }
 
-pub fn add_offset(address: usize, offset: isize) -> usize {
-    if offset < 0 {
-        address.checked_sub(offset.wrapping_abs() as usize).expect("Error during add_offset")
-    } else {
-        address.checked_add(offset as usize).expect("Error during add_offset")
+/// Start address and path of every file mapping in `/proc/<pid>/maps`.
+pub fn memory_maps(pid: Pid) -> Vec<(usize, String)> {
+    let maps = match File::open(format!("/proc/{}/maps", pid)) {
+        Ok(maps) => maps,
+        Err(_) => return Vec::new(),
+    };
+    BufReader::new(maps)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| {
+            let start = line.split('-').next()?;
+            // address perms offset dev inode path
+            let path = line.split_whitespace().nth(5)?;
+            Some((usize::from_str_radix(start, 16).ok()?, String::from(path)))
+        })
+        .collect()
+}
+
+pub fn add_offset(address: usize, offset: isize) -> Option<usize> {
+    address.checked_add_signed(offset)
+}
+
+/// Take over stdout for a machine readable protocol. Returns the original stdout,
+/// everything printed afterwards with `println!` ends up on stderr.
+pub fn take_stdout() -> Result<File, nix::Error> {
+    let output = dup(1)?;
+    dup2(2, 1)?;
+    Ok(unsafe { File::from_raw_fd(output) })
+}
+
+/// Redirect stdout into a pipe, so that output can be shown inside the TUI.
+/// Returns the original stdout and the read end of the pipe.
+pub fn capture_stdout() -> Result<(File, File), nix::Error> {
+    let (read_end, write_end) = pipe()?;
+    let output = dup(1)?;
+    dup2(write_end, 1)?;
+    close(write_end)?;
+    Ok(unsafe { (File::from_raw_fd(output), File::from_raw_fd(read_end)) })
+}
+
+/// Undo `capture_stdout`.
+pub fn restore_stdout(output: &File) -> Result<(), nix::Error> {
+    dup2(output.as_raw_fd(), 1)?;
+    Ok(())
+}
+
+/// Standard base64 encoding with padding (RFC 4648).
+pub fn base64_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// A progress line for slow work like reading the debug info of a large file. It only
+/// shows up on a terminal and once the work takes long enough to notice.
+pub struct Progress {
+    what: String,
+    start: Instant,
+    shown: Option<Instant>,
+}
+
+impl Progress {
+    const DELAY: Duration = Duration::from_millis(300);
+    const INTERVAL: Duration = Duration::from_millis(100);
+
+    pub fn new(what: &str) -> Progress {
+        Progress {
+            what: String::from(what),
+            start: Instant::now(),
+            shown: None,
+        }
+    }
+
+    /// Report that `done` of `total` steps are done.
+    pub fn update(&mut self, done: usize, total: usize) {
+        let now = Instant::now();
+        let due = match self.shown {
+            Some(shown) => now - shown >= Progress::INTERVAL,
+            None => now - self.start >= Progress::DELAY && io::stdout().is_terminal(),
+        };
+        if due {
+            print!("\r{} {}/{}", self.what, done, total);
+            io::stdout().flush().ok();
+            self.shown = Some(now);
+        }
+    }
+
+    /// Clear the progress line again.
+    pub fn finish(self) {
+        if self.shown.is_some() {
+            print!("\r\x1b[K");
+            io::stdout().flush().ok();
+        }
     }
 }